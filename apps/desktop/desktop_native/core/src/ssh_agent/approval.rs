@@ -0,0 +1,267 @@
+use std::{
+    collections::{HashMap, HashSet},
+    time::{Duration, Instant},
+};
+
+use super::peerinfo::models::PeerInfo;
+
+/// Controls how often the user is asked to approve signatures made with a key.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ApprovalPolicy {
+    /// Requests are passed to the app, which prompts according to its own prompt setting. Vault
+    /// keys without a policy of their own use this.
+    #[default]
+    FollowAppSetting,
+    /// Every signature request is shown to the user.
+    AlwaysAsk,
+    /// The first approved request unlocks the key until the vault is locked again.
+    AskOncePerUnlock,
+    /// The first approved request unlocks the key for the requesting client process.
+    AskOncePerProcess,
+    /// Signature requests are approved without showing a prompt.
+    NeverAsk,
+}
+
+/// Controls whether a key can be used through a forwarded agent connection, i.e. by a remote
+/// host that the user connected to with `ssh -A`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ForwardingPolicy {
+    /// Forwarded requests are handled like local ones.
+    Allowed,
+    /// Forwarded requests are rejected, and the key is not listed when forwarded listing hides
+    /// keys.
+    #[default]
+    Denied,
+    /// Every forwarded request is shown to the user, regardless of the approval policy.
+    RequireConfirmation,
+}
+
+/// Identifies a client process for the purpose of remembering approvals. The start time tells
+/// apart a later process that reuses the pid.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct ClientProcess {
+    pid: u32,
+    process_name: String,
+    start_time: Option<u64>,
+}
+
+impl From<&PeerInfo> for ClientProcess {
+    fn from(peer: &PeerInfo) -> Self {
+        Self {
+            pid: peer.pid(),
+            process_name: peer.process_name().to_string(),
+            start_time: peer.details().start_time,
+        }
+    }
+}
+
+/// Identifies a signature request for the purpose of remembering a time-boxed approval.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct RememberedRequest {
+    cipher_id: String,
+    client: ClientProcess,
+    is_forwarding: bool,
+    host_key: Vec<u8>,
+}
+
+impl RememberedRequest {
+    fn new(cipher_id: &str, peer: &PeerInfo) -> Self {
+        Self {
+            cipher_id: cipher_id.to_string(),
+            client: ClientProcess::from(peer),
+            is_forwarding: peer.is_forwarding(),
+            host_key: peer.host_key(),
+        }
+    }
+}
+
+/// Remembers approvals that were granted under the `AskOncePerUnlock` and
+/// `AskOncePerProcess` policies, as well as approvals the user asked to remember for a while.
+#[derive(Debug, Default)]
+pub(crate) struct ApprovalState {
+    unlock_session: HashSet<String>,
+    processes: HashSet<(String, ClientProcess)>,
+    remembered: HashMap<RememberedRequest, Instant>,
+}
+
+impl ApprovalState {
+    /// Returns true if a signature request for the given cipher can be approved without a prompt.
+    pub(crate) fn is_approved(
+        &self,
+        policy: ApprovalPolicy,
+        cipher_id: &str,
+        peer: &PeerInfo,
+    ) -> bool {
+        if self.is_remembered(cipher_id, peer) {
+            return true;
+        }
+
+        match policy {
+            ApprovalPolicy::FollowAppSetting | ApprovalPolicy::AlwaysAsk => false,
+            ApprovalPolicy::AskOncePerUnlock => self.unlock_session.contains(cipher_id),
+            ApprovalPolicy::AskOncePerProcess => self
+                .processes
+                .contains(&(cipher_id.to_string(), ClientProcess::from(peer))),
+            ApprovalPolicy::NeverAsk => true,
+        }
+    }
+
+    /// Records that the user approved a signature request, so that the policy can skip later prompts.
+    pub(crate) fn record_approval(
+        &mut self,
+        policy: ApprovalPolicy,
+        cipher_id: &str,
+        peer: &PeerInfo,
+    ) {
+        match policy {
+            ApprovalPolicy::AskOncePerUnlock => {
+                self.unlock_session.insert(cipher_id.to_string());
+            }
+            ApprovalPolicy::AskOncePerProcess => {
+                self.processes
+                    .insert((cipher_id.to_string(), ClientProcess::from(peer)));
+            }
+            ApprovalPolicy::FollowAppSetting
+            | ApprovalPolicy::AlwaysAsk
+            | ApprovalPolicy::NeverAsk => {}
+        }
+    }
+
+    /// Auto-approves requests for the same cipher, client process, forwarding state and
+    /// host key until `duration` has passed.
+    pub(crate) fn remember(&mut self, cipher_id: &str, peer: &PeerInfo, duration: Duration) {
+        let now = Instant::now();
+        self.remembered.retain(|_, expires_at| *expires_at > now);
+        self.remembered
+            .insert(RememberedRequest::new(cipher_id, peer), now + duration);
+    }
+
+    fn is_remembered(&self, cipher_id: &str, peer: &PeerInfo) -> bool {
+        self.remembered
+            .get(&RememberedRequest::new(cipher_id, peer))
+            .is_some_and(|expires_at| *expires_at > Instant::now())
+    }
+
+    /// Forgets all remembered approvals, e.g. when the vault is locked.
+    pub(crate) fn clear(&mut self) {
+        self.unlock_session.clear();
+        self.processes.clear();
+        self.remembered.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ssh_agent::peerinfo::models::ProcessDetails;
+
+    fn peer(pid: u32, name: &str) -> PeerInfo {
+        PeerInfo::new(1000, pid, name.to_string())
+    }
+
+    fn started_at(peer: PeerInfo, start_time: u64) -> PeerInfo {
+        peer.with_details(ProcessDetails {
+            start_time: Some(start_time),
+            ..ProcessDetails::default()
+        })
+    }
+
+    #[test]
+    fn test_always_ask_is_never_remembered() {
+        let mut state = ApprovalState::default();
+        let peer = peer(1, "ssh");
+        state.record_approval(ApprovalPolicy::AlwaysAsk, "cipher", &peer);
+
+        assert!(!state.is_approved(ApprovalPolicy::AlwaysAsk, "cipher", &peer));
+    }
+
+    #[test]
+    fn test_never_ask_is_always_approved() {
+        let state = ApprovalState::default();
+
+        assert!(state.is_approved(ApprovalPolicy::NeverAsk, "cipher", &peer(1, "ssh")));
+    }
+
+    #[test]
+    fn test_ask_once_per_unlock_applies_to_all_processes() {
+        let mut state = ApprovalState::default();
+        assert!(!state.is_approved(ApprovalPolicy::AskOncePerUnlock, "cipher", &peer(1, "ssh")));
+
+        state.record_approval(ApprovalPolicy::AskOncePerUnlock, "cipher", &peer(1, "ssh"));
+
+        assert!(state.is_approved(ApprovalPolicy::AskOncePerUnlock, "cipher", &peer(2, "git")));
+        assert!(!state.is_approved(ApprovalPolicy::AskOncePerUnlock, "other", &peer(1, "ssh")));
+    }
+
+    #[test]
+    fn test_ask_once_per_process_is_scoped_to_process() {
+        let mut state = ApprovalState::default();
+        state.record_approval(ApprovalPolicy::AskOncePerProcess, "cipher", &peer(1, "ssh"));
+
+        assert!(state.is_approved(ApprovalPolicy::AskOncePerProcess, "cipher", &peer(1, "ssh")));
+        assert!(!state.is_approved(ApprovalPolicy::AskOncePerProcess, "cipher", &peer(2, "ssh")));
+        assert!(!state.is_approved(ApprovalPolicy::AskOncePerProcess, "other", &peer(1, "ssh")));
+    }
+
+    #[test]
+    fn test_approvals_are_not_inherited_by_a_reused_pid() {
+        let mut state = ApprovalState::default();
+        let first = started_at(peer(1, "ssh"), 100);
+        let reused = started_at(peer(1, "ssh"), 200);
+        state.record_approval(ApprovalPolicy::AskOncePerProcess, "cipher", &first);
+        state.remember("remembered", &first, Duration::from_secs(60));
+
+        assert!(state.is_approved(ApprovalPolicy::AskOncePerProcess, "cipher", &first));
+        assert!(!state.is_approved(ApprovalPolicy::AskOncePerProcess, "cipher", &reused));
+        assert!(state.is_approved(ApprovalPolicy::AlwaysAsk, "remembered", &first));
+        assert!(!state.is_approved(ApprovalPolicy::AlwaysAsk, "remembered", &reused));
+    }
+
+    #[test]
+    fn test_clear_forgets_approvals() {
+        let mut state = ApprovalState::default();
+        let peer = peer(1, "ssh");
+        state.record_approval(ApprovalPolicy::AskOncePerUnlock, "a", &peer);
+        state.record_approval(ApprovalPolicy::AskOncePerProcess, "b", &peer);
+
+        state.remember("c", &peer, Duration::from_secs(60));
+
+        state.clear();
+
+        assert!(!state.is_approved(ApprovalPolicy::AskOncePerUnlock, "a", &peer));
+        assert!(!state.is_approved(ApprovalPolicy::AskOncePerProcess, "b", &peer));
+        assert!(!state.is_approved(ApprovalPolicy::AlwaysAsk, "c", &peer));
+    }
+
+    #[test]
+    fn test_remembered_request_is_approved_until_expiry() {
+        let mut state = ApprovalState::default();
+        let peer = peer(1, "ssh");
+        state.remember("cipher", &peer, Duration::from_secs(60));
+        state.remember("expired", &peer, Duration::ZERO);
+
+        assert!(state.is_approved(ApprovalPolicy::AlwaysAsk, "cipher", &peer));
+        assert!(!state.is_approved(ApprovalPolicy::AlwaysAsk, "expired", &peer));
+    }
+
+    #[test]
+    fn test_remembered_request_is_scoped_to_forwarding_and_host_key() {
+        let mut state = ApprovalState::default();
+        let peer = peer(1, "ssh");
+        peer.set_host_key(vec![1, 2, 3]);
+        state.remember("cipher", &peer, Duration::from_secs(60));
+
+        peer.set_forwarding(true);
+        assert!(!state.is_approved(ApprovalPolicy::AlwaysAsk, "cipher", &peer));
+
+        peer.set_forwarding(false);
+        peer.set_host_key(vec![4, 5, 6]);
+        assert!(!state.is_approved(ApprovalPolicy::AlwaysAsk, "cipher", &peer));
+
+        assert!(!state.is_approved(
+            ApprovalPolicy::AlwaysAsk,
+            "cipher",
+            &PeerInfo::new(1000, 2, "ssh".to_string())
+        ));
+    }
+}
@@ -0,0 +1,343 @@
+use std::{
+    collections::VecDeque,
+    fs::{File, OpenOptions},
+    io::{BufRead, BufReader, Write},
+    path::{Path, PathBuf},
+};
+
+use anyhow::anyhow;
+use base64::{engine::general_purpose::STANDARD, Engine as _};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tracing::error;
+
+use super::{peerinfo::models::PeerInfo, BitwardenSshKey};
+
+/// Number of entries kept in memory for display in the app.
+const RECENT_ENTRIES: usize = 500;
+
+/// Hash that the first entry of a log chains to.
+const GENESIS_HASH: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum AuditEventKind {
+    List,
+    Sign,
+    /// A client locked the agent with `ssh-add -x`
+    Lock,
+    /// A client asked to unlock the agent with `ssh-add -X`
+    Unlock,
+}
+
+/// How a request was decided.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum ApprovalOutcome {
+    /// The user approved the request in the app.
+    Approved,
+    /// The request was approved without a prompt, e.g. by the key's approval policy.
+    AutoApproved,
+    Denied,
+}
+
+impl ApprovalOutcome {
+    pub fn is_approved(&self) -> bool {
+        !matches!(self, ApprovalOutcome::Denied)
+    }
+}
+
+/// A single list or sign request handled by the agent.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AuditRecord {
+    /// Unix time in seconds
+    pub timestamp: u64,
+    pub kind: AuditEventKind,
+    /// SHA256 fingerprint of the key used for signing
+    pub key_fingerprint: Option<String>,
+    pub cipher_id: Option<String>,
+    pub pid: u32,
+    pub uid: u32,
+    pub process_name: String,
+    pub is_forwarding: bool,
+    /// Base64 encoded host key from the last session-bind of the connection
+    pub host_key: Option<String>,
+    /// Namespace of SSHSIG requests
+    pub namespace: Option<String>,
+    pub outcome: ApprovalOutcome,
+}
+
+impl AuditRecord {
+    pub(crate) fn new(kind: AuditEventKind, peer: &PeerInfo, outcome: ApprovalOutcome) -> Self {
+        let host_key = peer.host_key();
+        Self {
+            timestamp: super::unix_now(),
+            kind,
+            key_fingerprint: None,
+            cipher_id: None,
+            pid: peer.pid(),
+            uid: peer.uid(),
+            process_name: peer.process_name().to_string(),
+            is_forwarding: peer.is_forwarding(),
+            host_key: (!host_key.is_empty()).then(|| STANDARD.encode(host_key)),
+            namespace: None,
+            outcome,
+        }
+    }
+
+    pub(crate) fn with_key(mut self, key: &BitwardenSshKey) -> Self {
+        self.key_fingerprint = key.fingerprint();
+        self.cipher_id = (!key.is_ephemeral).then(|| key.cipher_uuid.clone());
+        self
+    }
+
+    pub(crate) fn with_namespace(mut self, namespace: Option<&str>) -> Self {
+        self.namespace = namespace.map(str::to_string);
+        self
+    }
+}
+
+/// An `AuditRecord` chained to its predecessor. Changing, removing or reordering entries in
+/// the log breaks the chain of hashes, which `verify_audit_log` detects.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AuditEntry {
+    #[serde(flatten)]
+    pub record: AuditRecord,
+    pub previous_hash: String,
+    pub hash: String,
+}
+
+impl AuditEntry {
+    fn new(record: AuditRecord, previous_hash: String) -> Result<Self, anyhow::Error> {
+        let hash = chain_hash(&previous_hash, &record)?;
+        Ok(Self {
+            record,
+            previous_hash,
+            hash,
+        })
+    }
+}
+
+fn chain_hash(previous_hash: &str, record: &AuditRecord) -> Result<String, anyhow::Error> {
+    let mut hasher = Sha256::new();
+    hasher.update(previous_hash.as_bytes());
+    hasher.update(b"\n");
+    hasher.update(serde_json::to_string(record)?.as_bytes());
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+/// Append-only log of agent activity, written as JSON lines once a file is configured.
+/// Recent entries are always kept in memory.
+#[derive(Debug)]
+pub(crate) struct AuditLog {
+    file: Option<(PathBuf, File)>,
+    last_hash: String,
+    recent: VecDeque<AuditEntry>,
+}
+
+impl Default for AuditLog {
+    fn default() -> Self {
+        Self {
+            file: None,
+            last_hash: GENESIS_HASH.to_string(),
+            recent: VecDeque::new(),
+        }
+    }
+}
+
+impl AuditLog {
+    /// Continues the log stored at `path`, creating the file if needed. A log whose chain is
+    /// broken is not continued, since new entries would make the modification look legitimate.
+    pub(crate) fn open(&mut self, path: &Path) -> Result<(), anyhow::Error> {
+        let mut last_hash = GENESIS_HASH.to_string();
+        let mut recent = VecDeque::new();
+        if path.exists() {
+            let entries = read_entries(path)?;
+            verify_chain(&entries)?;
+            for entry in entries {
+                last_hash = entry.hash.clone();
+                if recent.len() == RECENT_ENTRIES {
+                    recent.pop_front();
+                }
+                recent.push_back(entry);
+            }
+        }
+
+        let file = open_append(path)?;
+        self.file = Some((path.to_path_buf(), file));
+        self.last_hash = last_hash;
+        self.recent = recent;
+        Ok(())
+    }
+
+    pub(crate) fn record(&mut self, record: AuditRecord) {
+        let entry = match AuditEntry::new(record, self.last_hash.clone()) {
+            Ok(entry) => entry,
+            Err(e) => {
+                error!(error = %e, "Could not create audit entry");
+                return;
+            }
+        };
+
+        if let Some((ref path, ref mut file)) = self.file {
+            let written = serde_json::to_string(&entry)
+                .map_err(anyhow::Error::from)
+                .and_then(|line| Ok(writeln!(file, "{line}")?));
+            if let Err(e) = written {
+                error!(error = %e, ?path, "Could not write audit entry");
+            }
+        }
+
+        self.last_hash = entry.hash.clone();
+        if self.recent.len() == RECENT_ENTRIES {
+            self.recent.pop_front();
+        }
+        self.recent.push_back(entry);
+    }
+
+    /// Returns up to `limit` entries, newest first.
+    pub(crate) fn recent(&self, limit: usize) -> Vec<AuditEntry> {
+        self.recent.iter().rev().take(limit).cloned().collect()
+    }
+}
+
+fn open_append(path: &Path) -> Result<File, anyhow::Error> {
+    let mut options = OpenOptions::new();
+    options.create(true).append(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    options
+        .open(path)
+        .map_err(|e| anyhow!("Could not open audit log {path:?}: {e}"))
+}
+
+fn read_entries(path: &Path) -> Result<Vec<AuditEntry>, anyhow::Error> {
+    let file = File::open(path).map_err(|e| anyhow!("Could not open audit log {path:?}: {e}"))?;
+    BufReader::new(file)
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !matches!(line, Ok(line) if line.trim().is_empty()))
+        .map(|(index, line)| {
+            serde_json::from_str(&line?)
+                .map_err(|e| anyhow!("Invalid audit entry on line {}: {e}", index + 1))
+        })
+        .collect()
+}
+
+fn verify_chain(entries: &[AuditEntry]) -> Result<(), anyhow::Error> {
+    let mut previous_hash = GENESIS_HASH;
+    for (index, entry) in entries.iter().enumerate() {
+        if entry.previous_hash != previous_hash
+            || entry.hash != chain_hash(previous_hash, &entry.record)?
+        {
+            return Err(anyhow!("Audit log was modified at entry {}", index + 1));
+        }
+        previous_hash = &entry.hash;
+    }
+    Ok(())
+}
+
+/// Checks the hash chain of the log stored at `path` and returns the number of entries.
+pub fn verify_audit_log(path: &Path) -> Result<usize, anyhow::Error> {
+    let entries = read_entries(path)?;
+    verify_chain(&entries)?;
+    Ok(entries.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ssh_agent::tests::rand_file_in_temp;
+
+    fn record(outcome: ApprovalOutcome) -> AuditRecord {
+        AuditRecord::new(
+            AuditEventKind::Sign,
+            &PeerInfo::new(1000, 42, "ssh".to_string()),
+            outcome,
+        )
+        .with_namespace(Some("git"))
+    }
+
+    #[test]
+    fn test_log_is_chained_and_verifiable() {
+        let path = rand_file_in_temp();
+        let mut log = AuditLog::default();
+        log.open(&path).unwrap();
+        log.record(record(ApprovalOutcome::Approved));
+        log.record(record(ApprovalOutcome::Denied));
+
+        assert_eq!(verify_audit_log(&path).unwrap(), 2);
+
+        let recent = log.recent(10);
+        assert_eq!(recent.len(), 2);
+        assert_eq!(recent[0].record.outcome, ApprovalOutcome::Denied);
+        assert_eq!(recent[0].previous_hash, recent[1].hash);
+        assert_eq!(recent[1].previous_hash, GENESIS_HASH);
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_reopened_log_continues_chain() {
+        let path = rand_file_in_temp();
+        let mut log = AuditLog::default();
+        log.open(&path).unwrap();
+        log.record(record(ApprovalOutcome::Approved));
+
+        let mut reopened = AuditLog::default();
+        reopened.open(&path).unwrap();
+        assert_eq!(reopened.recent(10).len(), 1);
+        reopened.record(record(ApprovalOutcome::AutoApproved));
+
+        assert_eq!(verify_audit_log(&path).unwrap(), 2);
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_tampering_is_detected() {
+        let path = rand_file_in_temp();
+        let mut log = AuditLog::default();
+        log.open(&path).unwrap();
+        log.record(record(ApprovalOutcome::Denied));
+        log.record(record(ApprovalOutcome::Denied));
+
+        let contents = std::fs::read_to_string(&path).unwrap();
+        std::fs::write(&path, contents.replacen("\"denied\"", "\"approved\"", 1)).unwrap();
+        assert!(verify_audit_log(&path).is_err());
+
+        // removing the first entry breaks the chain as well
+        let second_line = contents.lines().nth(1).unwrap();
+        std::fs::write(&path, format!("{second_line}\n")).unwrap();
+        assert!(verify_audit_log(&path).is_err());
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_modified_log_is_not_continued() {
+        let path = rand_file_in_temp();
+        let mut log = AuditLog::default();
+        log.open(&path).unwrap();
+        log.record(record(ApprovalOutcome::Denied));
+
+        let contents = std::fs::read_to_string(&path).unwrap();
+        std::fs::write(&path, contents.replacen("\"denied\"", "\"approved\"", 1)).unwrap();
+        assert!(AuditLog::default().open(&path).is_err());
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_recent_entries_are_bounded() {
+        let mut log = AuditLog::default();
+        for _ in 0..RECENT_ENTRIES + 10 {
+            log.record(record(ApprovalOutcome::Approved));
+        }
+
+        assert_eq!(log.recent(usize::MAX).len(), RECENT_ENTRIES);
+        assert_eq!(log.recent(5).len(), 5);
+    }
+}
@@ -0,0 +1,119 @@
+use std::sync::atomic::Ordering;
+
+use anyhow::anyhow;
+use tracing::info;
+
+use super::{
+    peerinfo::models::PeerInfo, ApprovalOutcome, AuditEventKind, AuditRecord,
+    BitwardenDesktopAgent, SshAgentUIRequest,
+};
+
+/// Locking by clients with `ssh-add -x`. While locked, the agent lists no keys and refuses all
+/// other requests. The passphrase that clients send is not used: `ssh-add -X` asks the user to
+/// confirm the unlock in the app instead, which is denied if the prompt is dismissed.
+impl BitwardenDesktopAgent {
+    /// Handles `SSH_AGENTC_LOCK`. Private keys are dropped like `lock` does.
+    pub(crate) fn lock_from_client(&self, peer_info: &PeerInfo) -> Result<(), anyhow::Error> {
+        if self.is_client_locked.swap(true, Ordering::Relaxed) {
+            return Err(anyhow!("Agent is already locked"));
+        }
+
+        info!(process_name = %peer_info.process_name(), "Agent locked by client");
+        self.drop_private_keys();
+        self.record_audit(AuditRecord::new(
+            AuditEventKind::Lock,
+            peer_info,
+            ApprovalOutcome::AutoApproved,
+        ));
+        Ok(())
+    }
+
+    /// Handles `SSH_AGENTC_UNLOCK` by asking the user to unlock through the UI.
+    pub(crate) async fn unlock_from_client(
+        &self,
+        peer_info: &PeerInfo,
+    ) -> Result<(), anyhow::Error> {
+        if !self.is_client_locked() {
+            return Err(anyhow!("Agent is not locked"));
+        }
+
+        let request = SshAgentUIRequest {
+            request_id: self.get_request_id(),
+            cipher_id: None,
+            key_name: None,
+            process_name: peer_info.process_name().to_string(),
+            process_description: peer_info.description(),
+            is_list: false,
+            is_unlock: true,
+            namespace: None,
+            hash_algorithm: None,
+            digest_preview: None,
+            user_name: None,
+            is_arbitrary_data: false,
+            // undoing `ssh-add -x` is always confirmed
+            must_confirm: true,
+            host_names: Vec::new(),
+            is_forwarding: peer_info.is_forwarding(),
+        };
+        let accepted = self
+            .prompt_user(None, request, None, peer_info)
+            .await
+            .is_some_and(|response| response.accepted);
+
+        let outcome = if accepted {
+            ApprovalOutcome::Approved
+        } else {
+            ApprovalOutcome::Denied
+        };
+        self.record_audit(AuditRecord::new(AuditEventKind::Unlock, peer_info, outcome));
+        if !accepted {
+            return Err(anyhow!("Unlock was denied"));
+        }
+
+        info!(process_name = %peer_info.process_name(), "Agent unlocked by client");
+        self.is_client_locked.store(false, Ordering::Relaxed);
+        Ok(())
+    }
+
+    pub(crate) fn is_client_locked(&self) -> bool {
+        self.is_client_locked.load(Ordering::Relaxed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::ssh_agent::tests::{fake_ui, peer, test_agent};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_lock_and_unlock() {
+        let (agent, request_rx, response_tx) = test_agent();
+        let prompts = fake_ui(request_rx, response_tx, true, None);
+
+        agent.lock_from_client(&peer(1)).unwrap();
+        assert!(agent.is_client_locked());
+        assert!(agent.lock_from_client(&peer(1)).is_err());
+
+        agent.unlock_from_client(&peer(1)).await.unwrap();
+        assert!(!agent.is_client_locked());
+        assert_eq!(prompts.load(Ordering::Relaxed), 1);
+        assert!(agent.unlock_from_client(&peer(1)).await.is_err());
+
+        let entries = agent.audit_entries(10);
+        assert_eq!(entries[0].record.kind, AuditEventKind::Unlock);
+        assert_eq!(entries[0].record.outcome, ApprovalOutcome::Approved);
+        assert_eq!(entries[1].record.kind, AuditEventKind::Lock);
+    }
+
+    #[tokio::test]
+    async fn test_denied_unlock_keeps_agent_locked() {
+        let (agent, request_rx, response_tx) = test_agent();
+        fake_ui(request_rx, response_tx, false, None);
+
+        agent.lock_from_client(&peer(1)).unwrap();
+
+        assert!(agent.unlock_from_client(&peer(1)).await.is_err());
+        assert!(agent.is_client_locked());
+    }
+}
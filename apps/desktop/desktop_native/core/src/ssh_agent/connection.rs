@@ -0,0 +1,462 @@
+use std::{io, sync::atomic::Ordering};
+
+use anyhow::anyhow;
+use futures::{Stream, StreamExt};
+use tokio::{
+    io::{AsyncRead, AsyncWrite, DuplexStream},
+    sync::mpsc,
+};
+use tracing::{debug, error};
+
+use bitwarden_russh::ssh_agent::Agent;
+
+use super::{peerinfo::models::PeerInfo, protocol, BitwardenDesktopAgent, ForwardingPolicy};
+
+/// Buffer size of the in-memory pipe between a client connection and bitwarden-russh.
+const PIPE_BUFFER_SIZE: usize = 64 * 1024;
+
+impl BitwardenDesktopAgent {
+    /// Wraps the accepted client connections of a listener, so that agent protocol messages
+    /// that bitwarden-russh does not implement are answered here. All other messages are passed
+    /// on unchanged to `ssh_agent::serve`, which receives the returned stream.
+    pub(crate) fn intercept_connections<L, S>(
+        &self,
+        listener: L,
+    ) -> impl Stream<Item = io::Result<(DuplexStream, PeerInfo)>> + Unpin
+    where
+        L: Stream<Item = io::Result<(S, PeerInfo)>> + Unpin,
+        S: AsyncRead + AsyncWrite + Send + Unpin + 'static,
+    {
+        let agent = self.clone();
+        listener.map(move |connection| {
+            connection.map(|(stream, peer_info)| {
+                (
+                    agent.intercept_connection(stream, peer_info.clone()),
+                    peer_info,
+                )
+            })
+        })
+    }
+
+    fn intercept_connection<S>(&self, client: S, peer_info: PeerInfo) -> DuplexStream
+    where
+        S: AsyncRead + AsyncWrite + Send + Unpin + 'static,
+    {
+        let (russh_stream, agent_stream) = tokio::io::duplex(PIPE_BUFFER_SIZE);
+        let agent = self.clone();
+        agent.connected_clients.fetch_add(1, Ordering::Relaxed);
+        tokio::spawn(async move {
+            if let Err(e) = agent
+                .proxy_connection(client, agent_stream, &peer_info)
+                .await
+            {
+                error!(error = %e, pid = peer_info.pid(), "SSH agent connection failed");
+            }
+            agent.connected_clients.fetch_sub(1, Ordering::Relaxed);
+            debug!(pid = peer_info.pid(), "SSH agent connection closed");
+        });
+        russh_stream
+    }
+
+    async fn proxy_connection<S>(
+        &self,
+        client: S,
+        mut agent_stream: DuplexStream,
+        peer_info: &PeerInfo,
+    ) -> Result<(), anyhow::Error>
+    where
+        S: AsyncRead + AsyncWrite + Send + Unpin + 'static,
+    {
+        let (client_reader, mut client_writer) = tokio::io::split(client);
+        let mut messages = read_messages(client_reader, peer_info.clone());
+        while let Some(message) = messages.recv().await {
+            let message = message?;
+            let response = match self.handle_message(&message, peer_info).await {
+                Some(response) => response,
+                None => {
+                    protocol::write_message(&mut agent_stream, &message).await?;
+                    let response = protocol::read_message(&mut agent_stream)
+                        .await?
+                        .ok_or_else(|| anyhow!("SSH agent closed the connection"))?;
+                    if message.first() == Some(&protocol::SSH_AGENTC_REQUEST_IDENTITIES) {
+                        self.add_upstream_identities(response, peer_info).await
+                    } else {
+                        response
+                    }
+                }
+            };
+            protocol::write_message(&mut client_writer, &response).await?;
+        }
+        Ok(())
+    }
+
+    /// Returns the response to messages that are handled here, or `None` if the message should
+    /// be passed on to bitwarden-russh.
+    async fn handle_message(&self, message: &[u8], peer_info: &PeerInfo) -> Option<Vec<u8>> {
+        let (&message_type, body) = message.split_first()?;
+        if self.is_client_locked()
+            && !matches!(
+                message_type,
+                protocol::SSH_AGENTC_LOCK | protocol::SSH_AGENTC_UNLOCK
+            )
+        {
+            // like OpenSSH, a locked agent lists no keys and fails everything else
+            debug!(message_type, "Agent is locked, refusing request");
+            return Some(if message_type == protocol::SSH_AGENTC_REQUEST_IDENTITIES {
+                protocol::identities_answer(&[])
+            } else {
+                protocol::status_message(false)
+            });
+        }
+
+        match message_type {
+            protocol::SSH_AGENTC_REQUEST_IDENTITIES => {
+                self.expire_keys();
+                if peer_info.is_forwarding()
+                    && self.hide_keys_when_forwarded.load(Ordering::Relaxed)
+                {
+                    return Some(self.list_forwardable_identities(peer_info).await);
+                }
+                self.list_cached_identities(peer_info).await
+            }
+            protocol::SSH_AGENTC_SIGN_REQUEST => {
+                self.expire_keys();
+                if let Some(response) = self.unlock_for_cached_key(body, peer_info).await {
+                    return Some(response);
+                }
+                self.sign_upstream(message, body, peer_info).await
+            }
+            protocol::SSH_AGENTC_ADD_IDENTITY | protocol::SSH_AGENTC_ADD_ID_CONSTRAINED => {
+                let constrained = message_type == protocol::SSH_AGENTC_ADD_ID_CONSTRAINED;
+                let result = self.add_identity(body, constrained);
+                if let Err(ref e) = result {
+                    error!(error = %e, "Could not add identity");
+                }
+                Some(protocol::status_message(result.is_ok()))
+            }
+            protocol::SSH_AGENTC_REMOVE_IDENTITY => {
+                let result = self.remove_identity(body);
+                if let Err(ref e) = result {
+                    error!(error = %e, "Could not remove identity");
+                }
+                Some(protocol::status_message(result.is_ok()))
+            }
+            protocol::SSH_AGENTC_REMOVE_ALL_IDENTITIES => {
+                self.remove_all_identities();
+                Some(protocol::status_message(true))
+            }
+            protocol::SSH_AGENTC_LOCK => {
+                let result = self.lock_from_client(peer_info);
+                if let Err(ref e) = result {
+                    error!(error = %e, "Could not lock agent");
+                }
+                Some(protocol::status_message(result.is_ok()))
+            }
+            protocol::SSH_AGENTC_UNLOCK => {
+                let result = self.unlock_from_client(peer_info).await;
+                if let Err(ref e) = result {
+                    error!(error = %e, "Could not unlock agent");
+                }
+                Some(protocol::status_message(result.is_ok()))
+            }
+            _ => None,
+        }
+    }
+
+    /// Answers a list request on a forwarded connection with only the keys whose forwarding
+    /// policy allows forwarding.
+    async fn list_forwardable_identities(&self, peer_info: &PeerInfo) -> Vec<u8> {
+        if !self.can_list(peer_info).await {
+            return protocol::status_message(false);
+        }
+
+        let identities: Vec<(Vec<u8>, String)> = self
+            .keystore
+            .0
+            .read()
+            .expect("RwLock is not poisoned")
+            .iter()
+            .filter(|(_, key)| key.forwarding_policy == ForwardingPolicy::Allowed)
+            // the keystore index still holds the public key of locked keys
+            .map(|(public_key, key)| (public_key.clone(), key.name.clone()))
+            .collect();
+        protocol::identities_answer(&identities)
+    }
+}
+
+/// Reads the messages of a client in the background, so that a disconnect is noticed while a
+/// request is pending, e.g. to cancel its prompt.
+fn read_messages<R>(
+    mut reader: R,
+    peer_info: PeerInfo,
+) -> mpsc::Receiver<Result<Vec<u8>, anyhow::Error>>
+where
+    R: AsyncRead + Send + Unpin + 'static,
+{
+    let (message_tx, message_rx) = mpsc::channel(1);
+    tokio::spawn(async move {
+        loop {
+            let message = match protocol::read_message(&mut reader).await {
+                Ok(Some(message)) => Ok(message),
+                Ok(None) => break,
+                Err(e) => Err(e),
+            };
+            let is_error = message.is_err();
+            if message_tx.send(message).await.is_err() || is_error {
+                break;
+            }
+        }
+        peer_info.set_disconnected();
+    });
+    message_rx
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use bitwarden_russh::ssh_agent::{self, SshKey};
+    use futures::stream;
+    use p256::ecdsa::signature::Verifier;
+    use ssh_encoding::{Decode, Encode};
+    use ssh_key::{rand_core::OsRng, Algorithm, EcdsaCurve, LineEnding, PrivateKey, Signature};
+    use tokio::sync::Mutex;
+
+    use super::*;
+    use crate::ssh_agent::{tests::sign_data, ApprovalPolicy, ForwardingPolicy, VaultSshKey};
+
+    /// Starts an agent serving a single in-memory connection from `peer_info` and returns the
+    /// client end.
+    fn serve_one_connection(peer_info: PeerInfo) -> (BitwardenDesktopAgent, DuplexStream) {
+        let (request_tx, _request_rx) = tokio::sync::mpsc::channel(32);
+        let (_response_tx, response_rx) = tokio::sync::broadcast::channel(32);
+        let agent = BitwardenDesktopAgent::new(request_tx, Arc::new(Mutex::new(response_rx)));
+        agent.is_running.store(true, Ordering::Relaxed);
+        agent.needs_unlock.store(false, Ordering::Relaxed);
+
+        let (client, server) = tokio::io::duplex(PIPE_BUFFER_SIZE);
+        let listener = stream::iter(vec![Ok((server, peer_info))]).chain(stream::pending());
+        let stream = agent.intercept_connections(listener);
+        let cloned_agent = agent.clone();
+        tokio::spawn(async move {
+            let _ = ssh_agent::serve(
+                stream,
+                cloned_agent.clone(),
+                cloned_agent.keystore.clone(),
+                cloned_agent.cancellation_token.clone(),
+            )
+            .await;
+        });
+        (agent, client)
+    }
+
+    async fn request(client: &mut DuplexStream, message: &[u8]) -> Vec<u8> {
+        protocol::write_message(client, message).await.unwrap();
+        protocol::read_message(client).await.unwrap().unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_added_identity_is_listed() {
+        let (agent, mut client) = serve_one_connection(PeerInfo::unknown());
+        let private_key = PrivateKey::random(&mut OsRng, Algorithm::Ed25519).unwrap();
+
+        let mut add_identity = vec![protocol::SSH_AGENTC_ADD_IDENTITY];
+        private_key.key_data().encode(&mut add_identity).unwrap();
+        "added key".encode(&mut add_identity).unwrap();
+        assert_eq!(
+            request(&mut client, &add_identity).await,
+            vec![protocol::SSH_AGENT_SUCCESS]
+        );
+
+        // passed on to bitwarden-russh
+        let identities = request(&mut client, &[protocol::SSH_AGENTC_REQUEST_IDENTITIES]).await;
+        // SSH_AGENT_IDENTITIES_ANSWER with a single key
+        let mut expected = vec![12, 0, 0, 0, 1];
+        private_key
+            .public_key()
+            .to_bytes()
+            .unwrap()
+            .encode(&mut expected)
+            .unwrap();
+        "added key".encode(&mut expected).unwrap();
+        assert_eq!(identities, expected);
+
+        assert_eq!(
+            request(&mut client, &[protocol::SSH_AGENTC_REMOVE_ALL_IDENTITIES]).await,
+            vec![protocol::SSH_AGENT_SUCCESS]
+        );
+        assert!(agent.keystore.0.read().unwrap().is_empty());
+        agent.cancellation_token.cancel();
+    }
+
+    #[tokio::test]
+    async fn test_ecdsa_sign_requests() {
+        for curve in [
+            EcdsaCurve::NistP256,
+            EcdsaCurve::NistP384,
+            EcdsaCurve::NistP521,
+        ] {
+            let (mut agent, mut client) = serve_one_connection(PeerInfo::unknown());
+            let private_key = PrivateKey::random(&mut OsRng, Algorithm::Ecdsa { curve }).unwrap();
+            agent
+                .set_keys(vec![VaultSshKey {
+                    private_key: private_key.to_openssh(LineEnding::LF).unwrap().to_string(),
+                    name: "ecdsa key".to_string(),
+                    cipher_id: "cipher".to_string(),
+                    approval_policy: ApprovalPolicy::NeverAsk,
+                    forwarding_policy: ForwardingPolicy::Denied,
+                    certificate: None,
+                    destination_constraints: Vec::new(),
+                    lifetime: None,
+                }])
+                .unwrap();
+            agent.needs_unlock.store(false, Ordering::Relaxed);
+            let key = agent.keystore.0.read().unwrap().values().next().cloned();
+            let data = sign_data(&key.unwrap());
+
+            let mut sign_request = vec![protocol::SSH_AGENTC_SIGN_REQUEST];
+            private_key
+                .public_key()
+                .to_bytes()
+                .unwrap()
+                .encode(&mut sign_request)
+                .unwrap();
+            data.encode(&mut sign_request).unwrap();
+            // flags
+            0u32.encode(&mut sign_request).unwrap();
+            let response = request(&mut client, &sign_request).await;
+
+            // SSH_AGENT_SIGN_RESPONSE
+            let (&message_type, mut signature) = response.split_first().unwrap();
+            assert_eq!(message_type, 14);
+            let signature = Vec::<u8>::decode(&mut signature).unwrap();
+            let signature = Signature::decode(&mut signature.as_slice()).unwrap();
+            assert_eq!(signature.algorithm(), Algorithm::Ecdsa { curve });
+            private_key
+                .public_key()
+                .key_data()
+                .verify(&data, &signature)
+                .unwrap();
+            agent.cancellation_token.cancel();
+        }
+    }
+
+    #[tokio::test]
+    async fn test_forwarded_listing_hides_keys() {
+        let peer_info = PeerInfo::unknown();
+        peer_info.add_session_bind(vec![1, 2, 3], vec![4, 5, 6], true);
+        let (mut agent, mut client) = serve_one_connection(peer_info);
+        let mut keys = Vec::new();
+        for (name, forwarding_policy) in [
+            ("allowed", ForwardingPolicy::Allowed),
+            ("denied", ForwardingPolicy::Denied),
+            ("confirmed", ForwardingPolicy::RequireConfirmation),
+        ] {
+            let private_key = PrivateKey::random(&mut OsRng, Algorithm::Ed25519).unwrap();
+            keys.push(VaultSshKey {
+                private_key: private_key.to_openssh(LineEnding::LF).unwrap().to_string(),
+                name: name.to_string(),
+                cipher_id: name.to_string(),
+                approval_policy: ApprovalPolicy::NeverAsk,
+                forwarding_policy,
+                certificate: None,
+                destination_constraints: Vec::new(),
+                lifetime: None,
+            });
+        }
+        agent.set_keys(keys).unwrap();
+        agent.needs_unlock.store(false, Ordering::Relaxed);
+
+        let identities = request(&mut client, &[protocol::SSH_AGENTC_REQUEST_IDENTITIES]).await;
+        assert_eq!(&identities[..5], &[12, 0, 0, 0, 3]);
+
+        agent.set_hide_keys_when_forwarded(true);
+        let identities = request(&mut client, &[protocol::SSH_AGENTC_REQUEST_IDENTITIES]).await;
+        let allowed_key = agent
+            .keystore
+            .0
+            .read()
+            .unwrap()
+            .values()
+            .find(|key| key.name == "allowed")
+            .map(|key| (key.public_key_bytes(), key.name.clone()))
+            .unwrap();
+        assert_eq!(
+            identities,
+            protocol::identities_answer(&[allowed_key.clone()])
+        );
+
+        // locked keys are listed with their public key
+        agent.lock().unwrap();
+        agent.needs_unlock.store(false, Ordering::Relaxed);
+        let identities = request(&mut client, &[protocol::SSH_AGENTC_REQUEST_IDENTITIES]).await;
+        assert_eq!(identities, protocol::identities_answer(&[allowed_key]));
+        agent.cancellation_token.cancel();
+    }
+
+    #[tokio::test]
+    async fn test_closed_connection_marks_peer_disconnected() {
+        let peer_info = PeerInfo::unknown();
+        let (agent, client) = serve_one_connection(peer_info.clone());
+
+        drop(client);
+        peer_info.wait_for_disconnect().await;
+        agent.cancellation_token.cancel();
+    }
+
+    #[tokio::test]
+    async fn test_connected_clients_are_counted() {
+        let peer_info = PeerInfo::unknown();
+        let (agent, mut client) = serve_one_connection(peer_info.clone());
+        request(&mut client, &[protocol::SSH_AGENTC_REQUEST_IDENTITIES]).await;
+        assert_eq!(agent.status().connected_clients, 1);
+
+        drop(client);
+        peer_info.wait_for_disconnect().await;
+        while agent.status().connected_clients > 0 {
+            tokio::task::yield_now().await;
+        }
+        agent.cancellation_token.cancel();
+    }
+
+    #[tokio::test]
+    async fn test_locked_agent_refuses_requests() {
+        let (agent, mut client) = serve_one_connection(PeerInfo::unknown());
+        let private_key = PrivateKey::random(&mut OsRng, Algorithm::Ed25519).unwrap();
+        let mut add_identity = vec![protocol::SSH_AGENTC_ADD_IDENTITY];
+        private_key.key_data().encode(&mut add_identity).unwrap();
+        "added key".encode(&mut add_identity).unwrap();
+        request(&mut client, &add_identity).await;
+
+        let mut lock = vec![protocol::SSH_AGENTC_LOCK];
+        "passphrase".encode(&mut lock).unwrap();
+        assert_eq!(
+            request(&mut client, &lock).await,
+            vec![protocol::SSH_AGENT_SUCCESS]
+        );
+        assert!(agent.keystore.0.read().unwrap().is_empty());
+
+        assert_eq!(
+            request(&mut client, &[protocol::SSH_AGENTC_REQUEST_IDENTITIES]).await,
+            protocol::identities_answer(&[])
+        );
+        assert_eq!(
+            request(&mut client, &add_identity).await,
+            vec![protocol::SSH_AGENT_FAILURE]
+        );
+        assert_eq!(
+            request(&mut client, &lock).await,
+            vec![protocol::SSH_AGENT_FAILURE]
+        );
+
+        // the passphrase is ignored, and without a UI the unlock is denied
+        let mut unlock = vec![protocol::SSH_AGENTC_UNLOCK];
+        "passphrase".encode(&mut unlock).unwrap();
+        assert_eq!(
+            request(&mut client, &unlock).await,
+            vec![protocol::SSH_AGENT_FAILURE]
+        );
+        assert!(agent.is_client_locked());
+        agent.cancellation_token.cancel();
+    }
+}
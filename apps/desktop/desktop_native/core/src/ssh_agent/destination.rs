@@ -0,0 +1,253 @@
+use anyhow::anyhow;
+
+use super::{peerinfo::models::SessionBind, request_parser::UserAuthRequest};
+
+/// Restricts which hosts a key may be used with, modelled after `ssh-add -h`.
+///
+/// Without `from_host_key` the constraint permits connections made from this machine to
+/// `to_host_key`. With it, the constraint permits a forwarded agent on `from_host_key` to be
+/// used for connecting to `to_host_key`. Host keys are in OpenSSH public key format.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DestinationConstraint {
+    pub from_host_key: Option<String>,
+    pub to_host_key: String,
+}
+
+/// A parsed `DestinationConstraint`, holding the wire encoding of the host keys.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DestinationHop {
+    from: Option<Vec<u8>>,
+    to: Vec<u8>,
+}
+
+pub(crate) fn parse_constraints(
+    constraints: &[DestinationConstraint],
+) -> Result<Vec<DestinationHop>, anyhow::Error> {
+    constraints
+        .iter()
+        .map(|constraint| {
+            Ok(DestinationHop {
+                from: constraint
+                    .from_host_key
+                    .as_deref()
+                    .map(parse_host_key)
+                    .transpose()?,
+                to: parse_host_key(&constraint.to_host_key)?,
+            })
+        })
+        .collect()
+}
+
+fn parse_host_key(host_key: &str) -> Result<Vec<u8>, anyhow::Error> {
+    ssh_key::PublicKey::from_openssh(host_key.trim())
+        .and_then(|key| key.to_bytes())
+        .map_err(|e| anyhow!("Invalid host key in destination constraint: {e}"))
+}
+
+/// Checks that every hop of a connection, as recorded by its session-binds, is permitted by the
+/// destination constraints of a key that is about to sign.
+///
+/// Keys without constraints are unrestricted. Like OpenSSH, constrained keys only sign userauth
+/// requests for the session of the most recent bind, so a client cannot get around the
+/// constraints by skipping the session-bind or by asking for a signature over other data.
+pub(crate) fn check_destination(
+    hops: &[DestinationHop],
+    session_binds: &[SessionBind],
+    user_auth: Option<&UserAuthRequest>,
+) -> Result<(), anyhow::Error> {
+    if hops.is_empty() {
+        return Ok(());
+    }
+
+    let Some(last_bind) = session_binds.last() else {
+        return Err(anyhow!(
+            "Refusing to sign with a constrained key on a connection without session-bind"
+        ));
+    };
+    let Some(user_auth) = user_auth else {
+        return Err(anyhow!(
+            "Refusing to sign data other than a userauth request with a constrained key"
+        ));
+    };
+    if user_auth.session_id != last_bind.session_id {
+        return Err(anyhow!(
+            "Userauth request does not belong to the bound session"
+        ));
+    }
+    if user_auth.host_key.as_ref() != Some(&last_bind.host_key) {
+        return Err(anyhow!(
+            "Userauth request is not bound to the host key of the session"
+        ));
+    }
+
+    // Authentication happens on a non-forwarding bind; a signature on a forwarding bind means
+    // the forwarded agent is being misused.
+    if last_bind.is_forwarding {
+        return Err(anyhow!("Refusing to sign on a forwarding session-bind"));
+    }
+
+    let mut from: Option<&[u8]> = None;
+    for (index, bind) in session_binds.iter().enumerate() {
+        if index + 1 < session_binds.len() && !bind.is_forwarding {
+            return Err(anyhow!(
+                "Agent was forwarded through a non-forwarding session"
+            ));
+        }
+
+        let permitted = hops
+            .iter()
+            .any(|hop| hop.from.as_deref() == from && hop.to == bind.host_key);
+        if !permitted {
+            return Err(anyhow!(
+                "Host key is not permitted by the destination constraints"
+            ));
+        }
+
+        from = Some(&bind.host_key);
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use ssh_key::{rand_core::OsRng, Algorithm, PrivateKey};
+
+    use super::*;
+
+    fn host_key() -> String {
+        PrivateKey::random(&mut OsRng, Algorithm::Ed25519)
+            .unwrap()
+            .public_key()
+            .to_openssh()
+            .unwrap()
+    }
+
+    fn bind(host_key: &str, is_forwarding: bool) -> SessionBind {
+        SessionBind {
+            host_key: parse_host_key(host_key).unwrap(),
+            session_id: host_key.as_bytes().to_vec(),
+            is_forwarding,
+        }
+    }
+
+    /// A `publickey-hostbound-v00@openssh.com` request on the session of `bind`.
+    fn user_auth(bind: &SessionBind) -> UserAuthRequest {
+        UserAuthRequest {
+            session_id: bind.session_id.clone(),
+            user_name: "deploy".to_string(),
+            service: "ssh-connection".to_string(),
+            algorithm: "ssh-ed25519".to_string(),
+            public_key: Vec::new(),
+            host_key: Some(bind.host_key.clone()),
+        }
+    }
+
+    /// Checks `binds` for a userauth request on the session of the last bind.
+    fn check(hops: &[DestinationHop], binds: &[SessionBind]) -> Result<(), anyhow::Error> {
+        let request = binds.last().map(user_auth);
+        check_destination(hops, binds, request.as_ref())
+    }
+
+    fn constraint(from: Option<&str>, to: &str) -> DestinationConstraint {
+        DestinationConstraint {
+            from_host_key: from.map(str::to_string),
+            to_host_key: to.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_unconstrained_key_is_permitted() {
+        let host = host_key();
+
+        assert!(check_destination(&[], &[bind(&host, false)], None).is_ok());
+        assert!(check_destination(&[], &[], None).is_ok());
+    }
+
+    #[test]
+    fn test_connection_without_bind_is_refused() {
+        let host = host_key();
+        let hops = parse_constraints(&[constraint(None, &host)]).unwrap();
+        let request = user_auth(&bind(&host, false));
+
+        assert!(check_destination(&hops, &[], None).is_err());
+        assert!(check_destination(&hops, &[], Some(&request)).is_err());
+    }
+
+    #[test]
+    fn test_arbitrary_data_is_refused() {
+        let host = host_key();
+        let hops = parse_constraints(&[constraint(None, &host)]).unwrap();
+
+        assert!(check_destination(&hops, &[bind(&host, false)], None).is_err());
+    }
+
+    #[test]
+    fn test_user_auth_must_match_last_bind() {
+        let host = host_key();
+        let hops = parse_constraints(&[constraint(None, &host)]).unwrap();
+        let binds = [bind(&host, false)];
+
+        let other_session = UserAuthRequest {
+            session_id: vec![1, 2, 3],
+            ..user_auth(&binds[0])
+        };
+        assert!(check_destination(&hops, &binds, Some(&other_session)).is_err());
+
+        let not_hostbound = UserAuthRequest {
+            host_key: None,
+            ..user_auth(&binds[0])
+        };
+        assert!(check_destination(&hops, &binds, Some(&not_hostbound)).is_err());
+    }
+
+    #[test]
+    fn test_direct_connection_to_permitted_host() {
+        let host = host_key();
+        let hops = parse_constraints(&[constraint(None, &host)]).unwrap();
+
+        assert!(check(&hops, &[bind(&host, false)]).is_ok());
+        assert!(check(&hops, &[bind(&host_key(), false)]).is_err());
+    }
+
+    #[test]
+    fn test_forwarded_connection_requires_hop_constraint() {
+        let jump = host_key();
+        let target = host_key();
+        let binds = [bind(&jump, true), bind(&target, false)];
+
+        let only_jump = parse_constraints(&[constraint(None, &jump)]).unwrap();
+        assert!(check(&only_jump, &binds).is_err());
+
+        let both_hops =
+            parse_constraints(&[constraint(None, &jump), constraint(Some(&jump), &target)])
+                .unwrap();
+        assert!(check(&both_hops, &binds).is_ok());
+
+        // the target may only be reached through the jump host
+        assert!(check(&both_hops, &[bind(&target, false)]).is_err());
+    }
+
+    #[test]
+    fn test_signing_on_forwarding_bind_is_refused() {
+        let host = host_key();
+        let hops = parse_constraints(&[constraint(None, &host)]).unwrap();
+
+        assert!(check(&hops, &[bind(&host, true)]).is_err());
+    }
+
+    #[test]
+    fn test_forwarding_through_non_forwarding_bind_is_refused() {
+        let jump = host_key();
+        let target = host_key();
+        let hops = parse_constraints(&[constraint(None, &jump), constraint(Some(&jump), &target)])
+            .unwrap();
+
+        assert!(check(&hops, &[bind(&jump, false), bind(&target, false)]).is_err());
+    }
+
+    #[test]
+    fn test_invalid_host_key_is_rejected() {
+        assert!(parse_constraints(&[constraint(None, "not a key")]).is_err());
+    }
+}
@@ -0,0 +1,250 @@
+use std::time::Duration;
+
+use anyhow::anyhow;
+use ssh_encoding::Decode;
+use ssh_key::{private::KeypairData, HashAlg};
+use tokio::time::Instant;
+use tracing::info;
+
+use super::{protocol, ApprovalPolicy, BitwardenDesktopAgent, BitwardenSshKey, ForwardingPolicy};
+
+/// Keys added by clients with `ssh-add`. They are only held in memory next to the vault keys,
+/// and are removed when the agent is locked or stopped.
+impl BitwardenDesktopAgent {
+    /// Handles `SSH_AGENTC_ADD_IDENTITY` and `SSH_AGENTC_ADD_ID_CONSTRAINED`.
+    pub(crate) fn add_identity(&self, body: &[u8], constrained: bool) -> Result<(), anyhow::Error> {
+        if !self.is_running() {
+            return Err(anyhow!("Tried to add a key while agent is not running"));
+        }
+
+        let mut reader = body;
+        let keypair =
+            KeypairData::decode(&mut reader).map_err(|e| anyhow!("Failed to parse key: {e}"))?;
+        let comment =
+            String::decode(&mut reader).map_err(|e| anyhow!("Failed to parse comment: {e}"))?;
+
+        let mut lifetime = None;
+        let mut confirm = false;
+        while constrained && !reader.is_empty() {
+            let constraint =
+                u8::decode(&mut reader).map_err(|e| anyhow!("Failed to parse constraint: {e}"))?;
+            match constraint {
+                protocol::SSH_AGENT_CONSTRAIN_LIFETIME => {
+                    let seconds = u32::decode(&mut reader)
+                        .map_err(|e| anyhow!("Failed to parse lifetime: {e}"))?;
+                    lifetime = Some(Duration::from_secs(seconds.into()));
+                }
+                protocol::SSH_AGENT_CONSTRAIN_CONFIRM => confirm = true,
+                // Ignoring a constraint would make the key more permissive than requested
+                other => return Err(anyhow!("Unsupported key constraint {other}")),
+            }
+        }
+        if !reader.is_empty() {
+            return Err(anyhow!("Unexpected trailing data"));
+        }
+
+        let private_key = ssh_key::PrivateKey::new(keypair, comment.clone())
+            .map_err(|e| anyhow!("Failed to parse key: {e}"))?;
+        let public_key_bytes = private_key
+            .public_key()
+            .to_bytes()
+            .map_err(|e| anyhow!("Failed to parse public key: {e}"))?;
+        let fingerprint = private_key.fingerprint(HashAlg::Sha256).to_string();
+
+        let mut keystore = self.keystore.0.write().expect("RwLock is not poisoned");
+        if keystore
+            .get(&public_key_bytes)
+            .is_some_and(|key| !key.is_ephemeral)
+        {
+            return Err(anyhow!(
+                "Key {fingerprint} is already provided by the vault"
+            ));
+        }
+
+        info!(%fingerprint, ?lifetime, confirm, "Adding ephemeral key");
+        keystore.insert(
+            public_key_bytes,
+            BitwardenSshKey {
+                private_key: Some(private_key),
+                name: comment,
+                // ephemeral keys have no cipher, the fingerprint identifies them for approvals
+                cipher_uuid: fingerprint,
+                approval_policy: if confirm {
+                    ApprovalPolicy::AlwaysAsk
+                } else {
+                    ApprovalPolicy::NeverAsk
+                },
+                // keys added with `ssh-add` have no policy of their own and are not forwarded
+                forwarding_policy: ForwardingPolicy::Denied,
+                certificate: None,
+                destination_constraints: Vec::new(),
+                is_ephemeral: true,
+                lifetime: None,
+                expires_at: lifetime.map(|lifetime| Instant::now() + lifetime),
+            },
+        );
+        Ok(())
+    }
+
+    /// Handles `SSH_AGENTC_REMOVE_IDENTITY`. Vault keys cannot be removed by clients.
+    pub(crate) fn remove_identity(&self, body: &[u8]) -> Result<(), anyhow::Error> {
+        let mut reader = body;
+        let public_key_bytes =
+            Vec::<u8>::decode(&mut reader).map_err(|e| anyhow!("Failed to parse key: {e}"))?;
+
+        let mut keystore = self.keystore.0.write().expect("RwLock is not poisoned");
+        match keystore.get(&public_key_bytes) {
+            Some(key) if key.is_ephemeral => {
+                keystore.remove(&public_key_bytes);
+                Ok(())
+            }
+            Some(_) => Err(anyhow!("Vault keys cannot be removed")),
+            None => Err(anyhow!("Key not found")),
+        }
+    }
+
+    /// Handles `SSH_AGENTC_REMOVE_ALL_IDENTITIES`. Vault keys are kept.
+    pub(crate) fn remove_all_identities(&self) {
+        self.keystore
+            .0
+            .write()
+            .expect("RwLock is not poisoned")
+            .retain(|_, key| !key.is_ephemeral);
+    }
+
+    /// Removes ephemeral keys whose lifetime has passed.
+    pub(crate) fn remove_expired_keys(&self) {
+        let now = Instant::now();
+        self.keystore
+            .0
+            .write()
+            .expect("RwLock is not poisoned")
+            .retain(|_, key| !key.is_ephemeral || key.expires_at.is_none_or(|at| at > now));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ssh_encoding::Encode;
+    use ssh_key::{rand_core::OsRng, Algorithm, PrivateKey};
+
+    use crate::ssh_agent::tests::test_agent;
+
+    use super::*;
+
+    fn add_identity_body(private_key: &PrivateKey, constraints: &[u8]) -> Vec<u8> {
+        let mut body = Vec::new();
+        private_key.key_data().encode(&mut body).unwrap();
+        "added key".encode(&mut body).unwrap();
+        body.extend_from_slice(constraints);
+        body
+    }
+
+    fn stored_key(
+        agent: &BitwardenDesktopAgent,
+        private_key: &PrivateKey,
+    ) -> Option<BitwardenSshKey> {
+        agent
+            .keystore
+            .0
+            .read()
+            .unwrap()
+            .get(&private_key.public_key().to_bytes().unwrap())
+            .cloned()
+    }
+
+    #[test]
+    fn test_add_identity() {
+        let (agent, _request_rx, _response_tx) = test_agent();
+        let private_key = PrivateKey::random(&mut OsRng, Algorithm::Ed25519).unwrap();
+
+        agent
+            .add_identity(&add_identity_body(&private_key, &[]), false)
+            .unwrap();
+
+        let key = stored_key(&agent, &private_key).unwrap();
+        assert!(key.is_ephemeral);
+        assert_eq!(key.name, "added key");
+        assert_eq!(key.approval_policy, ApprovalPolicy::NeverAsk);
+        assert_eq!(key.forwarding_policy, ForwardingPolicy::Denied);
+        assert!(key.expires_at.is_none());
+        assert_eq!(key.private_key.unwrap().key_data(), private_key.key_data());
+    }
+
+    #[test]
+    fn test_add_identity_with_constraints() {
+        let (agent, _request_rx, _response_tx) = test_agent();
+        let private_key = PrivateKey::random(&mut OsRng, Algorithm::Ed25519).unwrap();
+        let mut constraints = vec![protocol::SSH_AGENT_CONSTRAIN_LIFETIME];
+        constraints.extend_from_slice(&60u32.to_be_bytes());
+        constraints.push(protocol::SSH_AGENT_CONSTRAIN_CONFIRM);
+
+        agent
+            .add_identity(&add_identity_body(&private_key, &constraints), true)
+            .unwrap();
+
+        let key = stored_key(&agent, &private_key).unwrap();
+        assert_eq!(key.approval_policy, ApprovalPolicy::AlwaysAsk);
+        assert!(key.expires_at.unwrap() > Instant::now());
+    }
+
+    #[test]
+    fn test_add_identity_rejects_unknown_constraint() {
+        let (agent, _request_rx, _response_tx) = test_agent();
+        let private_key = PrivateKey::random(&mut OsRng, Algorithm::Ed25519).unwrap();
+
+        assert!(agent
+            .add_identity(&add_identity_body(&private_key, &[255]), true)
+            .is_err());
+        assert!(stored_key(&agent, &private_key).is_none());
+    }
+
+    #[test]
+    fn test_expired_keys_are_removed() {
+        let (agent, _request_rx, _response_tx) = test_agent();
+        let private_key = PrivateKey::random(&mut OsRng, Algorithm::Ed25519).unwrap();
+        let mut constraints = vec![protocol::SSH_AGENT_CONSTRAIN_LIFETIME];
+        constraints.extend_from_slice(&0u32.to_be_bytes());
+        agent
+            .add_identity(&add_identity_body(&private_key, &constraints), true)
+            .unwrap();
+
+        agent.remove_expired_keys();
+
+        assert!(stored_key(&agent, &private_key).is_none());
+    }
+
+    #[test]
+    fn test_remove_identity() {
+        let (agent, _request_rx, _response_tx) = test_agent();
+        let private_key = PrivateKey::random(&mut OsRng, Algorithm::Ed25519).unwrap();
+        agent
+            .add_identity(&add_identity_body(&private_key, &[]), false)
+            .unwrap();
+
+        let mut body = Vec::new();
+        private_key
+            .public_key()
+            .to_bytes()
+            .unwrap()
+            .encode(&mut body)
+            .unwrap();
+        agent.remove_identity(&body).unwrap();
+
+        assert!(stored_key(&agent, &private_key).is_none());
+        assert!(agent.remove_identity(&body).is_err());
+    }
+
+    #[test]
+    fn test_lock_removes_ephemeral_keys() {
+        let (mut agent, _request_rx, _response_tx) = test_agent();
+        let private_key = PrivateKey::random(&mut OsRng, Algorithm::Ed25519).unwrap();
+        agent
+            .add_identity(&add_identity_body(&private_key, &[]), false)
+            .unwrap();
+
+        agent.lock().unwrap();
+
+        assert!(stored_key(&agent, &private_key).is_none());
+    }
+}
@@ -0,0 +1,390 @@
+use std::{
+    collections::HashSet,
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use tracing::{error, info};
+
+use super::BitwardenDesktopAgent;
+
+/// Name of the combined file in the export directory, in `authorized_keys` format.
+const AUTHORIZED_KEYS_FILE: &str = "authorized_keys";
+
+/// Lists the files that the export wrote, one per line. Only these are ever replaced or removed,
+/// so that pointing the export at a directory like `~/.ssh` leaves the user's own files alone.
+const MANIFEST_FILE: &str = ".bitwarden-export";
+
+/// Where and how the public keys of the vault keys are written to disk, so that ssh_config can
+/// select a key with `IdentityFile` and `IdentitiesOnly yes`. Private keys are never written.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PublicKeyExport {
+    /// Directory that the keys are written to. Files that an earlier export wrote and that do not
+    /// belong to a current key are removed.
+    pub directory: PathBuf,
+    /// Also write all public keys to a single `authorized_keys` file
+    pub authorized_keys: bool,
+}
+
+impl PublicKeyExport {
+    /// `~/.ssh/bitwarden`
+    pub fn default_directory() -> Option<PathBuf> {
+        dirs::home_dir().map(|home| home.join(".ssh").join("bitwarden"))
+    }
+}
+
+/// The public halves of a vault key, in OpenSSH format.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct ExportedKey {
+    name: String,
+    cipher_id: String,
+    public_key: String,
+    /// Written next to the key as `<name>-cert.pub`, where ssh looks for it
+    certificate: Option<String>,
+}
+
+impl BitwardenDesktopAgent {
+    /// Enables writing the public keys to disk, and writes them right away. `None` stops keeping
+    /// the files in sync but leaves them in place.
+    pub fn set_public_key_export(&self, export: Option<PublicKeyExport>) {
+        *self
+            .public_key_export
+            .write()
+            .expect("RwLock is not poisoned") = export;
+        self.sync_public_key_export();
+    }
+
+    /// Brings the exported files in line with the vault keys. Failures are logged, since they
+    /// must not keep keys from being loaded.
+    pub(crate) fn sync_public_key_export(&self) {
+        let Some(export) = self
+            .public_key_export
+            .read()
+            .expect("RwLock is not poisoned")
+            .clone()
+        else {
+            return;
+        };
+
+        if let Err(e) = write_public_keys(&export, &self.exported_keys()) {
+            error!(error = %e, directory = ?export.directory, "Could not export public keys");
+        }
+    }
+
+    fn exported_keys(&self) -> Vec<ExportedKey> {
+        let keystore = self.keystore.0.read().expect("RwLock is not poisoned");
+        // the public key is taken from the keystore index, so expired keys are still exported
+        let mut keys: Vec<ExportedKey> = keystore
+            .iter()
+            .filter(|(_, key)| !key.is_ephemeral && key.certificate.is_none())
+            .filter_map(|(public_key, key)| {
+                let public_key = ssh_key::PublicKey::from_bytes(public_key).ok()?;
+                let certificate = keystore
+                    .values()
+                    .filter(|other| other.cipher_uuid == key.cipher_uuid)
+                    .find_map(|other| other.certificate.as_ref()?.to_openssh().ok());
+                Some(ExportedKey {
+                    name: key.name.clone(),
+                    cipher_id: key.cipher_uuid.clone(),
+                    public_key: public_key.to_openssh().ok()?,
+                    certificate,
+                })
+            })
+            .collect();
+        keys.sort_by(|a, b| (&a.name, &a.cipher_id).cmp(&(&b.name, &b.cipher_id)));
+        keys
+    }
+}
+
+fn write_public_keys(export: &PublicKeyExport, keys: &[ExportedKey]) -> Result<(), io::Error> {
+    create_directory(&export.directory)?;
+    let managed = read_manifest(&export.directory)?;
+
+    let mut files = HashSet::new();
+    let mut authorized_keys = String::new();
+    for key in keys {
+        let stem = file_stem(key, &files);
+        let line = format!("{} {}\n", key.public_key, key.name);
+        write_exported(export, &managed, &mut files, format!("{stem}.pub"), &line)?;
+        if let Some(ref certificate) = key.certificate {
+            let line = format!("{certificate} {}\n", key.name);
+            write_exported(
+                export,
+                &managed,
+                &mut files,
+                format!("{stem}-cert.pub"),
+                &line,
+            )?;
+        }
+        authorized_keys.push_str(&line);
+    }
+    if export.authorized_keys {
+        write_exported(
+            export,
+            &managed,
+            &mut files,
+            AUTHORIZED_KEYS_FILE.to_string(),
+            &authorized_keys,
+        )?;
+    }
+
+    for file_name in managed.difference(&files) {
+        info!(file = %file_name, "Removing stale exported public key");
+        match fs::remove_file(export.directory.join(file_name)) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+            _ => {}
+        }
+    }
+    write_manifest(&export.directory, &files)
+}
+
+/// Writes a file of the export, unless a file of the same name exists that the export did not
+/// write.
+fn write_exported(
+    export: &PublicKeyExport,
+    managed: &HashSet<String>,
+    files: &mut HashSet<String>,
+    file_name: String,
+    contents: &str,
+) -> Result<(), io::Error> {
+    let path = export.directory.join(&file_name);
+    if !managed.contains(&file_name) && fs::symlink_metadata(&path).is_ok() {
+        error!(file = %file_name, "Not overwriting a file that the export did not write");
+        return Ok(());
+    }
+    write_if_changed(&path, contents)?;
+    files.insert(file_name);
+    Ok(())
+}
+
+fn read_manifest(directory: &Path) -> Result<HashSet<String>, io::Error> {
+    match fs::read_to_string(directory.join(MANIFEST_FILE)) {
+        Ok(manifest) => Ok(manifest
+            .lines()
+            // the manifest is only trusted with plain names within the directory
+            .filter(|name| is_exported_file_name(name))
+            .map(str::to_string)
+            .collect()),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(HashSet::new()),
+        Err(e) => Err(e),
+    }
+}
+
+fn write_manifest(directory: &Path, files: &HashSet<String>) -> Result<(), io::Error> {
+    let mut files: Vec<&String> = files.iter().collect();
+    files.sort();
+    let manifest: String = files.into_iter().fold(String::new(), |mut manifest, name| {
+        manifest.push_str(name);
+        manifest.push('\n');
+        manifest
+    });
+    write_if_changed(&directory.join(MANIFEST_FILE), &manifest)
+}
+
+fn is_exported_file_name(name: &str) -> bool {
+    (name.ends_with(".pub") || name == AUTHORIZED_KEYS_FILE)
+        && !name.starts_with('.')
+        && !name.contains(['/', '\\'])
+}
+
+/// A file name for the key that is safe on all platforms and unique within the export.
+fn file_stem(key: &ExportedKey, taken: &HashSet<String>) -> String {
+    let name: String = key
+        .name
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.') {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    let name = name.trim_matches('.');
+    let stem = if name.is_empty() {
+        key.cipher_id.clone()
+    } else {
+        name.to_string()
+    };
+
+    if taken.contains(&format!("{stem}.pub")) {
+        format!("{stem}-{}", key.cipher_id)
+    } else {
+        stem
+    }
+}
+
+/// The vault reloads its keys regularly, so unchanged files are left alone.
+fn write_if_changed(path: &Path, contents: &str) -> Result<(), io::Error> {
+    if fs::read_to_string(path).is_ok_and(|existing| existing == contents) {
+        return Ok(());
+    }
+    fs::write(path, contents)
+}
+
+fn create_directory(directory: &Path) -> Result<(), io::Error> {
+    fs::create_dir_all(directory)?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(directory, fs::Permissions::from_mode(0o700))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::ssh_agent::{
+        tests::{test_agent, vault_key},
+        ApprovalPolicy, VaultSshKey,
+    };
+
+    use super::*;
+
+    fn export_directory(name: &str) -> PathBuf {
+        let directory = std::env::temp_dir().join(format!("export-{name}-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&directory);
+        directory
+    }
+
+    fn file_names(directory: &Path) -> Vec<String> {
+        let mut names: Vec<String> = fs::read_dir(directory)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().to_string())
+            .collect();
+        names.sort();
+        names
+    }
+
+    #[tokio::test]
+    async fn test_public_keys_follow_set_keys() {
+        let (mut agent, _request_rx, _response_tx) = test_agent();
+        let directory = export_directory("sync");
+        agent.set_public_key_export(Some(PublicKeyExport {
+            directory: directory.clone(),
+            authorized_keys: true,
+        }));
+
+        let github = VaultSshKey {
+            name: "GitHub / work".to_string(),
+            cipher_id: "github".to_string(),
+            ..vault_key(ApprovalPolicy::NeverAsk)
+        };
+        let server = VaultSshKey {
+            name: "server".to_string(),
+            cipher_id: "server".to_string(),
+            ..vault_key(ApprovalPolicy::NeverAsk)
+        };
+        agent.set_keys(vec![github, server]).unwrap();
+        assert_eq!(
+            file_names(&directory),
+            vec![
+                ".bitwarden-export",
+                "GitHub___work.pub",
+                "authorized_keys",
+                "server.pub"
+            ]
+        );
+        let public_key = fs::read_to_string(directory.join("server.pub")).unwrap();
+        assert!(public_key.starts_with("ssh-ed25519 "));
+        assert!(public_key.ends_with(" server\n"));
+        let authorized_keys = fs::read_to_string(directory.join("authorized_keys")).unwrap();
+        assert_eq!(authorized_keys.lines().count(), 2);
+
+        // private keys are never written
+        assert!(!authorized_keys.contains("PRIVATE"));
+
+        agent
+            .set_keys(vec![VaultSshKey {
+                name: "server".to_string(),
+                cipher_id: "server".to_string(),
+                ..vault_key(ApprovalPolicy::NeverAsk)
+            }])
+            .unwrap();
+        assert_eq!(
+            file_names(&directory),
+            vec![".bitwarden-export", "authorized_keys", "server.pub"]
+        );
+
+        agent.clear_keys().unwrap();
+        assert_eq!(
+            file_names(&directory),
+            vec![".bitwarden-export", "authorized_keys"]
+        );
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_files_not_written_by_export_are_kept() {
+        let (mut agent, _request_rx, _response_tx) = test_agent();
+        let directory = export_directory("foreign");
+        fs::create_dir_all(&directory).unwrap();
+        fs::write(directory.join("id_ed25519.pub"), "user key\n").unwrap();
+        fs::write(directory.join("server.pub"), "user key\n").unwrap();
+        fs::write(directory.join("authorized_keys"), "user keys\n").unwrap();
+        agent.set_public_key_export(Some(PublicKeyExport {
+            directory: directory.clone(),
+            authorized_keys: true,
+        }));
+
+        let key = |name: &str| VaultSshKey {
+            name: name.to_string(),
+            cipher_id: name.to_string(),
+            ..vault_key(ApprovalPolicy::NeverAsk)
+        };
+        agent.set_keys(vec![key("server"), key("github")]).unwrap();
+        assert_eq!(
+            file_names(&directory),
+            vec![
+                ".bitwarden-export",
+                "authorized_keys",
+                "github.pub",
+                "id_ed25519.pub",
+                "server.pub"
+            ]
+        );
+        for file in ["id_ed25519.pub", "server.pub"] {
+            assert_eq!(
+                fs::read_to_string(directory.join(file)).unwrap(),
+                "user key\n"
+            );
+        }
+        assert_eq!(
+            fs::read_to_string(directory.join("authorized_keys")).unwrap(),
+            "user keys\n"
+        );
+
+        agent.clear_keys().unwrap();
+        assert_eq!(
+            file_names(&directory),
+            vec![
+                ".bitwarden-export",
+                "authorized_keys",
+                "id_ed25519.pub",
+                "server.pub"
+            ]
+        );
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn test_file_stem() {
+        let key = ExportedKey {
+            name: "../id".to_string(),
+            cipher_id: "cipher".to_string(),
+            public_key: String::new(),
+            certificate: None,
+        };
+
+        assert_eq!(file_stem(&key, &HashSet::new()), "_id");
+        assert_eq!(
+            file_stem(&key, &HashSet::from(["_id.pub".to_string()])),
+            "_id-cipher"
+        );
+        let unnamed = ExportedKey {
+            name: "..".to_string(),
+            ..key
+        };
+        assert_eq!(file_stem(&unnamed, &HashSet::new()), "cipher");
+    }
+}
@@ -0,0 +1,506 @@
+//! PuTTY private key files, versions 2 and 3.
+//!
+//! The format is described in the PuTTY source (`sshpubk.c`). Both versions store the public key
+//! blob, the private key blob (optionally encrypted with AES-256-CBC) and a MAC over both, which
+//! also tells whether the passphrase was correct.
+
+use aes::cipher::{block_padding::NoPadding, BlockDecryptMut, KeyIvInit};
+use argon2::{Argon2, Block, Params};
+use base64::{engine::general_purpose::STANDARD as base64_engine, Engine};
+use hmac::{Hmac, Mac};
+use sha1::{Digest, Sha1};
+use sha2::Sha256;
+use ssh_encoding::Decode;
+use ssh_key::{private::KeypairData, public::KeyData, Mpint, PrivateKey};
+
+use super::{decode_hex, ecdsa_keypair, ed25519_keypair, rsa_keypair, ImportError};
+
+pub(super) const HEADER_PREFIX: &str = "PuTTY-User-Key-File-";
+
+const ENCRYPTION_NONE: &str = "none";
+const ENCRYPTION_AES256_CBC: &str = "aes256-cbc";
+const V2_MAC_KEY_PREFIX: &[u8] = b"putty-private-key-file-mac-key";
+
+/// Upper bounds for the Argon2 parameters, which come from the file. PuTTY uses 8 MiB and picks
+/// the passes to take about a tenth of a second, so these leave plenty of room while keeping a
+/// crafted file from allocating terabytes or spinning forever.
+const MAX_ARGON2_MEMORY_KIB: u32 = 1024 * 1024;
+const MAX_ARGON2_PASSES: u32 = 256;
+const MAX_ARGON2_PARALLELISM: u32 = 64;
+
+/// The Argon2 parameters of an encrypted version 3 file.
+struct KeyDerivation {
+    algorithm: argon2::Algorithm,
+    memory: u32,
+    passes: u32,
+    parallelism: u32,
+    salt: Vec<u8>,
+}
+
+struct PpkFile<'a> {
+    version: u8,
+    algorithm: &'a str,
+    encryption: &'a str,
+    comment: &'a str,
+    public_blob: Vec<u8>,
+    key_derivation: Option<KeyDerivation>,
+    private_blob: Vec<u8>,
+    mac: Vec<u8>,
+}
+
+/// Keys derived from the passphrase.
+struct FileKeys {
+    cipher_key: Vec<u8>,
+    iv: Vec<u8>,
+    mac_key: Vec<u8>,
+}
+
+pub(super) fn import(text: &str, passphrase: Option<&str>) -> Result<PrivateKey, ImportError> {
+    let mut file = PpkFile::parse(text)?;
+
+    let keys = match (file.encryption, passphrase) {
+        (ENCRYPTION_NONE, _) => file.derive_keys(b"")?,
+        (ENCRYPTION_AES256_CBC, Some(passphrase)) => file.derive_keys(passphrase.as_bytes())?,
+        (ENCRYPTION_AES256_CBC, None) => return Err(ImportError::PassphraseRequired),
+        (encryption, _) => return Err(ImportError::UnsupportedCipher(encryption.to_string())),
+    };
+
+    if file.encryption == ENCRYPTION_AES256_CBC {
+        file.private_blob =
+            cbc::Decryptor::<aes::Aes256>::new_from_slices(&keys.cipher_key, &keys.iv)
+                .map_err(ImportError::parsing)?
+                .decrypt_padded_vec_mut::<NoPadding>(&file.private_blob)
+                .map_err(|_| {
+                    ImportError::parsing("Private key is not a multiple of the block size")
+                })?;
+    }
+
+    if !file.verify_mac(&keys.mac_key) {
+        return Err(if file.encryption == ENCRYPTION_NONE {
+            ImportError::parsing("MAC mismatch, the key file is corrupted")
+        } else {
+            ImportError::WrongPassphrase
+        });
+    }
+
+    let keypair = file.keypair()?;
+    PrivateKey::new(keypair, file.comment).map_err(ImportError::parsing)
+}
+
+impl<'a> PpkFile<'a> {
+    fn parse(text: &'a str) -> Result<Self, ImportError> {
+        let mut lines = Lines(text.lines().peekable());
+
+        let (version, algorithm) = lines
+            .next()?
+            .strip_prefix(HEADER_PREFIX)
+            .and_then(|header| header.split_once(": "))
+            .ok_or_else(|| ImportError::parsing("Missing PuTTY key file header"))?;
+        let version = match version {
+            "2" => 2,
+            "3" => 3,
+            version => {
+                return Err(ImportError::parsing(format!(
+                    "Unsupported PuTTY key file version {version}"
+                )))
+            }
+        };
+
+        let encryption = lines.header("Encryption")?;
+        let comment = lines.header("Comment")?;
+        let public_blob = lines.base64("Public-Lines")?;
+        let key_derivation = match lines.optional_header("Key-Derivation") {
+            Some(algorithm) if version == 3 => Some(KeyDerivation {
+                algorithm: match algorithm {
+                    "Argon2id" => argon2::Algorithm::Argon2id,
+                    "Argon2i" => argon2::Algorithm::Argon2i,
+                    "Argon2d" => argon2::Algorithm::Argon2d,
+                    algorithm => return Err(ImportError::UnsupportedCipher(algorithm.to_string())),
+                },
+                memory: lines.number("Argon2-Memory")?,
+                passes: lines.number("Argon2-Passes")?,
+                parallelism: lines.number("Argon2-Parallelism")?,
+                salt: decode_hex(lines.header("Argon2-Salt")?)?,
+            }),
+            Some(_) => return Err(ImportError::parsing("Unexpected Key-Derivation header")),
+            None => None,
+        };
+        let private_blob = lines.base64("Private-Lines")?;
+        let mac = decode_hex(lines.header("Private-MAC")?)?;
+
+        Ok(PpkFile {
+            version,
+            algorithm,
+            encryption,
+            comment,
+            public_blob,
+            key_derivation,
+            private_blob,
+            mac,
+        })
+    }
+
+    fn derive_keys(&self, passphrase: &[u8]) -> Result<FileKeys, ImportError> {
+        if self.version == 2 {
+            // The cipher key is two SHA-1 hashes of a sequence number and the passphrase, and the
+            // IV is all zeroes
+            let mut cipher_key = Vec::with_capacity(40);
+            for sequence in [0u32, 1] {
+                cipher_key.extend(
+                    Sha1::new()
+                        .chain_update(sequence.to_be_bytes())
+                        .chain_update(passphrase)
+                        .finalize(),
+                );
+            }
+            cipher_key.truncate(32);
+            let mac_key = Sha1::new()
+                .chain_update(V2_MAC_KEY_PREFIX)
+                .chain_update(passphrase)
+                .finalize()
+                .to_vec();
+            return Ok(FileKeys {
+                cipher_key,
+                iv: vec![0; 16],
+                mac_key,
+            });
+        }
+
+        let Some(key_derivation) = &self.key_derivation else {
+            // Unencrypted version 3 files use an empty MAC key
+            return Ok(FileKeys {
+                cipher_key: Vec::new(),
+                iv: Vec::new(),
+                mac_key: Vec::new(),
+            });
+        };
+
+        if key_derivation.memory > MAX_ARGON2_MEMORY_KIB
+            || key_derivation.passes > MAX_ARGON2_PASSES
+            || key_derivation.parallelism > MAX_ARGON2_PARALLELISM
+        {
+            return Err(ImportError::parsing(
+                "Argon2 parameters exceed the supported limits",
+            ));
+        }
+
+        let params = Params::new(
+            key_derivation.memory,
+            key_derivation.passes,
+            key_derivation.parallelism,
+            Some(80),
+        )
+        .map_err(|e| ImportError::parsing(format!("Invalid Argon2 parameters: {e}")))?;
+        let mut memory = vec![Block::default(); params.block_count()];
+        let mut output = vec![0; 80];
+        Argon2::new(key_derivation.algorithm, argon2::Version::V0x13, params)
+            .hash_password_into_with_memory(
+                passphrase,
+                &key_derivation.salt,
+                &mut output,
+                &mut memory,
+            )
+            .map_err(|e| ImportError::parsing(format!("Failed to derive key: {e}")))?;
+
+        let mac_key = output.split_off(48);
+        let iv = output.split_off(32);
+        Ok(FileKeys {
+            cipher_key: output,
+            iv,
+            mac_key,
+        })
+    }
+
+    /// The MAC covers the header fields and both key blobs, with the private blob decrypted.
+    fn verify_mac(&self, mac_key: &[u8]) -> bool {
+        let mut mac_data = Vec::new();
+        for field in [
+            self.algorithm.as_bytes(),
+            self.encryption.as_bytes(),
+            self.comment.as_bytes(),
+            &self.public_blob,
+            &self.private_blob,
+        ] {
+            mac_data.extend((field.len() as u32).to_be_bytes());
+            mac_data.extend(field);
+        }
+
+        if self.version == 2 {
+            Hmac::<Sha1>::new_from_slice(mac_key)
+                .map(|mac| mac.chain_update(&mac_data).verify_slice(&self.mac).is_ok())
+                .unwrap_or(false)
+        } else {
+            Hmac::<Sha256>::new_from_slice(mac_key)
+                .map(|mac| mac.chain_update(&mac_data).verify_slice(&self.mac).is_ok())
+                .unwrap_or(false)
+        }
+    }
+
+    fn keypair(&self) -> Result<KeypairData, ImportError> {
+        let public_key = KeyData::decode(&mut self.public_blob.as_slice())
+            .map_err(|e| ImportError::parsing(format!("Invalid public key: {e}")))?;
+        let private = &mut self.private_blob.as_slice();
+        let keypair = match &public_key {
+            KeyData::Ed25519(_) => {
+                // PuTTY stores the private key as a little-endian integer without trailing zeroes
+                let private_key = Vec::<u8>::decode(private).map_err(invalid_private_key)?;
+                let mut seed = [0; 32];
+                seed.get_mut(..private_key.len())
+                    .ok_or_else(|| ImportError::parsing("Invalid Ed25519 private key"))?
+                    .copy_from_slice(&private_key);
+                ed25519_keypair(&seed)
+            }
+            KeyData::Ecdsa(ecdsa) => {
+                let private_key = Mpint::decode(private).map_err(invalid_private_key)?;
+                let private_scalar = private_key
+                    .as_positive_bytes()
+                    .ok_or_else(|| ImportError::parsing("Invalid ECDSA private key"))?;
+                ecdsa_keypair(ecdsa.curve(), private_scalar)?
+            }
+            KeyData::Rsa(rsa) => {
+                // The last component, iqmp, is recomputed from the others
+                let mut next_component =
+                    || to_biguint(&Mpint::decode(&mut *private).map_err(invalid_private_key)?);
+                let d = next_component()?;
+                let p = next_component()?;
+                let q = next_component()?;
+                let private_key = rsa::RsaPrivateKey::from_components(
+                    to_biguint(&rsa.n)?,
+                    to_biguint(&rsa.e)?,
+                    d,
+                    vec![p, q],
+                )
+                .map_err(ImportError::parsing)?;
+                rsa_keypair(&private_key)?
+            }
+            public_key => {
+                return Err(ImportError::UnsupportedAlgorithm(
+                    public_key.algorithm().as_str().to_string(),
+                ))
+            }
+        };
+
+        if KeyData::try_from(&keypair).ok().as_ref() != Some(&public_key) {
+            return Err(ImportError::parsing(
+                "The public key does not match the private key",
+            ));
+        }
+        if public_key.algorithm().as_str() != self.algorithm {
+            return Err(ImportError::parsing(
+                "The key algorithm does not match the file header",
+            ));
+        }
+        Ok(keypair)
+    }
+}
+
+fn invalid_private_key(e: impl std::fmt::Display) -> ImportError {
+    ImportError::parsing(format!("Invalid private key: {e}"))
+}
+
+fn to_biguint(mpint: &Mpint) -> Result<rsa::BigUint, ImportError> {
+    mpint
+        .as_positive_bytes()
+        .map(rsa::BigUint::from_bytes_be)
+        .ok_or_else(|| ImportError::parsing("Invalid RSA private key"))
+}
+
+/// The lines of a PPK file, which are read in the order PuTTY writes them.
+struct Lines<'a>(std::iter::Peekable<std::str::Lines<'a>>);
+
+impl<'a> Lines<'a> {
+    fn next(&mut self) -> Result<&'a str, ImportError> {
+        self.0
+            .next()
+            .ok_or_else(|| ImportError::parsing("Truncated PuTTY key file"))
+    }
+
+    fn optional_header(&mut self, name: &str) -> Option<&'a str> {
+        let value = self
+            .0
+            .peek()?
+            .strip_prefix(name)?
+            .strip_prefix(": ")?
+            .trim_end();
+        self.0.next();
+        Some(value)
+    }
+
+    fn header(&mut self, name: &str) -> Result<&'a str, ImportError> {
+        self.optional_header(name)
+            .ok_or_else(|| ImportError::parsing(format!("Missing {name} header")))
+    }
+
+    fn number(&mut self, name: &str) -> Result<u32, ImportError> {
+        self.header(name)?
+            .parse()
+            .map_err(|_| ImportError::parsing(format!("Invalid {name} header")))
+    }
+
+    /// Reads a line count header followed by that many lines of base64.
+    fn base64(&mut self, name: &str) -> Result<Vec<u8>, ImportError> {
+        let mut base64 = String::new();
+        for _ in 0..self.number(name)? {
+            base64.push_str(self.next()?.trim());
+        }
+        base64_engine
+            .decode(base64)
+            .map_err(|e| ImportError::parsing(format!("Invalid base64 after {name}: {e}")))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::ssh_agent::importer::{
+        import_key,
+        tests::{assert_imports, ECDSA_PUBLIC_KEY, ED25519_PUBLIC_KEY, RSA_PUBLIC_KEY},
+        ImportError,
+    };
+
+    const PASSPHRASE: Option<&str> = Some("passphrase");
+
+    const ED25519_PPK_V2_ENCRYPTED: &str = "PuTTY-User-Key-File-2: ssh-ed25519
+Encryption: aes256-cbc
+Comment: ed25519-key
+Public-Lines: 2
+AAAAC3NzaC1lZDI1NTE5AAAAIGpNJwxJfXloHWrjH5jErkc9u9Pz9nhTnEfXoXTv
+wiy7
+Private-Lines: 1
+SHA+m+vzx7G9Cxn95gmljmavAktKIIkc0Hs9eL6AvT/52XKJS1aUGYWgjLAS6iIT
+Private-MAC: 3c81abbb2095ad549ef1ce1e26f56ac14d233065";
+    const ED25519_PPK_V3_ENCRYPTED: &str = "PuTTY-User-Key-File-3: ssh-ed25519
+Encryption: aes256-cbc
+Comment: ed25519-key
+Public-Lines: 2
+AAAAC3NzaC1lZDI1NTE5AAAAIGpNJwxJfXloHWrjH5jErkc9u9Pz9nhTnEfXoXTv
+wiy7
+Key-Derivation: Argon2id
+Argon2-Memory: 8192
+Argon2-Passes: 2
+Argon2-Parallelism: 1
+Argon2-Salt: 48cf74b8d6410b42fd6e343386e8c663
+Private-Lines: 1
+0oyhFzr6Ipfu/5y59kVyQgCSUavHlXEEUhpy0Iy+hj8qgheYWE4rmVktrT4JNFk3
+Private-MAC: 41f559d0e104bb92330340e328c6a349a70f68ceb2fbe1de9ebc62b8e521efaa";
+    const ED25519_PPK_V3: &str = "PuTTY-User-Key-File-3: ssh-ed25519
+Encryption: none
+Comment: ed25519-key
+Public-Lines: 2
+AAAAC3NzaC1lZDI1NTE5AAAAIGpNJwxJfXloHWrjH5jErkc9u9Pz9nhTnEfXoXTv
+wiy7
+Private-Lines: 1
+AAAAIHM6Scf0bmuUKydVJeOzMR+5hqMCs+D3m3tr0njwG4Vn
+Private-MAC: 0cdc2c702006bce0046fe98f25c6b2242b238ed2790533898e7cba4a590ee2e7";
+    const ECDSA_PPK_V2: &str = "PuTTY-User-Key-File-2: ecdsa-sha2-nistp256
+Encryption: none
+Comment: ecdsa-key
+Public-Lines: 3
+AAAAE2VjZHNhLXNoYTItbmlzdHAyNTYAAAAIbmlzdHAyNTYAAABBBIX+4ro8+7Si
+dQgkVZLSqkov/EQo/CGkU0bE0gUEZZjeAZuLCiGjTvfb7Gse1G54JwRYxKMAnpej
+MhVbmnngKRk=
+Private-Lines: 1
+AAAAIQC3wuEywzEtulbXO6cx8ejACE3ufRGPh9EYpPp8ARP4Lw==
+Private-MAC: 19c19cf9a3d06cee581ebeaedb73d92a2c005597";
+    const RSA_PPK_V3: &str = "PuTTY-User-Key-File-3: ssh-rsa
+Encryption: none
+Comment: rsa-key
+Public-Lines: 6
+AAAAB3NzaC1yc2EAAAADAQABAAABAQC5B7PmTcgD1MSpz5Vf4nk/I0SJhlIv70nY
+k3B84+JZwnhq+wmlGt/qqHQNxuACcdXCAGMaufCnAf5oC2AQefWt8WSWbHaanRJw
+daF06TnZyK7GM2gwFHw/E3C1nP5KdIrogMvD9ed+gJq09WxK9xmfvpr7aLq1AzCN
+GUB9notAjkZS2tqHdZXFjSwxStufQ/6W/ZCgesXYUvQzxZ3iS+AAimu/1o5v5esE
+sMrduXTXcKy6XpHdExoPdDlNCqfbYiP3jaiLZsm64Q9Lzc0LjR9sPmJqXG9aDHje
+LeGjTkk7dDEHm/wmtz62WDoGODwVUOebyWsvS4XazZ/ZEWl9Jrpp
+Private-Lines: 14
+AAABABvOIQY8bRDKUg10sO1aUr8etSd3hHVIn8/IhFB4TGURuNfWxsHFcp3IV7xU
+RQiJA8bmGP6Er7nhLlKDVxnSwg00J9oFDaO5c/JrcupnO/bS1hTZ18/V3D/8U2Uv
+ZPVaYx+Cb1yQ4QtdFyKXeQEg9fxP/+lE+T3Hb23cvBjEkQePt+XFdAAVRMrIZuoT
+C3rOW4m3oR3aEI3IxsUYB3YI3ZNJVCmVS3Mi+WxyBefXBK4+F5y+5IjQjLm0T7j2
+FmyY5/YXxTxd+hAfa/QS2d8XJxlqO7qbZFz79u1ZMozc/ssf8OWnAmEsV3ZmwrTe
+PIJ055zG2lklNMXioO3EaNjLv8sAAACBAPdaCpZQWG4R6CZM8/rQzePjQ8QTKoa4
+CP8hQHI0p5NYGPC2IGLeJavlP8vim4If9RQk1kBiLWw9xM7p0oYJk2gsdgvyYUG+
+G82DH6LCdxYQZJtLKdLoVWiqeAFdeDCKypGcA176LR25mLmDaSZ+FqPH8tjjDM6e
+pQspMA80ppUXAAAAgQC/f9edm2dsMa78jDwDG/P/EZXGo484Ep7dWzrnpp1vKSrH
+XvxC+oIKtVlbyEm/RKk7X+GS4AYeQv3DR4B2fVUEG64U0MXVz2y7q0KrUf0DOPVX
+K7KUUwisU14fpOT8oGxheYFZQqrj+ThYKk0u9/qQubcdn+rp8lALU1IPEJXcfwAA
+AIEAxlcTVhFv2UKIkLCaVkVRhJBXSVtBRnoeUxSoJVDzCkuc1uWGlwaEcey3ylOR
+KfF01/vTLOq3r0iq+v20SfK/EaQVd+lIfJvHHfXTUWglpQ/1MBdGO0lbJ5mQ/QeD
+OZTW0TucrC9NCfhb0DAP3nQBu6ZH+5Vvsml9aEj/u+mne0Y=
+Private-MAC: 8225ea54d270036b3f583484031e080f86ad1450359eb885df45b32bf10ee577";
+
+    #[test]
+    fn test_import_unencrypted() {
+        assert_imports(ED25519_PPK_V3, None, ED25519_PUBLIC_KEY);
+        assert_imports(ECDSA_PPK_V2, None, ECDSA_PUBLIC_KEY);
+        assert_imports(RSA_PPK_V3, None, RSA_PUBLIC_KEY);
+    }
+
+    #[test]
+    fn test_import_keeps_comment() {
+        let key = import_key(ED25519_PPK_V3.as_bytes(), None).unwrap();
+        assert!(key.public_key.ends_with(" ed25519-key"));
+    }
+
+    #[test]
+    fn test_import_crlf() {
+        assert_imports(
+            &ED25519_PPK_V3.replace('\n', "\r\n"),
+            None,
+            ED25519_PUBLIC_KEY,
+        );
+    }
+
+    #[test]
+    fn test_import_encrypted() {
+        for ppk in [ED25519_PPK_V2_ENCRYPTED, ED25519_PPK_V3_ENCRYPTED] {
+            assert_imports(ppk, PASSPHRASE, ED25519_PUBLIC_KEY);
+            assert_eq!(
+                import_key(ppk.as_bytes(), None),
+                Err(ImportError::PassphraseRequired)
+            );
+            assert_eq!(
+                import_key(ppk.as_bytes(), Some("wrong")),
+                Err(ImportError::WrongPassphrase)
+            );
+        }
+    }
+
+    #[test]
+    fn test_import_corrupted() {
+        let ppk = ED25519_PPK_V3.replace("Comment: ed25519-key", "Comment: other-key");
+        assert!(matches!(
+            import_key(ppk.as_bytes(), None),
+            Err(ImportError::Parsing(_))
+        ));
+    }
+
+    #[test]
+    fn test_import_rejects_excessive_argon2_parameters() {
+        for (line, excessive) in [
+            ("Argon2-Memory: 8192", "Argon2-Memory: 4294967295"),
+            ("Argon2-Passes: 2", "Argon2-Passes: 100000"),
+            ("Argon2-Parallelism: 1", "Argon2-Parallelism: 1000"),
+        ] {
+            let ppk = ED25519_PPK_V3_ENCRYPTED.replace(line, excessive);
+            assert!(matches!(
+                import_key(ppk.as_bytes(), PASSPHRASE),
+                Err(ImportError::Parsing(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn test_import_unsupported() {
+        let ppk = ED25519_PPK_V3.replace("Encryption: none", "Encryption: aes128-ctr");
+        assert_eq!(
+            import_key(ppk.as_bytes(), PASSPHRASE),
+            Err(ImportError::UnsupportedCipher("aes128-ctr".to_string()))
+        );
+
+        let ppk = ED25519_PPK_V3.replace("PuTTY-User-Key-File-3", "PuTTY-User-Key-File-1");
+        assert!(matches!(
+            import_key(ppk.as_bytes(), None),
+            Err(ImportError::Parsing(_))
+        ));
+    }
+}
@@ -0,0 +1,461 @@
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+    sync::atomic::Ordering,
+};
+
+use anyhow::anyhow;
+use base64::{engine::general_purpose::STANDARD, Engine as _};
+use bitwarden_russh::ssh_agent::SshKey;
+use hmac::{Hmac, Mac};
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use ssh_encoding::Decode;
+use tokio::time::Instant;
+use tracing::{error, info};
+
+use super::{
+    lifetime, peerinfo::models::PeerInfo, protocol, ApprovalOutcome, AuditEventKind, AuditRecord,
+    BitwardenDesktopAgent, SshAgentUIRequest,
+};
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Bound into the MAC, so that a MAC made with the same key for another purpose does not verify
+const CACHE_FORMAT: &str = "bitwarden-ssh-agent-key-cache-v1";
+
+/// Where the public keys of an account's vault keys are cached, so that they can be listed
+/// before the vault is unlocked. Only public keys and key names are cached.
+#[derive(Clone, PartialEq, Eq)]
+pub struct PublicKeyCache {
+    pub directory: PathBuf,
+    pub account_id: String,
+    /// Secret that the cache is authenticated with. It should be kept outside of the cache
+    /// directory, e.g. in the OS keychain.
+    pub integrity_key: Vec<u8>,
+}
+
+impl std::fmt::Debug for PublicKeyCache {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("PublicKeyCache")
+            .field("directory", &self.directory)
+            .field("account_id", &self.account_id)
+            .finish_non_exhaustive()
+    }
+}
+
+impl PublicKeyCache {
+    /// `~/.cache/bitwarden-ssh-agent` or the platform equivalent
+    pub fn default_directory() -> Option<PathBuf> {
+        dirs::cache_dir().map(|cache| cache.join("bitwarden-ssh-agent"))
+    }
+
+    /// A new random integrity key, base64 encoded for storing it in the keychain.
+    pub fn generate_integrity_key() -> String {
+        let mut key = [0u8; 32];
+        rand::rng().fill_bytes(&mut key);
+        STANDARD.encode(key)
+    }
+
+    /// Each account has a file of its own, named after a hash of the account id.
+    fn path(&self) -> PathBuf {
+        let account_hash = hex::encode(Sha256::digest(self.account_id.as_bytes()));
+        self.directory.join(format!("keys-{account_hash}.json"))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct CachedKey {
+    /// Base64 encoded public key blob, as listed to clients
+    public_key: String,
+    name: String,
+    cipher_id: String,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct CacheFile {
+    account_id: String,
+    keys: Vec<CachedKey>,
+    /// Base64 encoded HMAC-SHA256 of the account id and keys
+    mac: String,
+}
+
+pub(crate) struct CacheState {
+    cache: PublicKeyCache,
+    /// `None` until the cache was read or written, in which case list requests still prompt
+    keys: Option<Vec<CachedKey>>,
+}
+
+/// Listing cached keys while the vault is locked. Sign requests for them ask to unlock the
+/// vault first, so that its keys get loaded.
+impl BitwardenDesktopAgent {
+    /// Sets the cache of the active account, reading the keys cached by an earlier session.
+    /// `None` disables the cache but leaves the file in place.
+    pub fn set_public_key_cache(&self, cache: Option<PublicKeyCache>) {
+        let mut state = self
+            .public_key_cache
+            .write()
+            .expect("RwLock is not poisoned");
+        // the app sets the cache regularly, so an unchanged cache is not read again
+        if state.as_ref().map(|state| &state.cache) == cache.as_ref() {
+            return;
+        }
+
+        *state = cache.map(|cache| {
+            let keys = read_cache(&cache).unwrap_or_else(|e| {
+                error!(error = %e, path = ?cache.path(), "Ignoring SSH public key cache");
+                None
+            });
+            CacheState { cache, keys }
+        });
+    }
+
+    /// Writes the vault keys to the cache when they changed. Failures are logged, since they
+    /// must not keep keys from being loaded.
+    pub(crate) fn sync_public_key_cache(&self) {
+        let keys = self.keys_to_cache();
+        let mut state = self
+            .public_key_cache
+            .write()
+            .expect("RwLock is not poisoned");
+        let Some(state) = state.as_mut() else {
+            return;
+        };
+        if state.keys.as_ref() == Some(&keys) {
+            return;
+        }
+
+        match write_cache(&state.cache, &keys) {
+            Ok(()) => state.keys = Some(keys),
+            Err(e) => error!(error = %e, path = ?state.cache.path(), "Could not write key cache"),
+        }
+    }
+
+    fn keys_to_cache(&self) -> Vec<CachedKey> {
+        // the public key is taken from the keystore index, which still holds it for locked keys
+        let mut keys: Vec<CachedKey> = self
+            .keystore
+            .0
+            .read()
+            .expect("RwLock is not poisoned")
+            .iter()
+            .filter(|(_, key)| !key.is_ephemeral)
+            .map(|(public_key, key)| CachedKey {
+                public_key: STANDARD.encode(public_key),
+                name: key.name.clone(),
+                cipher_id: key.cipher_uuid.clone(),
+            })
+            .collect();
+        keys.sort_by(|a, b| (&a.name, &a.public_key).cmp(&(&b.name, &b.public_key)));
+        keys
+    }
+
+    fn cached_key(&self, public_key: &[u8]) -> Option<CachedKey> {
+        let public_key = STANDARD.encode(public_key);
+        self.public_key_cache
+            .read()
+            .expect("RwLock is not poisoned")
+            .as_ref()?
+            .keys
+            .as_ref()?
+            .iter()
+            .find(|key| key.public_key == public_key)
+            .cloned()
+    }
+
+    /// Answers list requests from the cache while listing would otherwise require an unlock.
+    /// Returns `None` if the request should be passed on to bitwarden-russh.
+    pub(crate) async fn list_cached_identities(&self, peer_info: &PeerInfo) -> Option<Vec<u8>> {
+        // the forwarding policy of cached keys is not known, so forwarded requests still prompt
+        if peer_info.is_forwarding() || !self.needs_unlock.load(Ordering::Relaxed) {
+            return None;
+        }
+
+        let mut identities: Vec<(Vec<u8>, String)> = self
+            .public_key_cache
+            .read()
+            .expect("RwLock is not poisoned")
+            .as_ref()?
+            .keys
+            .as_ref()?
+            .iter()
+            .filter_map(|key| Some((STANDARD.decode(&key.public_key).ok()?, key.name.clone())))
+            .collect();
+        // keys added with `ssh-add` are not cached
+        identities.extend(
+            self.keystore
+                .0
+                .read()
+                .expect("RwLock is not poisoned")
+                .values()
+                .filter(|key| key.is_ephemeral)
+                .map(|key| (key.public_key_bytes(), key.name.clone())),
+        );
+
+        self.record_audit(AuditRecord::new(
+            AuditEventKind::List,
+            peer_info,
+            ApprovalOutcome::AutoApproved,
+        ));
+        let response = protocol::identities_answer(&identities);
+        Some(self.add_upstream_identities(response, peer_info).await)
+    }
+
+    /// Asks to unlock the vault when a client wants to sign with a cached key whose private key
+    /// is not loaded. Returns `None` if the request should be passed on to bitwarden-russh.
+    pub(crate) async fn unlock_for_cached_key(
+        &self,
+        body: &[u8],
+        peer_info: &PeerInfo,
+    ) -> Option<Vec<u8>> {
+        let public_key = Vec::<u8>::decode(&mut &body[..]).ok()?;
+        let cached_key = self.cached_key(&public_key)?;
+        let now = Instant::now();
+        let is_loaded = self
+            .keystore
+            .0
+            .read()
+            .expect("RwLock is not poisoned")
+            .get(&public_key)
+            // an expired key stays unusable after unlocking
+            .is_some_and(|key| key.private_key.is_some() || lifetime::is_expired(key, now));
+        if is_loaded {
+            return None;
+        }
+
+        info!(cipher_id = %cached_key.cipher_id, "Sign request for a cached key, asking to unlock");
+        let request = SshAgentUIRequest {
+            request_id: self.get_request_id(),
+            cipher_id: Some(cached_key.cipher_id.clone()),
+            key_name: Some(cached_key.name.clone()),
+            process_name: peer_info.process_name().to_string(),
+            process_description: peer_info.description(),
+            is_list: true,
+            is_unlock: false,
+            namespace: None,
+            hash_algorithm: None,
+            digest_preview: None,
+            user_name: None,
+            is_arbitrary_data: false,
+            must_confirm: false,
+            host_names: Vec::new(),
+            is_forwarding: peer_info.is_forwarding(),
+        };
+        let accepted = self
+            .prompt_user(None, request, None, peer_info)
+            .await
+            .is_some_and(|response| response.accepted);
+        if accepted {
+            // the sign request itself is confirmed once the key is loaded
+            return None;
+        }
+
+        self.record_audit(AuditRecord {
+            cipher_id: Some(cached_key.cipher_id),
+            ..AuditRecord::new(AuditEventKind::Sign, peer_info, ApprovalOutcome::Denied)
+        });
+        Some(protocol::status_message(false))
+    }
+}
+
+/// Returns `None` if there is no cache yet.
+fn read_cache(cache: &PublicKeyCache) -> Result<Option<Vec<CachedKey>>, anyhow::Error> {
+    let contents = match fs::read(cache.path()) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+    let file: CacheFile = serde_json::from_slice(&contents)?;
+    mac(cache, &file.keys)?
+        .verify_slice(&STANDARD.decode(&file.mac)?)
+        .map_err(|_| anyhow!("Cache was modified or belongs to another account"))?;
+    info!(count = file.keys.len(), "Read SSH public key cache");
+    Ok(Some(file.keys))
+}
+
+fn write_cache(cache: &PublicKeyCache, keys: &[CachedKey]) -> Result<(), anyhow::Error> {
+    create_directory(&cache.directory)?;
+    let file = CacheFile {
+        account_id: cache.account_id.clone(),
+        keys: keys.to_vec(),
+        mac: STANDARD.encode(mac(cache, keys)?.finalize().into_bytes()),
+    };
+
+    // written next to the cache and renamed, so that a crash does not leave a partial file
+    let path = cache.path();
+    let temporary_path = path.with_extension("json.tmp");
+    fs::write(&temporary_path, serde_json::to_vec(&file)?)?;
+    fs::rename(&temporary_path, &path)?;
+    Ok(())
+}
+
+/// The account id is authenticated as well, so that the cache of one account is not accepted
+/// for another.
+fn mac(cache: &PublicKeyCache, keys: &[CachedKey]) -> Result<HmacSha256, anyhow::Error> {
+    let mut mac = HmacSha256::new_from_slice(&cache.integrity_key)
+        .map_err(|e| anyhow!("Invalid integrity key: {e}"))?;
+    mac.update(CACHE_FORMAT.as_bytes());
+    mac.update(&serde_json::to_vec(&(&cache.account_id, keys))?);
+    Ok(mac)
+}
+
+fn create_directory(directory: &Path) -> Result<(), io::Error> {
+    fs::create_dir_all(directory)?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(directory, fs::Permissions::from_mode(0o700))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use ssh_encoding::Encode;
+
+    use crate::ssh_agent::{
+        tests::{fake_ui, peer, test_agent, vault_key},
+        ApprovalPolicy,
+    };
+
+    use super::*;
+
+    fn key_cache(name: &str, account_id: &str) -> PublicKeyCache {
+        PublicKeyCache {
+            directory: std::env::temp_dir()
+                .join(format!("key-cache-{name}-{}", std::process::id())),
+            account_id: account_id.to_string(),
+            integrity_key: b"integrity key".to_vec(),
+        }
+    }
+
+    fn sign_request(public_key: &[u8]) -> Vec<u8> {
+        let mut body = Vec::new();
+        public_key.encode(&mut body).unwrap();
+        b"data".as_slice().encode(&mut body).unwrap();
+        0u32.encode(&mut body).unwrap();
+        body
+    }
+
+    #[tokio::test]
+    async fn test_cached_keys_are_listed_while_locked() {
+        let (mut agent, request_rx, response_tx) = test_agent();
+        fake_ui(request_rx, response_tx, false, None);
+        let cache = key_cache("list", "account");
+        let _ = fs::remove_dir_all(&cache.directory);
+
+        // nothing cached yet, so listing still prompts
+        agent.set_public_key_cache(Some(cache.clone()));
+        assert_eq!(agent.list_cached_identities(&peer(1)).await, None);
+
+        agent
+            .set_keys(vec![vault_key(ApprovalPolicy::NeverAsk)])
+            .unwrap();
+        let public_key = agent
+            .keystore
+            .0
+            .read()
+            .unwrap()
+            .keys()
+            .next()
+            .cloned()
+            .unwrap();
+        let contents = fs::read_to_string(cache.path()).unwrap();
+        assert!(!contents.contains("PRIVATE"));
+
+        // a new session reads the cache before the vault is unlocked
+        let (agent, request_rx, response_tx) = test_agent();
+        fake_ui(request_rx, response_tx, false, None);
+        agent.set_public_key_cache(Some(cache.clone()));
+        let response = agent.list_cached_identities(&peer(1)).await.unwrap();
+        assert_eq!(
+            protocol::parse_identities_answer(&response).unwrap(),
+            vec![(public_key, "test key".to_string())]
+        );
+        fs::remove_dir_all(cache.directory).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_modified_or_foreign_cache_is_rejected() {
+        let (mut agent, _request_rx, _response_tx) = test_agent();
+        let cache = key_cache("integrity", "account");
+        let _ = fs::remove_dir_all(&cache.directory);
+        agent.set_public_key_cache(Some(cache.clone()));
+        agent
+            .set_keys(vec![vault_key(ApprovalPolicy::NeverAsk)])
+            .unwrap();
+        assert!(read_cache(&cache).unwrap().is_some());
+
+        let other_account = PublicKeyCache {
+            account_id: "other".to_string(),
+            ..cache.clone()
+        };
+        fs::copy(cache.path(), other_account.path()).unwrap();
+        assert!(read_cache(&other_account).is_err());
+
+        let contents = fs::read_to_string(cache.path()).unwrap();
+        fs::write(
+            cache.path(),
+            contents.replace("\"test key\"", "\"renamed\""),
+        )
+        .unwrap();
+        assert!(read_cache(&cache).is_err());
+        fs::remove_dir_all(cache.directory).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_sign_with_cached_key_asks_to_unlock() {
+        let (mut agent, request_rx, response_tx) = test_agent();
+        let prompts = fake_ui(request_rx, response_tx, true, None);
+        let cache = key_cache("sign", "account");
+        let _ = fs::remove_dir_all(&cache.directory);
+        agent.set_public_key_cache(Some(cache.clone()));
+        agent
+            .set_keys(vec![vault_key(ApprovalPolicy::NeverAsk)])
+            .unwrap();
+        let public_key = agent
+            .keystore
+            .0
+            .read()
+            .unwrap()
+            .keys()
+            .next()
+            .cloned()
+            .unwrap();
+
+        // the loaded key is signed with right away
+        let body = sign_request(&public_key);
+        assert_eq!(agent.unlock_for_cached_key(&body, &peer(1)).await, None);
+        assert_eq!(prompts.load(Ordering::Relaxed), 0);
+
+        agent.lock().unwrap();
+        assert_eq!(agent.unlock_for_cached_key(&body, &peer(1)).await, None);
+        assert_eq!(prompts.load(Ordering::Relaxed), 1);
+
+        // keys that are not cached are left to bitwarden-russh and the upstream agent
+        let body = sign_request(&[1, 2, 3]);
+        assert_eq!(agent.unlock_for_cached_key(&body, &peer(1)).await, None);
+        assert_eq!(prompts.load(Ordering::Relaxed), 1);
+        fs::remove_dir_all(cache.directory).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_clear_keys_disables_cache() {
+        let (mut agent, _request_rx, _response_tx) = test_agent();
+        let cache = key_cache("clear", "account");
+        let _ = fs::remove_dir_all(&cache.directory);
+        agent.set_public_key_cache(Some(cache.clone()));
+        agent
+            .set_keys(vec![vault_key(ApprovalPolicy::NeverAsk)])
+            .unwrap();
+
+        agent.clear_keys().unwrap();
+        assert_eq!(agent.list_cached_identities(&peer(1)).await, None);
+        // the cache of the previous account is kept for its next session
+        assert!(read_cache(&cache).unwrap().is_some());
+        fs::remove_dir_all(cache.directory).unwrap();
+    }
+}
@@ -0,0 +1,138 @@
+use anyhow::anyhow;
+use ssh_key::{
+    private::{KeypairData, RsaKeypair},
+    rand_core::OsRng,
+    Algorithm, EcdsaCurve, HashAlg, LineEnding, PrivateKey,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyAlgorithm {
+    Ed25519,
+    Rsa2048,
+    Rsa3072,
+    Rsa4096,
+    EcdsaP256,
+    EcdsaP384,
+    EcdsaP521,
+}
+
+/// An SSH key in the formats stored in the vault.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SshKeyPair {
+    /// OpenSSH private key
+    pub private_key: String,
+    /// Public key in `authorized_keys` format
+    pub public_key: String,
+    /// SHA256 fingerprint of the public key
+    pub key_fingerprint: String,
+}
+
+impl TryFrom<&PrivateKey> for SshKeyPair {
+    type Error = anyhow::Error;
+
+    fn try_from(private_key: &PrivateKey) -> Result<Self, Self::Error> {
+        Ok(SshKeyPair {
+            private_key: private_key
+                .to_openssh(LineEnding::LF)
+                .map_err(|e| anyhow!("Failed to encode private key: {e}"))?
+                .to_string(),
+            public_key: private_key
+                .public_key()
+                .to_openssh()
+                .map_err(|e| anyhow!("Failed to encode public key: {e}"))?,
+            key_fingerprint: private_key.fingerprint(HashAlg::Sha256).to_string(),
+        })
+    }
+}
+
+/// Generates a new, unencrypted key.
+pub fn generate_keypair(
+    algorithm: KeyAlgorithm,
+    comment: Option<&str>,
+) -> Result<SshKeyPair, anyhow::Error> {
+    let mut private_key = match algorithm {
+        KeyAlgorithm::Ed25519 => PrivateKey::random(&mut OsRng, Algorithm::Ed25519),
+        KeyAlgorithm::Rsa2048 => random_rsa(2048),
+        KeyAlgorithm::Rsa3072 => random_rsa(3072),
+        KeyAlgorithm::Rsa4096 => random_rsa(4096),
+        KeyAlgorithm::EcdsaP256 => PrivateKey::random(
+            &mut OsRng,
+            Algorithm::Ecdsa {
+                curve: EcdsaCurve::NistP256,
+            },
+        ),
+        KeyAlgorithm::EcdsaP384 => PrivateKey::random(
+            &mut OsRng,
+            Algorithm::Ecdsa {
+                curve: EcdsaCurve::NistP384,
+            },
+        ),
+        KeyAlgorithm::EcdsaP521 => PrivateKey::random(
+            &mut OsRng,
+            Algorithm::Ecdsa {
+                curve: EcdsaCurve::NistP521,
+            },
+        ),
+    }
+    .map_err(|e| anyhow!("Failed to generate {algorithm:?} key: {e}"))?;
+
+    if let Some(comment) = comment {
+        private_key.set_comment(comment);
+    }
+    SshKeyPair::try_from(&private_key)
+}
+
+/// `PrivateKey::random` only creates RSA keys of the default size.
+fn random_rsa(bits: usize) -> Result<PrivateKey, ssh_key::Error> {
+    let keypair = RsaKeypair::random(&mut OsRng, bits)?;
+    PrivateKey::new(KeypairData::from(keypair), "")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_valid(key: &SshKeyPair, algorithm: Algorithm) {
+        let private_key = PrivateKey::from_openssh(&key.private_key).unwrap();
+        assert_eq!(private_key.algorithm(), algorithm);
+        assert!(!private_key.is_encrypted());
+
+        let public_key = ssh_key::PublicKey::from_openssh(&key.public_key).unwrap();
+        assert_eq!(public_key.key_data(), private_key.public_key().key_data());
+        assert_eq!(
+            key.key_fingerprint,
+            public_key.fingerprint(HashAlg::Sha256).to_string()
+        );
+        assert!(key.key_fingerprint.starts_with("SHA256:"));
+    }
+
+    #[test]
+    fn test_generate_ed25519() {
+        let key = generate_keypair(KeyAlgorithm::Ed25519, Some("user@host")).unwrap();
+
+        assert_valid(&key, Algorithm::Ed25519);
+        assert!(key.public_key.ends_with(" user@host"));
+    }
+
+    #[test]
+    fn test_generate_ecdsa() {
+        for (algorithm, curve) in [
+            (KeyAlgorithm::EcdsaP256, EcdsaCurve::NistP256),
+            (KeyAlgorithm::EcdsaP384, EcdsaCurve::NistP384),
+            (KeyAlgorithm::EcdsaP521, EcdsaCurve::NistP521),
+        ] {
+            let key = generate_keypair(algorithm, None).unwrap();
+            assert_valid(&key, Algorithm::Ecdsa { curve });
+        }
+    }
+
+    #[test]
+    fn test_generate_rsa() {
+        let key = generate_keypair(KeyAlgorithm::Rsa2048, None).unwrap();
+
+        assert_valid(&key, Algorithm::Rsa { hash: None });
+        let private_key = PrivateKey::from_openssh(&key.private_key).unwrap();
+        let rsa = private_key.key_data().rsa().unwrap();
+        assert_eq!(rsa.public.n.as_positive_bytes().unwrap().len() * 8, 2048);
+    }
+}
@@ -0,0 +1,434 @@
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::SystemTime,
+};
+
+use base64::{engine::general_purpose::STANDARD, Engine as _};
+use hmac::{Hmac, Mac};
+use sha1::Sha1;
+use tracing::{debug, error};
+
+/// Hashed host names start with this magic, followed by the base64 salt and hash.
+const HASH_MAGIC: &str = "|1|";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Marker {
+    /// The key is a certificate authority for host certificates of the matching hosts
+    CertAuthority,
+    /// The key must never be accepted
+    Revoked,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Hosts {
+    /// Comma separated host names and patterns, which may be negated with `!`
+    Patterns(Vec<String>),
+    /// A single host name, hashed with HMAC-SHA1
+    Hashed { salt: Vec<u8>, hash: Vec<u8> },
+}
+
+impl Hosts {
+    fn parse(hosts: &str) -> Option<Self> {
+        match hosts.strip_prefix(HASH_MAGIC) {
+            Some(hashed) => {
+                let (salt, hash) = hashed.split_once('|')?;
+                Some(Hosts::Hashed {
+                    salt: STANDARD.decode(salt).ok()?,
+                    hash: STANDARD.decode(hash).ok()?,
+                })
+            }
+            None => Some(Hosts::Patterns(
+                hosts
+                    .split(',')
+                    .filter(|host| !host.is_empty())
+                    .map(str::to_string)
+                    .collect(),
+            )),
+        }
+    }
+
+    /// Returns the host names to show for this entry, resolving hashed entries from
+    /// `candidates` where possible.
+    fn names(&self, candidates: &[String]) -> Option<Vec<String>> {
+        match self {
+            Hosts::Patterns(patterns) => Some(
+                patterns
+                    .iter()
+                    .filter(|pattern| !pattern.starts_with('!'))
+                    .cloned()
+                    .collect(),
+            ),
+            Hosts::Hashed { salt, hash } => candidates
+                .iter()
+                .find(|candidate| hash_matches(salt, hash, candidate))
+                .map(|candidate| vec![candidate.clone()]),
+        }
+    }
+}
+
+fn hash_matches(salt: &[u8], hash: &[u8], host: &str) -> bool {
+    let Ok(mut mac) = Hmac::<Sha1>::new_from_slice(salt) else {
+        return false;
+    };
+    mac.update(host.as_bytes());
+    mac.verify_slice(hash).is_ok()
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Entry {
+    marker: Option<Marker>,
+    hosts: Hosts,
+    /// Public key in the SSH wire format
+    key: Vec<u8>,
+}
+
+impl Entry {
+    /// Parses a line in the format described in sshd(8), "SSH_KNOWN_HOSTS FILE FORMAT".
+    /// Comments, empty lines and malformed lines yield `None`.
+    fn parse(line: &str) -> Option<Self> {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            return None;
+        }
+
+        let mut fields = line.split_whitespace().peekable();
+        let marker = match fields.peek() {
+            Some(&"@cert-authority") => Some(Marker::CertAuthority),
+            Some(&"@revoked") => Some(Marker::Revoked),
+            Some(marker) if marker.starts_with('@') => return None,
+            _ => None,
+        };
+        if marker.is_some() {
+            fields.next();
+        }
+
+        let hosts = Hosts::parse(fields.next()?)?;
+        let _key_type = fields.next()?;
+        let key = STANDARD.decode(fields.next()?).ok()?;
+        Some(Entry { marker, hosts, key })
+    }
+}
+
+/// What the known_hosts files say about a host key.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub(crate) struct HostKeyInfo {
+    /// Host names and patterns that the key is known for
+    pub host_names: Vec<String>,
+    /// Set if the key, or the authority that signed it, is marked `@revoked`
+    pub is_revoked: bool,
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct KnownHosts {
+    entries: Vec<Entry>,
+}
+
+/// The user's and the system wide known_hosts files, in the locations used by OpenSSH.
+pub(crate) fn default_paths() -> Vec<PathBuf> {
+    let mut paths = Vec::new();
+    if let Some(home) = dirs::home_dir() {
+        paths.push(home.join(".ssh").join("known_hosts"));
+        paths.push(home.join(".ssh").join("known_hosts2"));
+    }
+    #[cfg(unix)]
+    {
+        paths.push(PathBuf::from("/etc/ssh/ssh_known_hosts"));
+        paths.push(PathBuf::from("/etc/ssh/ssh_known_hosts2"));
+    }
+    #[cfg(windows)]
+    if let Some(program_data) = std::env::var_os("PROGRAMDATA") {
+        let ssh_dir = PathBuf::from(program_data).join("ssh");
+        paths.push(ssh_dir.join("ssh_known_hosts"));
+        paths.push(ssh_dir.join("ssh_known_hosts2"));
+    }
+    paths
+}
+
+impl KnownHosts {
+    /// Reads the given files, skipping files that do not exist.
+    pub(crate) fn load(paths: &[PathBuf]) -> Self {
+        let mut known_hosts = Self::default();
+        for path in paths {
+            known_hosts.add_file(path);
+        }
+        known_hosts
+    }
+
+    fn add_file(&mut self, path: &Path) {
+        match std::fs::read_to_string(path) {
+            Ok(contents) => self
+                .entries
+                .extend(contents.lines().filter_map(Entry::parse)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                debug!(?path, "known_hosts file does not exist");
+            }
+            Err(e) => error!(error = %e, ?path, "Could not read known_hosts file"),
+        }
+    }
+
+    /// Looks up a host key in the SSH wire format, which may be a host certificate. Hashed entries
+    /// can only be resolved to a name that is one of `candidates`.
+    pub(crate) fn lookup(&self, host_key: &[u8], candidates: &[String]) -> HostKeyInfo {
+        let certificate = ssh_key::Certificate::from_bytes(host_key).ok();
+        let certified_key = certificate
+            .as_ref()
+            .and_then(|certificate| key_bytes(certificate.public_key()));
+        let authority_key = certificate
+            .as_ref()
+            .and_then(|certificate| key_bytes(certificate.signature_key()));
+
+        let is_host_key = |key: &[u8]| key == host_key || certified_key.as_deref() == Some(key);
+        let is_authority_key = |key: &[u8]| authority_key.as_deref() == Some(key);
+
+        let mut info = HostKeyInfo::default();
+        for entry in &self.entries {
+            let matches = match entry.marker {
+                Some(Marker::Revoked) => {
+                    if is_host_key(&entry.key) || is_authority_key(&entry.key) {
+                        info.is_revoked = true;
+                    }
+                    false
+                }
+                Some(Marker::CertAuthority) => is_authority_key(&entry.key),
+                None => is_host_key(&entry.key),
+            };
+            if !matches {
+                continue;
+            }
+            for name in entry.hosts.names(candidates).unwrap_or_default() {
+                if !info.host_names.contains(&name) {
+                    info.host_names.push(name);
+                }
+            }
+        }
+        info
+    }
+}
+
+/// Known_hosts files that are parsed once and read again when one of them changes.
+#[derive(Debug)]
+pub(crate) struct KnownHostsCache {
+    paths: Vec<PathBuf>,
+    /// Modification times of `paths` when they were last read, `None` before the first read
+    modified: Option<Vec<Option<SystemTime>>>,
+    known_hosts: Arc<KnownHosts>,
+}
+
+impl KnownHostsCache {
+    pub(crate) fn new(paths: Vec<PathBuf>) -> Self {
+        Self {
+            paths,
+            modified: None,
+            known_hosts: Arc::new(KnownHosts::default()),
+        }
+    }
+
+    /// Returns the parsed files, reading them again if any of them was created, modified or
+    /// removed since the last call. This accesses the file system and should not be called on
+    /// the async runtime.
+    pub(crate) fn get(&mut self) -> Arc<KnownHosts> {
+        let modified: Vec<_> = self
+            .paths
+            .iter()
+            .map(|path| std::fs::metadata(path).and_then(|m| m.modified()).ok())
+            .collect();
+        if self.modified.as_ref() != Some(&modified) {
+            debug!("Reading known_hosts files");
+            self.known_hosts = Arc::new(KnownHosts::load(&self.paths));
+            self.modified = Some(modified);
+        }
+        self.known_hosts.clone()
+    }
+}
+
+fn key_bytes(key_data: &ssh_key::public::KeyData) -> Option<Vec<u8>> {
+    ssh_key::PublicKey::from(key_data.clone()).to_bytes().ok()
+}
+
+/// Host names that a client may have connected to, taken from its command line, e.g. `host` for
+/// `ssh -A user@host`. They are used to resolve hashed known_hosts entries.
+pub(crate) fn candidate_host_names(cmdline: &[String]) -> Vec<String> {
+    cmdline
+        .iter()
+        .skip(1)
+        .filter(|arg| !arg.starts_with('-') && !arg.is_empty())
+        .map(|arg| {
+            let host = arg.rsplit_once('@').map_or(arg.as_str(), |(_, host)| host);
+            host.strip_prefix("ssh://").unwrap_or(host).to_string()
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use ssh_key::{rand_core::OsRng, Algorithm, PrivateKey};
+
+    use super::*;
+    use crate::ssh_agent::tests::rand_file_in_temp;
+
+    fn random_key() -> ssh_key::PublicKey {
+        PrivateKey::random(&mut OsRng, Algorithm::Ed25519)
+            .unwrap()
+            .public_key()
+            .clone()
+    }
+
+    fn hashed(host: &str) -> String {
+        let salt = [42u8; 20];
+        let mut mac = Hmac::<Sha1>::new_from_slice(&salt).unwrap();
+        mac.update(host.as_bytes());
+        format!(
+            "|1|{}|{}",
+            STANDARD.encode(salt),
+            STANDARD.encode(mac.finalize().into_bytes())
+        )
+    }
+
+    fn load_lines(lines: &[String]) -> KnownHosts {
+        let path = rand_file_in_temp();
+        std::fs::write(&path, lines.join("\n")).unwrap();
+        let known_hosts = KnownHosts::load(&[path.clone(), rand_file_in_temp()]);
+        std::fs::remove_file(&path).unwrap();
+        known_hosts
+    }
+
+    fn host_certificate(host_key: &ssh_key::PublicKey, ca_key: &PrivateKey) -> Vec<u8> {
+        let mut builder = ssh_key::certificate::Builder::new_with_random_nonce(
+            &mut OsRng,
+            host_key.key_data().clone(),
+            0,
+            4_102_444_800,
+        )
+        .unwrap();
+        builder
+            .cert_type(ssh_key::certificate::CertType::Host)
+            .unwrap();
+        builder.key_id("host").unwrap();
+        builder.all_principals_valid().unwrap();
+        builder.sign(ca_key).unwrap().to_bytes().unwrap()
+    }
+
+    #[test]
+    fn test_lookup_plain_entries() {
+        let key = random_key();
+        let other_key = random_key();
+        let known_hosts = load_lines(&[
+            "# comment".to_string(),
+            format!(
+                "example.com,192.0.2.1,!bad.example.com {}",
+                key.to_openssh().unwrap()
+            ),
+            format!("[example.org]:2222 {}", key.to_openssh().unwrap()),
+            format!("other.example.com {}", other_key.to_openssh().unwrap()),
+            "malformed line".to_string(),
+        ]);
+
+        let info = known_hosts.lookup(&key.to_bytes().unwrap(), &[]);
+
+        assert_eq!(
+            info.host_names,
+            vec!["example.com", "192.0.2.1", "[example.org]:2222"]
+        );
+        assert!(!info.is_revoked);
+    }
+
+    #[test]
+    fn test_lookup_hashed_entry() {
+        let key = random_key();
+        let known_hosts = load_lines(&[format!(
+            "{} {}",
+            hashed("example.com"),
+            key.to_openssh().unwrap()
+        )]);
+        let key_bytes = key.to_bytes().unwrap();
+
+        let candidates = candidate_host_names(&[
+            "ssh".to_string(),
+            "-A".to_string(),
+            "deploy@example.com".to_string(),
+        ]);
+        assert_eq!(
+            known_hosts.lookup(&key_bytes, &candidates).host_names,
+            vec!["example.com"]
+        );
+        assert!(known_hosts
+            .lookup(&key_bytes, &["example.org".to_string()])
+            .host_names
+            .is_empty());
+    }
+
+    #[test]
+    fn test_revoked_key() {
+        let key = random_key();
+        let known_hosts = load_lines(&[
+            format!("example.com {}", key.to_openssh().unwrap()),
+            format!("@revoked * {}", key.to_openssh().unwrap()),
+        ]);
+
+        let info = known_hosts.lookup(&key.to_bytes().unwrap(), &[]);
+
+        assert!(info.is_revoked);
+        assert_eq!(info.host_names, vec!["example.com"]);
+    }
+
+    #[test]
+    fn test_cert_authority() {
+        let ca_key = PrivateKey::random(&mut OsRng, Algorithm::Ed25519).unwrap();
+        let host_key = random_key();
+        let certificate = host_certificate(&host_key, &ca_key);
+        let ca_line = ca_key.public_key().to_openssh().unwrap();
+
+        let known_hosts = load_lines(&[format!("@cert-authority *.example.com {ca_line}")]);
+        let info = known_hosts.lookup(&certificate, &[]);
+        assert_eq!(info.host_names, vec!["*.example.com"]);
+        assert!(!info.is_revoked);
+
+        // the authority only vouches for certificates, not for its own key
+        assert!(known_hosts
+            .lookup(&ca_key.public_key().to_bytes().unwrap(), &[])
+            .host_names
+            .is_empty());
+
+        let known_hosts = load_lines(&[
+            format!("@cert-authority *.example.com {ca_line}"),
+            format!("@revoked * {ca_line}"),
+        ]);
+        assert!(known_hosts.lookup(&certificate, &[]).is_revoked);
+    }
+
+    #[test]
+    fn test_unknown_marker_is_ignored() {
+        let key = random_key();
+        let known_hosts = load_lines(&[format!(
+            "@unknown example.com {}",
+            key.to_openssh().unwrap()
+        )]);
+
+        assert_eq!(
+            known_hosts.lookup(&key.to_bytes().unwrap(), &[]),
+            HostKeyInfo::default()
+        );
+    }
+
+    #[test]
+    fn test_cache_reloads_changed_files() {
+        let key = random_key().to_openssh().unwrap();
+        let path = rand_file_in_temp();
+        let mut cache = KnownHostsCache::new(vec![path.clone()]);
+        assert!(cache.get().entries.is_empty());
+
+        std::fs::write(&path, format!("example.com {key}")).unwrap();
+        let known_hosts = cache.get();
+        assert_eq!(known_hosts.entries.len(), 1);
+        assert!(Arc::ptr_eq(&known_hosts, &cache.get()));
+
+        let file = std::fs::File::options().append(true).open(&path).unwrap();
+        std::fs::write(&path, format!("example.com {key}\nexample.org {key}")).unwrap();
+        file.set_modified(SystemTime::UNIX_EPOCH).unwrap();
+        assert_eq!(cache.get().entries.len(), 2);
+
+        std::fs::remove_file(&path).unwrap();
+        assert!(cache.get().entries.is_empty());
+    }
+}
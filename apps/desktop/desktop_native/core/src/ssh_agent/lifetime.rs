@@ -0,0 +1,306 @@
+use std::{collections::HashMap, time::Duration};
+
+use tokio::time::Instant;
+use tracing::info;
+
+use super::{unix_now, BitwardenDesktopAgent, BitwardenSshKey};
+
+/// How often the background task looks for keys whose lifetime has passed.
+const EXPIRY_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Limits how long a vault key can be used. Once the lifetime has passed, the private key is
+/// dropped until the vault is locked and unlocked again.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KeyLifetime {
+    pub duration: Duration,
+    pub start: LifetimeStart,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LifetimeStart {
+    /// The lifetime starts when the key is loaded with `set_keys`
+    #[default]
+    Loaded,
+    /// The lifetime starts with the first approved signature
+    FirstUse,
+}
+
+/// A key currently held by the agent, with its private key available.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LoadedKey {
+    pub name: String,
+    /// Not set for keys added with `ssh-add`
+    pub cipher_id: Option<String>,
+    pub fingerprint: Option<String>,
+    pub is_ephemeral: bool,
+    /// Unix time in seconds
+    pub expires_at: Option<u64>,
+}
+
+/// The lifetime state of the vault keys before `set_keys` replaces them, by cipher id.
+pub(crate) type PreviousExpiry = HashMap<String, (Option<KeyLifetime>, Option<Instant>)>;
+
+/// Returns when a key loaded by `set_keys` expires. The vault reloads its keys regularly, so a
+/// key keeps the expiry it had before unless its lifetime was changed.
+pub(crate) fn expiry_after_load(
+    previous: &PreviousExpiry,
+    cipher_id: &str,
+    lifetime: Option<KeyLifetime>,
+    now: Instant,
+) -> Option<Instant> {
+    let lifetime = lifetime?;
+    match previous.get(cipher_id) {
+        Some((previous_lifetime, Some(expires_at))) if *previous_lifetime == Some(lifetime) => {
+            Some(*expires_at)
+        }
+        _ => match lifetime.start {
+            LifetimeStart::Loaded => Some(now + lifetime.duration),
+            LifetimeStart::FirstUse => None,
+        },
+    }
+}
+
+impl BitwardenDesktopAgent {
+    /// Drops the private keys whose lifetime has passed until the agent is stopped.
+    pub(crate) fn spawn_expiry_task(&self) {
+        let agent = self.clone();
+        tokio::spawn(async move {
+            loop {
+                tokio::select! {
+                    _ = agent.cancellation_token.cancelled() => break,
+                    _ = tokio::time::sleep(EXPIRY_CHECK_INTERVAL) => agent.expire_keys(),
+                }
+            }
+        });
+    }
+
+    /// Removes ephemeral keys and drops the private keys of vault keys whose lifetime has passed.
+    pub(crate) fn expire_keys(&self) {
+        self.remove_expired_keys();
+
+        let now = Instant::now();
+        self.keystore
+            .0
+            .write()
+            .expect("RwLock is not poisoned")
+            .values_mut()
+            .filter(|key| key.private_key.is_some() && is_expired(key, now))
+            .for_each(|key| {
+                info!(cipher_id = %key.cipher_uuid, "Key lifetime has expired, dropping private key");
+                key.private_key = None;
+            });
+    }
+
+    /// Starts the lifetime of keys that expire after their first use.
+    pub(crate) fn start_lifetime_on_first_use(&self, cipher_id: &str) {
+        let now = Instant::now();
+        self.keystore
+            .0
+            .write()
+            .expect("RwLock is not poisoned")
+            .values_mut()
+            .filter(|key| !key.is_ephemeral && key.cipher_uuid == cipher_id)
+            .for_each(|key| {
+                if let Some(KeyLifetime {
+                    duration,
+                    start: LifetimeStart::FirstUse,
+                }) = key.lifetime
+                {
+                    key.expires_at.get_or_insert(now + duration);
+                }
+            });
+    }
+
+    pub(crate) fn previous_expiry(&self) -> PreviousExpiry {
+        self.keystore
+            .0
+            .read()
+            .expect("RwLock is not poisoned")
+            .values()
+            .filter(|key| !key.is_ephemeral)
+            .map(|key| (key.cipher_uuid.clone(), (key.lifetime, key.expires_at)))
+            .collect()
+    }
+
+    /// Lifetimes start over once the vault is unlocked again.
+    pub(crate) fn reset_key_lifetimes(&self) {
+        self.keystore
+            .0
+            .write()
+            .expect("RwLock is not poisoned")
+            .values_mut()
+            .for_each(|key| key.expires_at = None);
+    }
+
+    /// Returns the keys whose private key is available, and when they expire.
+    pub fn loaded_keys(&self) -> Vec<LoadedKey> {
+        let now = Instant::now();
+        let unix_time = unix_now();
+        let mut keys: Vec<LoadedKey> = self
+            .keystore
+            .0
+            .read()
+            .expect("RwLock is not poisoned")
+            .values()
+            .filter(|key| key.private_key.is_some() && !is_expired(key, now))
+            .map(|key| LoadedKey {
+                name: key.name.clone(),
+                cipher_id: (!key.is_ephemeral).then(|| key.cipher_uuid.clone()),
+                fingerprint: key.fingerprint(),
+                is_ephemeral: key.is_ephemeral,
+                expires_at: key.expires_at.map(|expires_at| {
+                    unix_time + expires_at.saturating_duration_since(now).as_secs()
+                }),
+            })
+            .collect();
+        keys.sort_by(|a, b| a.name.cmp(&b.name));
+        keys
+    }
+}
+
+pub(super) fn is_expired(key: &BitwardenSshKey, now: Instant) -> bool {
+    key.expires_at.is_some_and(|expires_at| expires_at <= now)
+}
+
+#[cfg(test)]
+mod tests {
+    use bitwarden_russh::ssh_agent::Agent;
+
+    use crate::ssh_agent::{
+        tests::{fake_ui, peer, sign_data, test_agent, vault_key},
+        ApprovalPolicy, VaultSshKey,
+    };
+
+    use super::*;
+
+    fn key_with_lifetime(start: LifetimeStart, duration: Duration) -> VaultSshKey {
+        VaultSshKey {
+            lifetime: Some(KeyLifetime { duration, start }),
+            ..vault_key(ApprovalPolicy::NeverAsk)
+        }
+    }
+
+    const SHORT_LIFETIME: Duration = Duration::from_millis(10);
+
+    async fn wait_for_expiry() {
+        tokio::time::advance(SHORT_LIFETIME * 2).await;
+    }
+
+    fn private_key_loaded(agent: &BitwardenDesktopAgent) -> bool {
+        agent
+            .keystore
+            .0
+            .read()
+            .unwrap()
+            .values()
+            .all(|key| key.private_key.is_some())
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_key_expires_after_load() {
+        let (mut agent, _request_rx, _response_tx) = test_agent();
+        agent
+            .set_keys(vec![key_with_lifetime(
+                LifetimeStart::Loaded,
+                SHORT_LIFETIME,
+            )])
+            .unwrap();
+        assert!(private_key_loaded(&agent));
+
+        wait_for_expiry().await;
+        agent.expire_keys();
+        assert!(!private_key_loaded(&agent));
+        assert!(agent.loaded_keys().is_empty());
+
+        // reloading the same key does not restart its lifetime
+        agent
+            .set_keys(vec![key_with_lifetime(
+                LifetimeStart::Loaded,
+                SHORT_LIFETIME,
+            )])
+            .unwrap();
+        assert!(!private_key_loaded(&agent));
+
+        agent.lock().unwrap();
+        agent
+            .set_keys(vec![key_with_lifetime(
+                LifetimeStart::Loaded,
+                SHORT_LIFETIME,
+            )])
+            .unwrap();
+        assert!(private_key_loaded(&agent));
+    }
+
+    #[tokio::test]
+    async fn test_key_expires_after_first_use() {
+        let (mut agent, request_rx, response_tx) = test_agent();
+        fake_ui(request_rx, response_tx, true, None);
+        agent
+            .set_keys(vec![key_with_lifetime(
+                LifetimeStart::FirstUse,
+                Duration::from_secs(3600),
+            )])
+            .unwrap();
+        let loaded = agent.loaded_keys();
+        assert_eq!(loaded.len(), 1);
+        assert_eq!(loaded[0].expires_at, None);
+
+        let key = agent
+            .keystore
+            .0
+            .read()
+            .unwrap()
+            .values()
+            .next()
+            .cloned()
+            .unwrap();
+        assert!(agent.confirm(key.clone(), &sign_data(&key), &peer(1)).await);
+
+        let expires_at = agent.loaded_keys()[0].expires_at.unwrap();
+        assert!(expires_at > unix_now() + 3500);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_expiry_task_drops_private_key() {
+        let (mut agent, _request_rx, _response_tx) = test_agent();
+        agent
+            .set_keys(vec![key_with_lifetime(
+                LifetimeStart::Loaded,
+                SHORT_LIFETIME,
+            )])
+            .unwrap();
+        agent.spawn_expiry_task();
+        wait_for_expiry().await;
+
+        tokio::time::sleep(EXPIRY_CHECK_INTERVAL * 2).await;
+        assert!(!private_key_loaded(&agent));
+        agent.cancellation_token.cancel();
+    }
+
+    #[test]
+    fn test_changed_lifetime_restarts_expiry() {
+        let now = Instant::now();
+        let lifetime = KeyLifetime {
+            duration: Duration::from_secs(60),
+            start: LifetimeStart::Loaded,
+        };
+        let previous = PreviousExpiry::from([(
+            "cipher".to_string(),
+            (Some(lifetime), Some(now - Duration::from_secs(1))),
+        )]);
+
+        assert_eq!(
+            expiry_after_load(&previous, "cipher", Some(lifetime), now),
+            Some(now - Duration::from_secs(1))
+        );
+        let longer = KeyLifetime {
+            duration: Duration::from_secs(120),
+            ..lifetime
+        };
+        assert_eq!(
+            expiry_after_load(&previous, "cipher", Some(longer), now),
+            Some(now + Duration::from_secs(120))
+        );
+        assert_eq!(expiry_after_load(&previous, "cipher", None, now), None);
+    }
+}
@@ -1,13 +1,18 @@
 use std::{
     collections::HashMap,
+    path::{Path, PathBuf},
     sync::{
-        atomic::{AtomicBool, AtomicU32},
+        atomic::{AtomicBool, AtomicU32, AtomicUsize},
         Arc, RwLock,
     },
+    time::{Duration, SystemTime, UNIX_EPOCH},
 };
 
 use base64::{engine::general_purpose::STANDARD, Engine as _};
-use tokio::sync::Mutex;
+use tokio::{
+    sync::{Mutex, Notify},
+    time::Instant,
+};
 use tokio_util::sync::CancellationToken;
 
 use bitwarden_russh::{
@@ -24,35 +29,184 @@ mod platform_ssh_agent;
 #[cfg(any(target_os = "linux", target_os = "macos"))]
 mod peercred_unix_listener_stream;
 
+mod approval;
+mod audit;
+mod client_lock;
+mod connection;
+mod destination;
+mod ephemeral_keys;
+mod export;
+pub mod importer;
+mod key_cache;
+pub mod keygen;
+mod known_hosts;
+mod lifetime;
 pub mod peerinfo;
+mod prompts;
+mod protocol;
 mod request_parser;
+mod status;
+#[cfg(target_os = "linux")]
+pub mod systemd;
+mod trusted;
+mod upstream;
+
+pub use approval::{ApprovalPolicy, ForwardingPolicy};
+pub use audit::{verify_audit_log, ApprovalOutcome, AuditEntry, AuditEventKind, AuditRecord};
+pub use destination::{DestinationConstraint, DestinationHop};
+pub use export::PublicKeyExport;
+pub use key_cache::PublicKeyCache;
+pub use lifetime::{KeyLifetime, LifetimeStart, LoadedKey};
+pub use status::{AgentStatus, KeyStatus};
+pub use trusted::{executable_sha256, TrustedExecutable};
+
+/// How long prompts wait for an answer by default, before the request is denied.
+const DEFAULT_PROMPT_TIMEOUT: Duration = Duration::from_secs(60);
 
 #[derive(Clone)]
 pub struct BitwardenDesktopAgent {
     keystore: ssh_agent::KeyStore<BitwardenSshKey>,
     cancellation_token: CancellationToken,
-    show_ui_request_tx: tokio::sync::mpsc::Sender<SshAgentUIRequest>,
-    get_ui_response_rx: Arc<Mutex<tokio::sync::broadcast::Receiver<(u32, bool)>>>,
+    show_ui_request_tx: tokio::sync::mpsc::Sender<SshAgentUIMessage>,
+    get_ui_response_rx: Arc<Mutex<tokio::sync::broadcast::Receiver<SshAgentUIResponse>>>,
     request_id: Arc<AtomicU32>,
+    /// approvals remembered according to the approval policy of each key
+    approvals: Arc<std::sync::Mutex<approval::ApprovalState>>,
+    audit_log: Arc<std::sync::Mutex<audit::AuditLog>>,
+    /// sign requests per key, by cipher id
+    key_usage: Arc<std::sync::Mutex<HashMap<String, status::KeyUsage>>>,
+    /// prompts currently shown to the user, shared by identical concurrent requests
+    pending_prompts: Arc<std::sync::Mutex<prompts::PendingPrompts>>,
+    /// pending prompts are denied after this long
+    prompt_timeout: Arc<RwLock<Duration>>,
+    /// notified when all pending prompts should be cancelled, e.g. when the agent is locked
+    cancel_prompts: Arc<Notify>,
+    /// client binaries that may sign with specific keys without a prompt
+    trusted_executables: Arc<RwLock<Vec<TrustedExecutable>>>,
+    /// host names of the host keys that connections are bound to
+    known_hosts: Arc<std::sync::Mutex<known_hosts::KnownHostsCache>>,
     /// before first unlock, or after account switching, listing keys should require an unlock to get a list of public keys
     needs_unlock: Arc<AtomicBool>,
+    /// when set, forwarded connections only see keys whose forwarding policy allows forwarding
+    hide_keys_when_forwarded: Arc<AtomicBool>,
+    /// set while a client locked the agent with `ssh-add -x`
+    is_client_locked: Arc<AtomicBool>,
+    /// socket or named pipe that the server listens on
+    socket_path: Arc<RwLock<Option<PathBuf>>>,
+    connected_clients: Arc<AtomicUsize>,
+    /// agent that requests for keys not held here are passed on to
+    upstream_agent: Arc<RwLock<Option<PathBuf>>>,
+    /// when set, the public keys of the vault keys are written to disk
+    public_key_export: Arc<RwLock<Option<PublicKeyExport>>>,
+    /// public keys of the active account, listed while listing would require an unlock
+    public_key_cache: Arc<RwLock<Option<key_cache::CacheState>>>,
     is_running: Arc<AtomicBool>,
 }
 
+/// Messages sent from the agent to the UI.
+pub enum SshAgentUIMessage {
+    /// Asks the user to approve a request
+    Request(Box<SshAgentUIRequest>),
+    /// The request is no longer pending, because it timed out, its client disconnected or the
+    /// agent was locked. Its prompt should be closed.
+    Cancel { request_id: u32 },
+}
+
+impl SshAgentUIMessage {
+    pub fn request_id(&self) -> u32 {
+        match self {
+            SshAgentUIMessage::Request(request) => request.request_id,
+            SshAgentUIMessage::Cancel { request_id } => *request_id,
+        }
+    }
+}
+
 pub struct SshAgentUIRequest {
     pub request_id: u32,
+    /// Not set for list requests and for keys that were added with `ssh-add`
     pub cipher_id: Option<String>,
+    pub key_name: Option<String>,
     pub process_name: String,
+    /// Who started the process and where, e.g. "ssh launched by git commit in ~/src/foo"
+    pub process_description: String,
     pub is_list: bool,
+    /// Set when a client asked to unlock the agent with `ssh-add -X`
+    pub is_unlock: bool,
     pub namespace: Option<String>,
+    /// Hash algorithm of SSHSIG requests
+    pub hash_algorithm: Option<String>,
+    /// Start of the signed message hash of SSHSIG requests, in hex
+    pub digest_preview: Option<String>,
+    /// User to log in as, for authentication requests
+    pub user_name: Option<String>,
+    /// Set when the data to sign is neither an authentication request nor an SSHSIG message
+    pub is_arbitrary_data: bool,
+    /// Set when the prompt must be shown regardless of the app's prompt setting, e.g. for keys
+    /// with an approval policy of their own
+    pub must_confirm: bool,
+    /// Names of the destination host in the known_hosts files
+    pub host_names: Vec<String>,
     pub is_forwarding: bool,
 }
 
+#[derive(Clone, Debug)]
+pub struct SshAgentUIResponse {
+    pub request_id: u32,
+    pub accepted: bool,
+    /// When set, identical requests (same key, client process, forwarding state and host key)
+    /// are approved without a prompt for this long.
+    pub remember_for: Option<Duration>,
+}
+
+/// A key as provided by the vault through `set_keys`.
+pub struct VaultSshKey {
+    pub private_key: String,
+    pub name: String,
+    pub cipher_id: String,
+    pub approval_policy: ApprovalPolicy,
+    pub forwarding_policy: ForwardingPolicy,
+    /// OpenSSH certificate for the key, in `authorized_keys` format
+    pub certificate: Option<String>,
+    /// When not empty, the key may only be used for the listed hosts
+    pub destination_constraints: Vec<DestinationConstraint>,
+    /// When set, the private key is dropped once the lifetime has passed
+    pub lifetime: Option<KeyLifetime>,
+}
+
 #[derive(Clone)]
 pub struct BitwardenSshKey {
     pub private_key: Option<ssh_key::private::PrivateKey>,
     pub name: String,
     pub cipher_uuid: String,
+    pub approval_policy: ApprovalPolicy,
+    pub forwarding_policy: ForwardingPolicy,
+    /// When set, this entry advertises the certificate instead of the bare public key
+    pub certificate: Option<ssh_key::Certificate>,
+    pub destination_constraints: Vec<DestinationHop>,
+    /// Keys added by clients with `ssh-add` are not part of the vault and only held in memory
+    pub is_ephemeral: bool,
+    pub lifetime: Option<KeyLifetime>,
+    /// Ephemeral keys are removed and vault keys lose their private key once this point in time
+    /// has passed
+    pub expires_at: Option<Instant>,
+}
+
+impl BitwardenSshKey {
+    /// SHA256 fingerprint of the public key, if it is known
+    pub fn fingerprint(&self) -> Option<String> {
+        if let Some(ref certificate) = self.certificate {
+            Some(
+                certificate
+                    .public_key()
+                    .fingerprint(Default::default())
+                    .to_string(),
+            )
+        } else {
+            self.private_key
+                .as_ref()
+                .map(|private_key| private_key.fingerprint(Default::default()).to_string())
+        }
+    }
 }
 
 impl SshKey for BitwardenSshKey {
@@ -61,7 +215,11 @@ impl SshKey for BitwardenSshKey {
     }
 
     fn public_key_bytes(&self) -> Vec<u8> {
-        if let Some(ref private_key) = self.private_key {
+        if let Some(ref certificate) = self.certificate {
+            certificate
+                .to_bytes()
+                .expect("Certificate is always correctly parsed")
+        } else if let Some(ref private_key) = self.private_key {
             private_key
                 .public_key()
                 .to_bytes()
@@ -87,82 +245,38 @@ impl ssh_agent::Agent<peerinfo::models::PeerInfo, BitwardenSshKey> for Bitwarden
         data: &[u8],
         info: &peerinfo::models::PeerInfo,
     ) -> bool {
-        if !self.is_running() {
-            error!("Agent is not running, but tried to call confirm");
-            return false;
-        }
+        let record = AuditRecord::new(AuditEventKind::Sign, info, ApprovalOutcome::Denied)
+            .with_key(&ssh_key);
 
-        let request_id = self.get_request_id();
         let request_data = match request_parser::parse_request(data) {
-            Ok(data) => data,
+            Ok(data) => data.for_public_key(&ssh_key.public_key_bytes()),
             Err(e) => {
                 error!(error = %e, "Error while parsing request");
+                self.record_audit(record);
+                self.record_key_usage(&ssh_key.cipher_uuid, false, info);
                 return false;
             }
         };
-        let namespace = match request_data {
-            request_parser::SshAgentSignRequest::SshSigRequest(ref req) => {
-                Some(req.namespace.clone())
-            }
-            _ => None,
-        };
 
-        info!(
-            is_forwarding = %info.is_forwarding(),
-            namespace = ?namespace.as_ref(),
-            host_key = %STANDARD.encode(info.host_key()),
-            "Confirming request from application: {}",
-            info.process_name(),
-        );
-
-        let mut rx_channel = self.get_ui_response_rx.lock().await.resubscribe();
-        self.show_ui_request_tx
-            .send(SshAgentUIRequest {
-                request_id,
-                cipher_id: Some(ssh_key.cipher_uuid.clone()),
-                process_name: info.process_name().to_string(),
-                is_list: false,
-                namespace,
-                is_forwarding: info.is_forwarding(),
-            })
-            .await
-            .expect("Should send request to ui");
-        while let Ok((id, response)) = rx_channel.recv().await {
-            if id == request_id {
-                return response;
-            }
+        let outcome = self
+            .authorize_sign_request(&ssh_key, &request_data, info)
+            .await;
+        self.record_audit(AuditRecord {
+            outcome,
+            ..record.with_namespace(request_data.namespace())
+        });
+        self.record_key_usage(&ssh_key.cipher_uuid, outcome.is_approved(), info);
+        if outcome.is_approved() && !ssh_key.is_ephemeral {
+            self.start_lifetime_on_first_use(&ssh_key.cipher_uuid);
         }
-        false
+        outcome.is_approved()
     }
 
     async fn can_list(&self, info: &peerinfo::models::PeerInfo) -> bool {
-        if !self.needs_unlock.load(std::sync::atomic::Ordering::Relaxed) {
-            return true;
-        }
-
-        let request_id = self.get_request_id();
-
-        let mut rx_channel = self.get_ui_response_rx.lock().await.resubscribe();
-        let message = SshAgentUIRequest {
-            request_id,
-            cipher_id: None,
-            process_name: info.process_name().to_string(),
-            is_list: true,
-            namespace: None,
-            is_forwarding: info.is_forwarding(),
-        };
-        self.show_ui_request_tx
-            .send(message)
-            .await
-            .expect("Should send request to ui");
-        while let Ok((id, response)) = rx_channel.recv().await {
-            if id == request_id {
-                return response;
-            }
-        }
-        false
+        let outcome = self.authorize_list(info).await;
+        self.record_audit(AuditRecord::new(AuditEventKind::List, info, outcome));
+        outcome.is_approved()
     }
-
     async fn set_sessionbind_info(
         &self,
         session_bind_info_result: &SessionBindResult,
@@ -170,8 +284,11 @@ impl ssh_agent::Agent<peerinfo::models::PeerInfo, BitwardenSshKey> for Bitwarden
     ) {
         match session_bind_info_result {
             SessionBindResult::Success(session_bind_info) => {
-                connection_info.set_forwarding(session_bind_info.is_forwarding);
-                connection_info.set_host_key(session_bind_info.host_key.clone());
+                connection_info.add_session_bind(
+                    session_bind_info.host_key.clone(),
+                    session_bind_info.session_id.clone(),
+                    session_bind_info.is_forwarding,
+                );
             }
             SessionBindResult::SignatureFailure => {
                 error!("Session bind failure: Signature failure");
@@ -183,8 +300,8 @@ impl ssh_agent::Agent<peerinfo::models::PeerInfo, BitwardenSshKey> for Bitwarden
 impl BitwardenDesktopAgent {
     /// Create a new `BitwardenDesktopAgent` from the provided auth channel handles.
     pub fn new(
-        auth_request_tx: tokio::sync::mpsc::Sender<SshAgentUIRequest>,
-        auth_response_rx: Arc<Mutex<tokio::sync::broadcast::Receiver<(u32, bool)>>>,
+        auth_request_tx: tokio::sync::mpsc::Sender<SshAgentUIMessage>,
+        auth_response_rx: Arc<Mutex<tokio::sync::broadcast::Receiver<SshAgentUIResponse>>>,
     ) -> Self {
         Self {
             keystore: ssh_agent::KeyStore(Arc::new(RwLock::new(HashMap::new()))),
@@ -192,7 +309,24 @@ impl BitwardenDesktopAgent {
             show_ui_request_tx: auth_request_tx,
             get_ui_response_rx: auth_response_rx,
             request_id: Arc::new(AtomicU32::new(0)),
+            approvals: Arc::new(std::sync::Mutex::new(approval::ApprovalState::default())),
+            audit_log: Arc::new(std::sync::Mutex::new(audit::AuditLog::default())),
+            key_usage: Arc::new(std::sync::Mutex::new(HashMap::new())),
+            pending_prompts: Arc::new(std::sync::Mutex::new(prompts::PendingPrompts::default())),
+            prompt_timeout: Arc::new(RwLock::new(DEFAULT_PROMPT_TIMEOUT)),
+            cancel_prompts: Arc::new(Notify::new()),
+            trusted_executables: Arc::new(RwLock::new(Vec::new())),
+            known_hosts: Arc::new(std::sync::Mutex::new(known_hosts::KnownHostsCache::new(
+                known_hosts::default_paths(),
+            ))),
             needs_unlock: Arc::new(AtomicBool::new(true)),
+            hide_keys_when_forwarded: Arc::new(AtomicBool::new(false)),
+            is_client_locked: Arc::new(AtomicBool::new(false)),
+            socket_path: Arc::new(RwLock::new(None)),
+            connected_clients: Arc::new(AtomicUsize::new(0)),
+            upstream_agent: Arc::new(RwLock::new(None)),
+            public_key_export: Arc::new(RwLock::new(None)),
+            public_key_cache: Arc::new(RwLock::new(None)),
             is_running: Arc::new(AtomicBool::new(false)),
         }
     }
@@ -210,39 +344,94 @@ impl BitwardenDesktopAgent {
             .write()
             .expect("RwLock is not poisoned")
             .clear();
+        self.clear_approvals();
+        self.cancel_prompts.notify_waiters();
     }
 
-    pub fn set_keys(
-        &mut self,
-        new_keys: Vec<(String, String, String)>,
-    ) -> Result<(), anyhow::Error> {
+    pub fn set_keys(&mut self, new_keys: Vec<VaultSshKey>) -> Result<(), anyhow::Error> {
         if !self.is_running() {
             return Err(anyhow::anyhow!(
                 "[BitwardenDesktopAgent] Tried to set keys while agent is not running"
             ));
         }
 
+        let previous_expiry = self.previous_expiry();
+        let now = Instant::now();
         let keystore = &mut self.keystore;
-        keystore.0.write().expect("RwLock is not poisoned").clear();
+        keystore
+            .0
+            .write()
+            .expect("RwLock is not poisoned")
+            .retain(|_, key| key.is_ephemeral);
 
         self.needs_unlock
             .store(true, std::sync::atomic::Ordering::Relaxed);
 
-        for (key, name, cipher_id) in new_keys.iter() {
-            match parse_key_safe(key) {
+        for key in new_keys.iter() {
+            let destination_constraints = match destination::parse_constraints(
+                &key.destination_constraints,
+            ) {
+                Ok(constraints) => constraints,
+                Err(e) => {
+                    // Loading the key without its constraints would allow it everywhere
+                    error!(error=%e, cipher_id = %key.cipher_id, "Error while parsing destination constraints");
+                    continue;
+                }
+            };
+
+            match parse_key_safe(&key.private_key) {
                 Ok(private_key) => {
-                    let public_key_bytes = private_key
-                        .public_key()
-                        .to_bytes()
-                        .expect("Cipher private key is always correctly parsed");
-                    keystore.0.write().expect("RwLock is not poisoned").insert(
-                        public_key_bytes,
-                        BitwardenSshKey {
-                            private_key: Some(private_key),
-                            name: name.clone(),
-                            cipher_uuid: cipher_id.clone(),
-                        },
+                    let certificate = key.certificate.as_deref().and_then(|certificate| {
+                        match parse_certificate_safe(certificate, &private_key) {
+                            Ok(certificate) => Some(certificate),
+                            Err(e) => {
+                                error!(error=%e, cipher_id = %key.cipher_id, "Error while parsing certificate");
+                                None
+                            }
+                        }
+                    });
+
+                    let expires_at = lifetime::expiry_after_load(
+                        &previous_expiry,
+                        &key.cipher_id,
+                        key.lifetime,
+                        now,
                     );
+                    let mut bitwarden_key = BitwardenSshKey {
+                        private_key: Some(private_key),
+                        name: key.name.clone(),
+                        cipher_uuid: key.cipher_id.clone(),
+                        approval_policy: key.approval_policy,
+                        forwarding_policy: key.forwarding_policy,
+                        certificate: None,
+                        destination_constraints,
+                        is_ephemeral: false,
+                        lifetime: key.lifetime,
+                        expires_at,
+                    };
+                    // A key whose lifetime passed stays without its private key until the vault
+                    // is locked
+                    let is_expired = expires_at.is_some_and(|expires_at| expires_at <= now);
+
+                    let mut keystore = keystore.0.write().expect("RwLock is not poisoned");
+                    // The certificate is offered in addition to the bare key, like `ssh-add` does
+                    if let Some(certificate) = certificate {
+                        let mut certificate_key = BitwardenSshKey {
+                            name: format!("{} (certificate {})", key.name, certificate.key_id()),
+                            certificate: Some(certificate),
+                            ..bitwarden_key.clone()
+                        };
+                        let public_key_bytes = certificate_key.public_key_bytes();
+                        if is_expired {
+                            certificate_key.private_key = None;
+                        }
+                        keystore.insert(public_key_bytes, certificate_key);
+                    }
+                    let public_key_bytes = bitwarden_key.public_key_bytes();
+                    if is_expired {
+                        bitwarden_key.private_key = None;
+                    }
+                    keystore.insert(public_key_bytes, bitwarden_key);
                 }
                 Err(e) => {
                     error!(error=%e, "Error while parsing key");
@@ -250,6 +439,8 @@ impl BitwardenDesktopAgent {
             }
         }
 
+        self.sync_public_key_export();
+        self.sync_public_key_cache();
         Ok(())
     }
 
@@ -260,8 +451,22 @@ impl BitwardenDesktopAgent {
             ));
         }
 
-        let keystore = &mut self.keystore;
-        keystore
+        self.drop_private_keys();
+        self.reset_key_lifetimes();
+        // the counts cover the time since the vault was unlocked
+        self.key_usage
+            .lock()
+            .expect("Mutex is not poisoned")
+            .clear();
+        Ok(())
+    }
+
+    /// Removes the keys added with `ssh-add` and the private keys of vault keys, and forgets
+    /// approvals and pending prompts.
+    fn drop_private_keys(&self) {
+        self.remove_all_identities();
+
+        self.keystore
             .0
             .write()
             .expect("RwLock is not poisoned")
@@ -269,7 +474,8 @@ impl BitwardenDesktopAgent {
             .for_each(|(_public_key, key)| {
                 key.private_key = None;
             });
-        Ok(())
+        self.clear_approvals();
+        self.cancel_prompts.notify_waiters();
     }
 
     pub fn clear_keys(&mut self) -> Result<(), anyhow::Error> {
@@ -277,10 +483,26 @@ impl BitwardenDesktopAgent {
         keystore.0.write().expect("RwLock is not poisoned").clear();
         self.needs_unlock
             .store(true, std::sync::atomic::Ordering::Relaxed);
+        self.clear_approvals();
+        self.key_usage
+            .lock()
+            .expect("Mutex is not poisoned")
+            .clear();
+        self.sync_public_key_export();
+        // the cache belongs to the previous account
+        self.set_public_key_cache(None);
 
         Ok(())
     }
 
+    /// Forget approvals granted under the `AskOncePerUnlock` and `AskOncePerProcess` policies.
+    fn clear_approvals(&self) {
+        self.approvals
+            .lock()
+            .expect("Mutex is not poisoned")
+            .clear();
+    }
+
     fn get_request_id(&self) -> u32 {
         if !self.is_running() {
             error!("Agent is not running, but tried to get request id");
@@ -294,6 +516,407 @@ impl BitwardenDesktopAgent {
     pub fn is_running(&self) -> bool {
         self.is_running.load(std::sync::atomic::Ordering::Relaxed)
     }
+
+    async fn authorize_sign_request(
+        &self,
+        ssh_key: &BitwardenSshKey,
+        request_data: &request_parser::SshAgentSignRequest,
+        info: &peerinfo::models::PeerInfo,
+    ) -> ApprovalOutcome {
+        if !self.is_running() {
+            error!("Agent is not running, but tried to call confirm");
+            return ApprovalOutcome::Denied;
+        }
+
+        if ssh_key
+            .expires_at
+            .is_some_and(|expires_at| expires_at <= Instant::now())
+        {
+            error!(cipher_id = %ssh_key.cipher_uuid, "Key lifetime has expired");
+            return ApprovalOutcome::Denied;
+        }
+
+        if info.is_forwarding() && ssh_key.forwarding_policy == ForwardingPolicy::Denied {
+            error!(
+                cipher_id = %ssh_key.cipher_uuid,
+                host_key = %STANDARD.encode(info.host_key()),
+                "Forwarded request denied by key forwarding policy"
+            );
+            return ApprovalOutcome::Denied;
+        }
+
+        if let Some(ref certificate) = ssh_key.certificate {
+            if !is_certificate_valid_at(certificate, unix_now()) {
+                error!(
+                    cipher_id = %ssh_key.cipher_uuid,
+                    key_id = %certificate.key_id(),
+                    "Certificate is outside of its validity window"
+                );
+                return ApprovalOutcome::Denied;
+            }
+        }
+
+        if let Err(e) = destination::check_destination(
+            &ssh_key.destination_constraints,
+            &info.session_binds(),
+            request_data.user_auth(),
+        ) {
+            error!(
+                error = %e,
+                cipher_id = %ssh_key.cipher_uuid,
+                host_key = %STANDARD.encode(info.host_key()),
+                "Request denied by destination constraints"
+            );
+            return ApprovalOutcome::Denied;
+        }
+
+        let host_names = match lookup_host_names(&self.known_hosts, request_data, info).await {
+            Ok(host_names) => host_names,
+            Err(e) => {
+                error!(error = %e, cipher_id = %ssh_key.cipher_uuid, "Request denied");
+                return ApprovalOutcome::Denied;
+            }
+        };
+
+        let request_id = self.get_request_id();
+        let namespace = request_data.namespace().map(str::to_string);
+
+        info!(
+            is_forwarding = %info.is_forwarding(),
+            namespace = ?namespace.as_ref(),
+            user_name = ?request_data.user_auth().map(|req| &req.user_name),
+            service = ?request_data.user_auth().map(|req| &req.service),
+            algorithm = ?request_data.user_auth().map(|req| &req.algorithm),
+            ?host_names,
+            host_key = %STANDARD.encode(info.host_key()),
+            "Confirming request from application: {}",
+            info.process_name(),
+        );
+
+        // Signing arbitrary data is always confirmed, as the signature could be used for
+        // anything; approval policies only cover logins and SSHSIG signatures.
+        let requires_forwarding_confirmation = info.is_forwarding()
+            && ssh_key.forwarding_policy == ForwardingPolicy::RequireConfirmation;
+        // Forwarded requests come from a remote host, not from the local binary
+        if !request_data.is_arbitrary_data()
+            && !info.is_forwarding()
+            && self.is_trusted_executable(ssh_key, info).await
+        {
+            info!(
+                cipher_id = %ssh_key.cipher_uuid,
+                exe_path = ?info.details().exe_path,
+                "Request approved for trusted executable"
+            );
+            return ApprovalOutcome::AutoApproved;
+        }
+
+        if !request_data.is_arbitrary_data()
+            && !requires_forwarding_confirmation
+            && self
+                .approvals
+                .lock()
+                .expect("Mutex is not poisoned")
+                .is_approved(ssh_key.approval_policy, &ssh_key.cipher_uuid, info)
+        {
+            info!(
+                cipher_id = %ssh_key.cipher_uuid,
+                policy = ?ssh_key.approval_policy,
+                "Request approved by key approval policy"
+            );
+            return ApprovalOutcome::AutoApproved;
+        }
+
+        let request = SshAgentUIRequest {
+            request_id,
+            cipher_id: (!ssh_key.is_ephemeral).then(|| ssh_key.cipher_uuid.clone()),
+            key_name: Some(ssh_key.name.clone()),
+            process_name: info.process_name().to_string(),
+            process_description: info.description(),
+            is_list: false,
+            is_unlock: false,
+            namespace,
+            hash_algorithm: request_data
+                .sshsig()
+                .map(|req| req.hash_algorithm.name().to_string()),
+            digest_preview: request_data.sshsig().map(|req| req.digest_preview()),
+            user_name: request_data.user_auth().map(|req| req.user_name.clone()),
+            is_arbitrary_data: request_data.is_arbitrary_data(),
+            must_confirm: request_data.is_arbitrary_data()
+                || requires_forwarding_confirmation
+                || ssh_key.approval_policy != ApprovalPolicy::FollowAppSetting,
+            host_names,
+            is_forwarding: info.is_forwarding(),
+        };
+        let Some(response) = self
+            .prompt_user(
+                Some(&ssh_key.cipher_uuid),
+                request,
+                request_data.sshsig().map(|req| req.message_hash.as_slice()),
+                info,
+            )
+            .await
+        else {
+            return ApprovalOutcome::Denied;
+        };
+
+        if !response.accepted {
+            return ApprovalOutcome::Denied;
+        }
+        let mut approvals = self.approvals.lock().expect("Mutex is not poisoned");
+        approvals.record_approval(ssh_key.approval_policy, &ssh_key.cipher_uuid, info);
+        if let Some(duration) = response.remember_for {
+            approvals.remember(&ssh_key.cipher_uuid, info, duration);
+        }
+        ApprovalOutcome::Approved
+    }
+
+    async fn authorize_list(&self, info: &peerinfo::models::PeerInfo) -> ApprovalOutcome {
+        if info.is_forwarding() && !self.has_forwardable_keys() {
+            info!(
+                host_key = %STANDARD.encode(info.host_key()),
+                "Forwarded list request denied, no key may be used through forwarding"
+            );
+            return ApprovalOutcome::Denied;
+        }
+
+        if !self.needs_unlock.load(std::sync::atomic::Ordering::Relaxed) {
+            return ApprovalOutcome::AutoApproved;
+        }
+
+        let request_id = self.get_request_id();
+
+        let message = SshAgentUIRequest {
+            request_id,
+            cipher_id: None,
+            key_name: None,
+            process_name: info.process_name().to_string(),
+            process_description: info.description(),
+            is_list: true,
+            is_unlock: false,
+            namespace: None,
+            hash_algorithm: None,
+            digest_preview: None,
+            user_name: None,
+            is_arbitrary_data: false,
+            must_confirm: false,
+            host_names: Vec::new(),
+            is_forwarding: info.is_forwarding(),
+        };
+        match self.prompt_user(None, message, None, info).await {
+            Some(response) if response.accepted => ApprovalOutcome::Approved,
+            _ => ApprovalOutcome::Denied,
+        }
+    }
+
+    /// Shows `request` to the user and waits for the answer. Identical requests that are pending
+    /// at the same time share a single prompt, and requests beyond `MAX_PENDING_PROMPTS` are
+    /// denied without one. `message_hash` is the message that an SSHSIG request signs. Returns
+    /// `None` if there is no answer.
+    async fn prompt_user(
+        &self,
+        key_id: Option<&str>,
+        request: SshAgentUIRequest,
+        message_hash: Option<&[u8]>,
+        info: &peerinfo::models::PeerInfo,
+    ) -> Option<SshAgentUIResponse> {
+        let prompt_key = prompts::PromptKey::new(key_id, &request, message_hash, info);
+        match prompts::PendingPrompts::acquire(&self.pending_prompts, prompt_key, info) {
+            prompts::Prompt::Show(guard, waiters) => {
+                let response = self.show_prompt(request, &waiters).await?;
+                guard.resolve(response.clone());
+                Some(response)
+            }
+            prompts::Prompt::Wait(answer) => {
+                info!(
+                    process_name = %info.process_name(),
+                    "Waiting for the answer to an identical pending request"
+                );
+                prompts::wait_for_answer(answer).await
+            }
+            prompts::Prompt::Rejected => {
+                error!(
+                    process_name = %info.process_name(),
+                    max_pending_prompts = prompts::MAX_PENDING_PROMPTS,
+                    "Request denied, too many prompts are pending"
+                );
+                None
+            }
+        }
+    }
+
+    /// Sends `request` to the UI and waits for its answer. The request is cancelled when it times
+    /// out, when every client waiting for it has disconnected and when the agent is locked.
+    async fn show_prompt(
+        &self,
+        request: SshAgentUIRequest,
+        waiters: &prompts::Waiters,
+    ) -> Option<SshAgentUIResponse> {
+        let request_id = request.request_id;
+        let timeout = *self.prompt_timeout.read().expect("RwLock is not poisoned");
+        // created before sending the request, so that a lock right after is not missed
+        let cancelled = self.cancel_prompts.notified();
+        let mut rx_channel = self.get_ui_response_rx.lock().await.resubscribe();
+        if self
+            .show_ui_request_tx
+            .send(SshAgentUIMessage::Request(Box::new(request)))
+            .await
+            .is_err()
+        {
+            error!(
+                request_id,
+                "UI is not listening for requests, denying request"
+            );
+            return None;
+        }
+
+        let answer = async {
+            while let Ok(response) = rx_channel.recv().await {
+                if response.request_id == request_id {
+                    return Some(response);
+                }
+            }
+            error!(
+                request_id,
+                "UI closed the response channel, denying request"
+            );
+            None
+        };
+        tokio::select! {
+            response = answer => return response,
+            _ = tokio::time::sleep(timeout) => {
+                info!(request_id, ?timeout, "Request timed out");
+            }
+            _ = waiters.all_disconnected() => {
+                info!(request_id, "Clients disconnected while the request was pending");
+            }
+            _ = cancelled => {
+                info!(request_id, "Request cancelled, the agent was locked or stopped");
+            }
+        }
+
+        // the UI may be gone as well, in which case there is no prompt to close
+        let _ = self
+            .show_ui_request_tx
+            .try_send(SshAgentUIMessage::Cancel { request_id });
+        None
+    }
+
+    /// Sets how long prompts wait for an answer before the request is denied.
+    pub fn set_prompt_timeout(&self, timeout: Duration) {
+        *self.prompt_timeout.write().expect("RwLock is not poisoned") = timeout;
+    }
+
+    /// Replaces the client binaries that may sign with specific keys without a prompt.
+    pub fn set_trusted_executables(&self, trusted_executables: Vec<TrustedExecutable>) {
+        *self
+            .trusted_executables
+            .write()
+            .expect("RwLock is not poisoned") = trusted_executables;
+    }
+
+    async fn is_trusted_executable(
+        &self,
+        ssh_key: &BitwardenSshKey,
+        info: &peerinfo::models::PeerInfo,
+    ) -> bool {
+        // keys added with `ssh-add` have no cipher id that could be trusted
+        if ssh_key.is_ephemeral {
+            return false;
+        }
+
+        let trusted_executables = self
+            .trusted_executables
+            .read()
+            .expect("RwLock is not poisoned")
+            .clone();
+        for trusted in &trusted_executables {
+            if trusted.trusts(&ssh_key.cipher_uuid, info).await {
+                return true;
+            }
+        }
+        false
+    }
+
+    /// Returns true if any loaded key may be used by forwarded connections, possibly after
+    /// confirmation.
+    fn has_forwardable_keys(&self) -> bool {
+        self.keystore
+            .0
+            .read()
+            .expect("RwLock is not poisoned")
+            .values()
+            .any(|key| key.forwarding_policy != ForwardingPolicy::Denied)
+    }
+
+    /// Controls whether forwarded connections only see the keys whose forwarding policy is
+    /// `ForwardingPolicy::Allowed` when listing keys.
+    pub fn set_hide_keys_when_forwarded(&self, hide: bool) {
+        self.hide_keys_when_forwarded
+            .store(hide, std::sync::atomic::Ordering::Relaxed);
+    }
+
+    fn record_audit(&self, record: AuditRecord) {
+        self.audit_log
+            .lock()
+            .expect("Mutex is not poisoned")
+            .record(record);
+    }
+
+    /// Writes the audit log to `path` from now on, continuing the hash chain of an existing log.
+    pub fn enable_audit_log(&self, path: &Path) -> Result<(), anyhow::Error> {
+        self.audit_log
+            .lock()
+            .expect("Mutex is not poisoned")
+            .open(path)
+    }
+
+    /// Returns up to `limit` of the most recent audit entries, newest first.
+    pub fn audit_entries(&self, limit: usize) -> Vec<AuditEntry> {
+        self.audit_log
+            .lock()
+            .expect("Mutex is not poisoned")
+            .recent(limit)
+    }
+}
+
+/// Looks up the host keys that the connection is bound to in the known_hosts files, and returns
+/// the names of the destination host. Fails if any of the host keys is revoked.
+async fn lookup_host_names(
+    known_hosts_cache: &Arc<std::sync::Mutex<known_hosts::KnownHostsCache>>,
+    request_data: &request_parser::SshAgentSignRequest,
+    info: &peerinfo::models::PeerInfo,
+) -> Result<Vec<String>, anyhow::Error> {
+    let mut host_keys: Vec<Vec<u8>> = info
+        .session_binds()
+        .into_iter()
+        .map(|bind| bind.host_key)
+        .collect();
+    // the server's host key, sent by OpenSSH with the publickey-hostbound userauth method
+    if let Some(host_key) = request_data
+        .user_auth()
+        .and_then(|req| req.host_key.clone())
+    {
+        host_keys.push(host_key);
+    }
+    let Some(destination) = host_keys.last() else {
+        return Ok(Vec::new());
+    };
+
+    let cache = known_hosts_cache.clone();
+    let known_hosts =
+        tokio::task::spawn_blocking(move || cache.lock().expect("Mutex is not poisoned").get())
+            .await?;
+    let candidates = known_hosts::candidate_host_names(&info.details().cmdline);
+    if let Some(revoked) = host_keys
+        .iter()
+        .find(|host_key| known_hosts.lookup(host_key, &candidates).is_revoked)
+    {
+        return Err(anyhow::anyhow!(
+            "Host key {} is revoked in known_hosts",
+            STANDARD.encode(revoked)
+        ));
+    }
+    Ok(known_hosts.lookup(destination, &candidates).host_names)
 }
 
 fn parse_key_safe(pem: &str) -> Result<ssh_key::private::PrivateKey, anyhow::Error> {
@@ -307,3 +930,895 @@ fn parse_key_safe(pem: &str) -> Result<ssh_key::private::PrivateKey, anyhow::Err
         Err(e) => Err(anyhow::Error::msg(format!("Failed to parse key: {e}"))),
     }
 }
+
+fn parse_certificate_safe(
+    certificate: &str,
+    private_key: &ssh_key::private::PrivateKey,
+) -> Result<ssh_key::Certificate, anyhow::Error> {
+    let certificate = ssh_key::Certificate::from_openssh(certificate.trim())
+        .map_err(|e| anyhow::Error::msg(format!("Failed to parse certificate: {e}")))?;
+
+    if certificate.public_key() != private_key.public_key().key_data() {
+        return Err(anyhow::anyhow!(
+            "Certificate does not match the private key"
+        ));
+    }
+
+    if certificate.valid_before() <= unix_now() {
+        return Err(anyhow::anyhow!(
+            "Certificate {} has expired",
+            certificate.key_id()
+        ));
+    }
+
+    Ok(certificate)
+}
+
+fn is_certificate_valid_at(certificate: &ssh_key::Certificate, unix_time: u64) -> bool {
+    certificate.valid_after() <= unix_time && unix_time < certificate.valid_before()
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::Ordering;
+
+    use bitwarden_russh::ssh_agent::Agent;
+    use rand::{distr::Alphanumeric, Rng};
+    use ssh_encoding::Encode;
+    use ssh_key::{rand_core::OsRng, Algorithm, LineEnding};
+
+    use super::*;
+
+    /// A publickey userauth request for `key`, as signed by ssh.
+    pub(super) fn sign_data(key: &BitwardenSshKey) -> Vec<u8> {
+        let mut data = Vec::new();
+        b"session id".as_slice().encode(&mut data).unwrap();
+        // SSH_MSG_USERAUTH_REQUEST
+        data.push(50);
+        "deploy".encode(&mut data).unwrap();
+        "ssh-connection".encode(&mut data).unwrap();
+        "publickey".encode(&mut data).unwrap();
+        // has signature
+        data.push(1);
+        let algorithm = key.private_key.as_ref().unwrap().algorithm();
+        algorithm.as_str().encode(&mut data).unwrap();
+        key.public_key_bytes().encode(&mut data).unwrap();
+        data
+    }
+    const YEAR_2100: u64 = 4_102_444_800;
+
+    /// A `publickey-hostbound-v00@openssh.com` request on the session `session_id` with the
+    /// server `host_key`.
+    fn hostbound_sign_data(key: &BitwardenSshKey, session_id: &[u8], host_key: &[u8]) -> Vec<u8> {
+        let mut data = Vec::new();
+        session_id.encode(&mut data).unwrap();
+        // SSH_MSG_USERAUTH_REQUEST
+        data.push(50);
+        "deploy".encode(&mut data).unwrap();
+        "ssh-connection".encode(&mut data).unwrap();
+        "publickey-hostbound-v00@openssh.com"
+            .encode(&mut data)
+            .unwrap();
+        // has signature
+        data.push(1);
+        let algorithm = key.private_key.as_ref().unwrap().algorithm();
+        algorithm.as_str().encode(&mut data).unwrap();
+        key.public_key_bytes().encode(&mut data).unwrap();
+        host_key.encode(&mut data).unwrap();
+        data
+    }
+
+    /// A path to a file in the temporary directory that does not exist yet.
+    pub(super) fn rand_file_in_temp() -> PathBuf {
+        let mut path = std::env::temp_dir();
+        let s: String = rand::rng()
+            .sample_iter(&Alphanumeric)
+            .take(16)
+            .map(char::from)
+            .collect();
+        path.push(s);
+        path
+    }
+
+    pub(super) fn test_agent() -> (
+        BitwardenDesktopAgent,
+        tokio::sync::mpsc::Receiver<SshAgentUIMessage>,
+        tokio::sync::broadcast::Sender<SshAgentUIResponse>,
+    ) {
+        let (request_tx, request_rx) = tokio::sync::mpsc::channel(32);
+        let (response_tx, response_rx) = tokio::sync::broadcast::channel(32);
+        let agent = BitwardenDesktopAgent::new(request_tx, Arc::new(Mutex::new(response_rx)));
+        agent.is_running.store(true, Ordering::Relaxed);
+        (agent, request_rx, response_tx)
+    }
+
+    /// Answers every UI request with `response` and counts how many prompts were shown.
+    pub(super) fn fake_ui(
+        mut request_rx: tokio::sync::mpsc::Receiver<SshAgentUIMessage>,
+        response_tx: tokio::sync::broadcast::Sender<SshAgentUIResponse>,
+        accepted: bool,
+        remember_for: Option<Duration>,
+    ) -> Arc<AtomicU32> {
+        let prompts = Arc::new(AtomicU32::new(0));
+        let cloned_prompts = prompts.clone();
+        tokio::spawn(async move {
+            while let Some(message) = request_rx.recv().await {
+                let SshAgentUIMessage::Request(request) = message else {
+                    continue;
+                };
+                cloned_prompts.fetch_add(1, Ordering::Relaxed);
+                let _ = response_tx.send(SshAgentUIResponse {
+                    request_id: request.request_id,
+                    accepted,
+                    remember_for,
+                });
+            }
+        });
+        prompts
+    }
+
+    pub(super) fn vault_key(approval_policy: ApprovalPolicy) -> VaultSshKey {
+        let private_key = ssh_key::PrivateKey::random(&mut OsRng, Algorithm::Ed25519)
+            .unwrap()
+            .to_openssh(LineEnding::LF)
+            .unwrap()
+            .to_string();
+        VaultSshKey {
+            private_key,
+            name: "test key".to_string(),
+            cipher_id: "cipher".to_string(),
+            approval_policy,
+            forwarding_policy: ForwardingPolicy::default(),
+            certificate: None,
+            destination_constraints: Vec::new(),
+            lifetime: None,
+        }
+    }
+
+    fn forwarded_peer(pid: u32) -> peerinfo::models::PeerInfo {
+        let peer = peer(pid);
+        peer.add_session_bind(vec![1, 2, 3], vec![4, 5, 6], true);
+        peer
+    }
+
+    /// Returns a vault key together with a certificate for it, valid in the given window.
+    fn vault_key_with_certificate(valid_after: u64, valid_before: u64) -> VaultSshKey {
+        let mut key = vault_key(ApprovalPolicy::NeverAsk);
+        let private_key = parse_key_safe(&key.private_key).unwrap();
+        key.certificate = Some(sign_certificate(
+            private_key.public_key().key_data(),
+            valid_after,
+            valid_before,
+        ));
+        key
+    }
+
+    fn sign_certificate(
+        public_key: &ssh_key::public::KeyData,
+        valid_after: u64,
+        valid_before: u64,
+    ) -> String {
+        let ca_key = ssh_key::PrivateKey::random(&mut OsRng, Algorithm::Ed25519).unwrap();
+        let mut builder = ssh_key::certificate::Builder::new_with_random_nonce(
+            &mut OsRng,
+            public_key.clone(),
+            valid_after,
+            valid_before,
+        )
+        .unwrap();
+        builder.key_id("user@example.com").unwrap();
+        builder.all_principals_valid().unwrap();
+        builder.sign(&ca_key).unwrap().to_openssh().unwrap()
+    }
+
+    fn loaded_keys(agent: &BitwardenDesktopAgent) -> Vec<BitwardenSshKey> {
+        agent.keystore.0.read().unwrap().values().cloned().collect()
+    }
+
+    fn loaded_key(agent: &BitwardenDesktopAgent) -> BitwardenSshKey {
+        agent
+            .keystore
+            .0
+            .read()
+            .unwrap()
+            .values()
+            .next()
+            .cloned()
+            .unwrap()
+    }
+
+    pub(super) fn peer(pid: u32) -> peerinfo::models::PeerInfo {
+        peerinfo::models::PeerInfo::new(1000, pid, "ssh".to_string())
+    }
+
+    #[tokio::test]
+    async fn test_never_ask_does_not_prompt() {
+        let (mut agent, request_rx, _response_tx) = test_agent();
+        // Without a UI listening, sending a prompt would fail.
+        drop(request_rx);
+        agent
+            .set_keys(vec![vault_key(ApprovalPolicy::NeverAsk)])
+            .unwrap();
+
+        assert!(
+            agent
+                .confirm(
+                    loaded_key(&agent),
+                    &sign_data(&loaded_key(&agent)),
+                    &peer(1)
+                )
+                .await
+        );
+    }
+
+    #[tokio::test]
+    async fn test_always_ask_prompts_every_time() {
+        let (mut agent, request_rx, response_tx) = test_agent();
+        let prompts = fake_ui(request_rx, response_tx, true, None);
+        agent
+            .set_keys(vec![vault_key(ApprovalPolicy::AlwaysAsk)])
+            .unwrap();
+
+        assert!(
+            agent
+                .confirm(
+                    loaded_key(&agent),
+                    &sign_data(&loaded_key(&agent)),
+                    &peer(1)
+                )
+                .await
+        );
+        assert!(
+            agent
+                .confirm(
+                    loaded_key(&agent),
+                    &sign_data(&loaded_key(&agent)),
+                    &peer(1)
+                )
+                .await
+        );
+        assert_eq!(prompts.load(Ordering::Relaxed), 2);
+    }
+
+    #[tokio::test]
+    async fn test_ask_once_per_unlock_prompts_again_after_lock() {
+        let (mut agent, request_rx, response_tx) = test_agent();
+        let prompts = fake_ui(request_rx, response_tx, true, None);
+        agent
+            .set_keys(vec![vault_key(ApprovalPolicy::AskOncePerUnlock)])
+            .unwrap();
+        let key = loaded_key(&agent);
+
+        assert!(agent.confirm(key.clone(), &sign_data(&key), &peer(1)).await);
+        assert!(agent.confirm(key.clone(), &sign_data(&key), &peer(2)).await);
+        assert_eq!(prompts.load(Ordering::Relaxed), 1);
+
+        agent.lock().unwrap();
+        assert!(agent.confirm(key.clone(), &sign_data(&key), &peer(1)).await);
+        assert_eq!(prompts.load(Ordering::Relaxed), 2);
+    }
+
+    #[tokio::test]
+    async fn test_denied_request_is_not_remembered() {
+        let (mut agent, request_rx, response_tx) = test_agent();
+        let prompts = fake_ui(request_rx, response_tx, false, None);
+        agent
+            .set_keys(vec![vault_key(ApprovalPolicy::AskOncePerProcess)])
+            .unwrap();
+
+        assert!(
+            !agent
+                .confirm(
+                    loaded_key(&agent),
+                    &sign_data(&loaded_key(&agent)),
+                    &peer(1)
+                )
+                .await
+        );
+        assert!(
+            !agent
+                .confirm(
+                    loaded_key(&agent),
+                    &sign_data(&loaded_key(&agent)),
+                    &peer(1)
+                )
+                .await
+        );
+        assert_eq!(prompts.load(Ordering::Relaxed), 2);
+    }
+
+    #[tokio::test]
+    async fn test_remembered_approval_skips_prompt_until_lock() {
+        let (mut agent, request_rx, response_tx) = test_agent();
+        let prompts = fake_ui(request_rx, response_tx, true, Some(Duration::from_secs(60)));
+        agent
+            .set_keys(vec![vault_key(ApprovalPolicy::AlwaysAsk)])
+            .unwrap();
+        let key = loaded_key(&agent);
+
+        assert!(agent.confirm(key.clone(), &sign_data(&key), &peer(1)).await);
+        assert!(agent.confirm(key.clone(), &sign_data(&key), &peer(1)).await);
+        assert_eq!(prompts.load(Ordering::Relaxed), 1);
+
+        // a different client process is not covered by the remembered approval
+        assert!(agent.confirm(key.clone(), &sign_data(&key), &peer(2)).await);
+        assert_eq!(prompts.load(Ordering::Relaxed), 2);
+
+        agent.lock().unwrap();
+        assert!(agent.confirm(key.clone(), &sign_data(&key), &peer(1)).await);
+        assert_eq!(prompts.load(Ordering::Relaxed), 3);
+    }
+
+    #[tokio::test]
+    async fn test_certificate_is_listed_next_to_key() {
+        let (mut agent, _request_rx, _response_tx) = test_agent();
+        agent
+            .set_keys(vec![vault_key_with_certificate(0, YEAR_2100)])
+            .unwrap();
+
+        let keys = loaded_keys(&agent);
+        assert_eq!(keys.len(), 2);
+        let certificate_key = keys.iter().find(|k| k.certificate.is_some()).unwrap();
+        assert_eq!(
+            certificate_key.name,
+            "test key (certificate user@example.com)"
+        );
+        assert!(agent.keystore.0.read().unwrap().contains_key(
+            &certificate_key
+                .certificate
+                .as_ref()
+                .unwrap()
+                .to_bytes()
+                .unwrap()
+        ));
+
+        assert!(
+            agent
+                .confirm(
+                    certificate_key.clone(),
+                    &sign_data(certificate_key),
+                    &peer(1)
+                )
+                .await
+        );
+    }
+
+    #[test]
+    fn test_expired_certificate_is_rejected() {
+        let (mut agent, _request_rx, _response_tx) = test_agent();
+        agent
+            .set_keys(vec![vault_key_with_certificate(1, 2)])
+            .unwrap();
+
+        let keys = loaded_keys(&agent);
+        assert_eq!(keys.len(), 1);
+        assert!(keys[0].certificate.is_none());
+    }
+
+    #[test]
+    fn test_certificate_for_other_key_is_rejected() {
+        let (mut agent, _request_rx, _response_tx) = test_agent();
+        let other_key = ssh_key::PrivateKey::random(&mut OsRng, Algorithm::Ed25519).unwrap();
+        let mut key = vault_key(ApprovalPolicy::NeverAsk);
+        key.certificate = Some(sign_certificate(
+            other_key.public_key().key_data(),
+            0,
+            YEAR_2100,
+        ));
+        agent.set_keys(vec![key]).unwrap();
+
+        let keys = loaded_keys(&agent);
+        assert_eq!(keys.len(), 1);
+        assert!(keys[0].certificate.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_confirm_denies_certificate_outside_validity_window() {
+        let (mut agent, _request_rx, _response_tx) = test_agent();
+        agent
+            .set_keys(vec![vault_key_with_certificate(0, YEAR_2100)])
+            .unwrap();
+        let mut key = loaded_keys(&agent)
+            .into_iter()
+            .find(|k| k.certificate.is_some())
+            .unwrap();
+        let private_key = key.private_key.clone().unwrap();
+        key.certificate = Some(
+            ssh_key::Certificate::from_openssh(&sign_certificate(
+                private_key.public_key().key_data(),
+                YEAR_2100 - 1,
+                YEAR_2100,
+            ))
+            .unwrap(),
+        );
+
+        assert!(!agent.confirm(key.clone(), &sign_data(&key), &peer(1)).await);
+    }
+
+    #[tokio::test]
+    async fn test_destination_constraints_are_enforced() {
+        let (mut agent, _request_rx, _response_tx) = test_agent();
+        let allowed_host = ssh_key::PrivateKey::random(&mut OsRng, Algorithm::Ed25519)
+            .unwrap()
+            .public_key()
+            .clone();
+        let other_host = ssh_key::PrivateKey::random(&mut OsRng, Algorithm::Ed25519)
+            .unwrap()
+            .public_key()
+            .clone();
+        let mut key = vault_key(ApprovalPolicy::NeverAsk);
+        key.destination_constraints = vec![DestinationConstraint {
+            from_host_key: None,
+            to_host_key: allowed_host.to_openssh().unwrap(),
+        }];
+        agent.set_keys(vec![key]).unwrap();
+
+        let key = loaded_key(&agent);
+        let allowed_host = allowed_host.to_bytes().unwrap();
+        let allowed_peer = peer(1);
+        allowed_peer.add_session_bind(allowed_host.clone(), b"session".to_vec(), false);
+        let data = hostbound_sign_data(&key, b"session", &allowed_host);
+        assert!(agent.confirm(key.clone(), &data, &allowed_peer).await);
+
+        // arbitrary data and requests for other sessions are refused, even to the allowed host
+        assert!(!agent.confirm(key.clone(), b"data", &allowed_peer).await);
+        let data = hostbound_sign_data(&key, b"other session", &allowed_host);
+        assert!(!agent.confirm(key.clone(), &data, &allowed_peer).await);
+
+        // without a session-bind, nothing shows where the key is used
+        let data = hostbound_sign_data(&key, b"session", &allowed_host);
+        assert!(!agent.confirm(key.clone(), &data, &peer(3)).await);
+
+        let other_host = other_host.to_bytes().unwrap();
+        let other_peer = peer(2);
+        other_peer.add_session_bind(other_host.clone(), b"session".to_vec(), false);
+        let data = hostbound_sign_data(&key, b"session", &other_host);
+        assert!(!agent.confirm(key, &data, &other_peer).await);
+    }
+
+    #[test]
+    fn test_key_with_invalid_destination_constraint_is_not_loaded() {
+        let (mut agent, _request_rx, _response_tx) = test_agent();
+        let mut key = vault_key(ApprovalPolicy::NeverAsk);
+        key.destination_constraints = vec![DestinationConstraint {
+            from_host_key: None,
+            to_host_key: "invalid".to_string(),
+        }];
+        agent.set_keys(vec![key]).unwrap();
+
+        assert!(loaded_keys(&agent).is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_requests_are_audited() {
+        let (mut agent, request_rx, response_tx) = test_agent();
+        fake_ui(request_rx, response_tx, false, None);
+        agent
+            .set_keys(vec![vault_key(ApprovalPolicy::AlwaysAsk)])
+            .unwrap();
+        agent.needs_unlock.store(false, Ordering::Relaxed);
+        let key = loaded_key(&agent);
+
+        assert!(agent.can_list(&peer(1)).await);
+        assert!(!agent.confirm(key.clone(), &sign_data(&key), &peer(1)).await);
+
+        let entries = agent.audit_entries(10);
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].record.kind, AuditEventKind::Sign);
+        assert_eq!(entries[0].record.outcome, ApprovalOutcome::Denied);
+        assert_eq!(entries[0].record.cipher_id.as_deref(), Some("cipher"));
+        assert_eq!(entries[0].record.key_fingerprint, key.fingerprint());
+        assert_eq!(entries[1].record.kind, AuditEventKind::List);
+        assert_eq!(entries[1].record.outcome, ApprovalOutcome::AutoApproved);
+        assert_eq!(entries[0].previous_hash, entries[1].hash);
+    }
+
+    #[tokio::test]
+    async fn test_arbitrary_data_always_prompts() {
+        let (mut agent, request_rx, response_tx) = test_agent();
+        let prompts = fake_ui(request_rx, response_tx, true, None);
+        agent
+            .set_keys(vec![vault_key(ApprovalPolicy::NeverAsk)])
+            .unwrap();
+
+        assert!(
+            agent
+                .confirm(
+                    loaded_key(&agent),
+                    &sign_data(&loaded_key(&agent)),
+                    &peer(1)
+                )
+                .await
+        );
+        assert_eq!(prompts.load(Ordering::Relaxed), 0);
+
+        assert!(
+            agent
+                .confirm(loaded_key(&agent), b"data to be signed", &peer(1))
+                .await
+        );
+        assert_eq!(prompts.load(Ordering::Relaxed), 1);
+    }
+
+    #[tokio::test]
+    async fn test_forwarded_request_is_denied_by_default() {
+        let (mut agent, _request_rx, _response_tx) = test_agent();
+        agent
+            .set_keys(vec![vault_key(ApprovalPolicy::NeverAsk)])
+            .unwrap();
+        agent.needs_unlock.store(false, Ordering::Relaxed);
+        let key = loaded_key(&agent);
+
+        assert!(agent.confirm(key.clone(), &sign_data(&key), &peer(1)).await);
+        assert!(
+            !agent
+                .confirm(key.clone(), &sign_data(&key), &forwarded_peer(1))
+                .await
+        );
+        assert!(agent.can_list(&peer(1)).await);
+        assert!(!agent.can_list(&forwarded_peer(1)).await);
+    }
+
+    #[tokio::test]
+    async fn test_forwarding_allowed_key_follows_approval_policy() {
+        let (mut agent, request_rx, response_tx) = test_agent();
+        let prompts = fake_ui(request_rx, response_tx, true, None);
+        let mut vault_key = vault_key(ApprovalPolicy::NeverAsk);
+        vault_key.forwarding_policy = ForwardingPolicy::Allowed;
+        agent.set_keys(vec![vault_key]).unwrap();
+        agent.needs_unlock.store(false, Ordering::Relaxed);
+        let key = loaded_key(&agent);
+
+        assert!(
+            agent
+                .confirm(key.clone(), &sign_data(&key), &forwarded_peer(1))
+                .await
+        );
+        assert!(agent.can_list(&forwarded_peer(1)).await);
+        assert_eq!(prompts.load(Ordering::Relaxed), 0);
+    }
+
+    #[tokio::test]
+    async fn test_keys_with_a_policy_must_be_confirmed() {
+        let cases: [(ApprovalPolicy, bool, bool); 4] = [
+            (ApprovalPolicy::FollowAppSetting, false, false),
+            (ApprovalPolicy::FollowAppSetting, true, true),
+            (ApprovalPolicy::AlwaysAsk, false, true),
+            (ApprovalPolicy::AskOncePerProcess, false, true),
+        ];
+        for (policy, is_arbitrary_data, must_confirm) in cases {
+            let (mut agent, mut request_rx, _response_tx) = test_agent();
+            agent.set_keys(vec![vault_key(policy)]).unwrap();
+            let key = loaded_key(&agent);
+            let data = if is_arbitrary_data {
+                b"data to be signed".to_vec()
+            } else {
+                sign_data(&key)
+            };
+
+            let cloned_agent = agent.clone();
+            tokio::spawn(async move { cloned_agent.confirm(key, &data, &peer(1)).await });
+            let Some(SshAgentUIMessage::Request(request)) = request_rx.recv().await else {
+                panic!("Expected a UI request");
+            };
+            assert_eq!(request.must_confirm, must_confirm, "{policy:?}");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_forwarding_requires_confirmation_despite_approval_policy() {
+        let (mut agent, request_rx, response_tx) = test_agent();
+        let prompts = fake_ui(request_rx, response_tx, true, Some(Duration::from_secs(60)));
+        let mut vault_key = vault_key(ApprovalPolicy::NeverAsk);
+        vault_key.forwarding_policy = ForwardingPolicy::RequireConfirmation;
+        agent.set_keys(vec![vault_key]).unwrap();
+        let key = loaded_key(&agent);
+
+        assert!(agent.confirm(key.clone(), &sign_data(&key), &peer(1)).await);
+        assert_eq!(prompts.load(Ordering::Relaxed), 0);
+
+        // remembered approvals do not skip the confirmation either
+        let forwarded_peer = forwarded_peer(1);
+        assert!(
+            agent
+                .confirm(key.clone(), &sign_data(&key), &forwarded_peer)
+                .await
+        );
+        assert!(
+            agent
+                .confirm(key.clone(), &sign_data(&key), &forwarded_peer)
+                .await
+        );
+        assert_eq!(prompts.load(Ordering::Relaxed), 2);
+    }
+
+    #[tokio::test]
+    async fn test_trusted_executable_signs_without_prompt() {
+        let (mut agent, request_rx, response_tx) = test_agent();
+        let prompts = fake_ui(request_rx, response_tx, true, None);
+        agent
+            .set_keys(vec![vault_key(ApprovalPolicy::AlwaysAsk)])
+            .unwrap();
+        let key = loaded_key(&agent);
+        let exe_file = std::env::temp_dir().join(format!("builder-{}", std::process::id()));
+        std::fs::write(&exe_file, "builder").unwrap();
+        agent.set_trusted_executables(vec![TrustedExecutable {
+            path: "/usr/bin/builder".into(),
+            sha256: executable_sha256(&exe_file).unwrap(),
+            cipher_ids: vec!["cipher".to_string()],
+        }]);
+        let builder = || {
+            peer(1).with_details(peerinfo::models::ProcessDetails {
+                exe_path: Some("/usr/bin/builder".into()),
+                exe_file: Some(exe_file.clone()),
+                ..Default::default()
+            })
+        };
+
+        assert!(
+            agent
+                .confirm(key.clone(), &sign_data(&key), &builder())
+                .await
+        );
+        assert_eq!(prompts.load(Ordering::Relaxed), 0);
+
+        // a replaced binary is no longer trusted
+        std::fs::write(&exe_file, "replaced builder").unwrap();
+        assert!(
+            agent
+                .confirm(key.clone(), &sign_data(&key), &builder())
+                .await
+        );
+        assert_eq!(prompts.load(Ordering::Relaxed), 1);
+        std::fs::remove_file(exe_file).unwrap();
+    }
+
+    /// Starts `count` list requests from different processes and returns their results.
+    fn spawn_list_requests(
+        agent: &BitwardenDesktopAgent,
+        count: u32,
+        process_name: impl Fn(u32) -> String,
+    ) -> Vec<tokio::task::JoinHandle<bool>> {
+        (0..count)
+            .map(|pid| {
+                let agent = agent.clone();
+                let peer = peerinfo::models::PeerInfo::new(1000, pid, process_name(pid));
+                tokio::spawn(async move { agent.can_list(&peer).await })
+            })
+            .collect()
+    }
+
+    async fn settle() {
+        for _ in 0..10 {
+            tokio::task::yield_now().await;
+        }
+    }
+
+    #[tokio::test]
+    async fn test_identical_requests_share_a_prompt() {
+        let (agent, mut request_rx, response_tx) = test_agent();
+        let requests = spawn_list_requests(&agent, 4, |_| "ssh".to_string());
+        settle().await;
+
+        let request = request_rx.recv().await.unwrap();
+        assert!(request_rx.try_recv().is_err());
+        response_tx
+            .send(SshAgentUIResponse {
+                request_id: request.request_id(),
+                accepted: true,
+                remember_for: None,
+            })
+            .unwrap();
+        for request in requests {
+            assert!(request.await.unwrap());
+        }
+
+        // the prompt is answered, so the next request is shown again
+        let requests = spawn_list_requests(&agent, 1, |_| "ssh".to_string());
+        settle().await;
+        assert!(request_rx.try_recv().is_ok());
+        drop(response_tx);
+        assert!(!requests.into_iter().next().unwrap().await.unwrap());
+    }
+
+    fn sshsig_data(message_hash: &[u8]) -> Vec<u8> {
+        let mut data = b"SSHSIG".to_vec();
+        "git".encode(&mut data).unwrap();
+        "".encode(&mut data).unwrap();
+        "sha512".encode(&mut data).unwrap();
+        message_hash.encode(&mut data).unwrap();
+        data
+    }
+
+    #[tokio::test]
+    async fn test_signatures_of_different_messages_do_not_share_a_prompt() {
+        let (mut agent, mut request_rx, response_tx) = test_agent();
+        agent
+            .set_keys(vec![vault_key(ApprovalPolicy::AlwaysAsk)])
+            .unwrap();
+        let key = loaded_key(&agent);
+        let requests: Vec<_> = [[1; 64], [2; 64]]
+            .into_iter()
+            .map(|message_hash| {
+                let agent = agent.clone();
+                let key = key.clone();
+                tokio::spawn(async move {
+                    agent
+                        .confirm(key, &sshsig_data(&message_hash), &peer(1))
+                        .await
+                })
+            })
+            .collect();
+        settle().await;
+
+        for _ in 0..2 {
+            let Some(SshAgentUIMessage::Request(request)) = request_rx.recv().await else {
+                panic!("Expected a UI request");
+            };
+            response_tx
+                .send(SshAgentUIResponse {
+                    request_id: request.request_id,
+                    accepted: true,
+                    remember_for: None,
+                })
+                .unwrap();
+        }
+        for request in requests {
+            assert!(request.await.unwrap());
+        }
+        assert!(request_rx.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_excess_prompts_are_denied() {
+        let (agent, mut request_rx, response_tx) = test_agent();
+        let requests = spawn_list_requests(&agent, prompts::MAX_PENDING_PROMPTS as u32, |pid| {
+            format!("ssh {pid}")
+        });
+        settle().await;
+
+        assert!(!agent.can_list(&peer(100)).await);
+
+        for _ in 0..prompts::MAX_PENDING_PROMPTS {
+            let request = request_rx.recv().await.unwrap();
+            response_tx
+                .send(SshAgentUIResponse {
+                    request_id: request.request_id(),
+                    accepted: true,
+                    remember_for: None,
+                })
+                .unwrap();
+        }
+        for request in requests {
+            assert!(request.await.unwrap());
+        }
+        assert!(request_rx.try_recv().is_err());
+    }
+
+    /// Starts a list request that prompts, and returns the id of the request shown to the UI.
+    async fn start_list_request(
+        agent: &BitwardenDesktopAgent,
+        request_rx: &mut tokio::sync::mpsc::Receiver<SshAgentUIMessage>,
+        peer: peerinfo::models::PeerInfo,
+    ) -> (tokio::task::JoinHandle<bool>, u32) {
+        let cloned_agent = agent.clone();
+        let request = tokio::spawn(async move { cloned_agent.can_list(&peer).await });
+        let Some(SshAgentUIMessage::Request(ui_request)) = request_rx.recv().await else {
+            panic!("Expected a UI request");
+        };
+        (request, ui_request.request_id)
+    }
+
+    async fn assert_cancelled(
+        request_rx: &mut tokio::sync::mpsc::Receiver<SshAgentUIMessage>,
+        request_id: u32,
+    ) {
+        match request_rx.recv().await {
+            Some(SshAgentUIMessage::Cancel { request_id: id }) => assert_eq!(id, request_id),
+            _ => panic!("Expected a cancellation"),
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_prompt_times_out() {
+        let (agent, mut request_rx, _response_tx) = test_agent();
+        agent.set_prompt_timeout(Duration::from_secs(10));
+
+        let (request, request_id) = start_list_request(&agent, &mut request_rx, peer(1)).await;
+
+        assert!(!request.await.unwrap());
+        assert_cancelled(&mut request_rx, request_id).await;
+    }
+
+    #[tokio::test]
+    async fn test_prompt_is_cancelled_when_client_disconnects() {
+        let (agent, mut request_rx, _response_tx) = test_agent();
+        let peer = peer(1);
+
+        let (request, request_id) = start_list_request(&agent, &mut request_rx, peer.clone()).await;
+        peer.set_disconnected();
+
+        assert!(!request.await.unwrap());
+        assert_cancelled(&mut request_rx, request_id).await;
+    }
+
+    #[tokio::test]
+    async fn test_shared_prompt_outlives_the_first_client() {
+        let (agent, mut request_rx, response_tx) = test_agent();
+        let (first_peer, second_peer) = (peer(1), peer(2));
+
+        let (first, request_id) =
+            start_list_request(&agent, &mut request_rx, first_peer.clone()).await;
+        let cloned_agent = agent.clone();
+        let waiting_peer = second_peer.clone();
+        let second = tokio::spawn(async move { cloned_agent.can_list(&waiting_peer).await });
+        settle().await;
+
+        first_peer.set_disconnected();
+        settle().await;
+        assert!(request_rx.try_recv().is_err());
+
+        response_tx
+            .send(SshAgentUIResponse {
+                request_id,
+                accepted: true,
+                remember_for: None,
+            })
+            .unwrap();
+        assert!(first.await.unwrap());
+        assert!(second.await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_shared_prompt_is_cancelled_when_all_clients_disconnect() {
+        let (agent, mut request_rx, _response_tx) = test_agent();
+        let (first_peer, second_peer) = (peer(1), peer(2));
+
+        let (first, request_id) =
+            start_list_request(&agent, &mut request_rx, first_peer.clone()).await;
+        let cloned_agent = agent.clone();
+        let waiting_peer = second_peer.clone();
+        let second = tokio::spawn(async move { cloned_agent.can_list(&waiting_peer).await });
+        settle().await;
+
+        first_peer.set_disconnected();
+        second_peer.set_disconnected();
+
+        assert!(!first.await.unwrap());
+        assert!(!second.await.unwrap());
+        assert_cancelled(&mut request_rx, request_id).await;
+    }
+
+    #[tokio::test]
+    async fn test_prompt_is_cancelled_when_agent_is_locked() {
+        let (mut agent, mut request_rx, _response_tx) = test_agent();
+
+        let (request, request_id) = start_list_request(&agent, &mut request_rx, peer(1)).await;
+        agent.lock().unwrap();
+
+        assert!(!request.await.unwrap());
+        assert_cancelled(&mut request_rx, request_id).await;
+    }
+
+    #[tokio::test]
+    async fn test_closed_ui_channel_denies_requests() {
+        let (mut agent, request_rx, response_tx) = test_agent();
+        agent
+            .set_keys(vec![vault_key(ApprovalPolicy::AlwaysAsk)])
+            .unwrap();
+        let key = loaded_key(&agent);
+        drop(request_rx);
+
+        assert!(!agent.can_list(&peer(1)).await);
+        assert!(!agent.confirm(key.clone(), &sign_data(&key), &peer(1)).await);
+        drop(response_tx);
+        assert!(!agent.can_list(&peer(1)).await);
+    }
+}
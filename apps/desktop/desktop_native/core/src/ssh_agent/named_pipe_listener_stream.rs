@@ -19,7 +19,7 @@ use windows::Win32::{Foundation::HANDLE, System::Pipes::GetNamedPipeClientProces
 
 use crate::ssh_agent::peerinfo::{self, models::PeerInfo};
 
-const PIPE_NAME: &str = r"\\.\pipe\openssh-ssh-agent";
+pub(crate) const PIPE_NAME: &str = r"\\.\pipe\openssh-ssh-agent";
 
 #[pin_project::pin_project]
 pub struct NamedPipeServerStream {
@@ -1,10 +1,165 @@
-use sysinfo::{Pid, System};
-
 use super::models::PeerInfo;
 
-pub fn get_peer_info(peer_pid: u32) -> Result<PeerInfo, String> {
-    let s = System::new_all();
-    if let Some(process) = s.process(Pid::from_u32(peer_pid)) {
+/// Parent processes are followed up to this depth, which covers chains like
+/// terminal → shell → git → ssh.
+const MAX_PARENTS: usize = 8;
+
+#[cfg(target_os = "linux")]
+pub use linux::get_peer_info;
+#[cfg(not(target_os = "linux"))]
+pub use sysinfo_process::get_peer_info;
+
+/// Reads the details of the single peer process from `/proc`, instead of scanning all processes.
+#[cfg(target_os = "linux")]
+mod linux {
+    use std::{
+        fs,
+        path::{Path, PathBuf},
+    };
+
+    use super::{PeerInfo, MAX_PARENTS};
+    use crate::ssh_agent::peerinfo::models::{ParentProcess, ProcessDetails, Sandbox};
+
+    /// The fields of `/proc/<pid>/stat` that are used.
+    #[derive(Debug, PartialEq, Eq)]
+    pub(super) struct Stat {
+        pub(super) name: String,
+        pub(super) ppid: u32,
+        /// Clock ticks since boot
+        pub(super) start_time: u64,
+    }
+
+    pub fn get_peer_info(peer_pid: u32) -> Result<PeerInfo, String> {
+        let proc_dir = proc_dir(peer_pid);
+        let stat = read_stat(&proc_dir).ok_or("Failed to get process".to_string())?;
+        let uid = read_uid(&proc_dir).ok_or("Failed to get process owner".to_string())?;
+
+        let details = ProcessDetails {
+            exe_path: fs::read_link(proc_dir.join("exe")).ok(),
+            exe_file: Some(proc_dir.join("exe")),
+            cmdline: read_cmdline(&proc_dir),
+            cwd: fs::read_link(proc_dir.join("cwd")).ok(),
+            start_time: start_time_to_unix(stat.start_time),
+            parents: read_parents(stat.ppid),
+            sandbox: detect_sandbox(&proc_dir),
+        };
+        Ok(PeerInfo::new(uid, peer_pid, stat.name).with_details(details))
+    }
+
+    fn proc_dir(pid: u32) -> PathBuf {
+        PathBuf::from(format!("/proc/{pid}"))
+    }
+
+    fn read_stat(proc_dir: &Path) -> Option<Stat> {
+        parse_stat(&fs::read_to_string(proc_dir.join("stat")).ok()?)
+    }
+
+    /// Parses `/proc/<pid>/stat`. The process name is enclosed in parentheses and may itself
+    /// contain spaces and parentheses, so the fields are counted from the last `)`.
+    pub(super) fn parse_stat(stat: &str) -> Option<Stat> {
+        let (head, fields) = stat.rsplit_once(')')?;
+        let (_, name) = head.split_once('(')?;
+        let fields: Vec<&str> = fields.split_whitespace().collect();
+        Some(Stat {
+            name: name.to_string(),
+            // fields 4 and 22 of proc_pid_stat(5), the first field after the name is field 3
+            ppid: fields.get(1)?.parse().ok()?,
+            start_time: fields.get(19)?.parse().ok()?,
+        })
+    }
+
+    fn read_uid(proc_dir: &Path) -> Option<u32> {
+        let status = fs::read_to_string(proc_dir.join("status")).ok()?;
+        parse_uid(&status)
+    }
+
+    /// Returns the real uid from `/proc/<pid>/status`.
+    pub(super) fn parse_uid(status: &str) -> Option<u32> {
+        status
+            .lines()
+            .find_map(|line| line.strip_prefix("Uid:"))?
+            .split_whitespace()
+            .next()?
+            .parse()
+            .ok()
+    }
+
+    fn read_cmdline(proc_dir: &Path) -> Vec<String> {
+        fs::read(proc_dir.join("cmdline"))
+            .map(|cmdline| parse_cmdline(&cmdline))
+            .unwrap_or_default()
+    }
+
+    pub(super) fn parse_cmdline(cmdline: &[u8]) -> Vec<String> {
+        cmdline
+            .split(|&byte| byte == 0)
+            .filter(|arg| !arg.is_empty())
+            .map(|arg| String::from_utf8_lossy(arg).to_string())
+            .collect()
+    }
+
+    fn read_parents(mut pid: u32) -> Vec<ParentProcess> {
+        let mut parents = Vec::new();
+        // pid 1 is init and pid 0 means there is no parent
+        while pid > 1 && parents.len() < MAX_PARENTS {
+            let proc_dir = proc_dir(pid);
+            let Some(stat) = read_stat(&proc_dir) else {
+                break;
+            };
+            parents.push(ParentProcess {
+                pid,
+                name: stat.name,
+                cmdline: read_cmdline(&proc_dir),
+            });
+            pid = stat.ppid;
+        }
+        parents
+    }
+
+    fn start_time_to_unix(start_time: u64) -> Option<u64> {
+        // SAFETY: sysconf has no preconditions
+        let ticks_per_second = u64::try_from(unsafe { libc::sysconf(libc::_SC_CLK_TCK) }).ok()?;
+        if ticks_per_second == 0 {
+            return None;
+        }
+        let boot_time = fs::read_to_string("/proc/stat")
+            .ok()?
+            .lines()
+            .find_map(|line| line.strip_prefix("btime "))?
+            .trim()
+            .parse::<u64>()
+            .ok()?;
+        Some(boot_time + start_time / ticks_per_second)
+    }
+
+    fn detect_sandbox(proc_dir: &Path) -> Option<Sandbox> {
+        if proc_dir.join("root/.flatpak-info").exists() {
+            return Some(Sandbox::Flatpak);
+        }
+
+        let cgroup = fs::read_to_string(proc_dir.join("cgroup")).unwrap_or_default();
+        if cgroup.contains("/snap.") {
+            return Some(Sandbox::Snap);
+        }
+
+        let pid_namespace = fs::read_link(proc_dir.join("ns/pid")).ok()?;
+        let own_pid_namespace = fs::read_link("/proc/self/ns/pid").ok()?;
+        (pid_namespace != own_pid_namespace).then_some(Sandbox::Container)
+    }
+}
+
+/// Looks up the peer process with sysinfo, refreshing only that process and its parents.
+#[cfg(not(target_os = "linux"))]
+mod sysinfo_process {
+    use sysinfo::{Pid, Process, ProcessRefreshKind, ProcessesToUpdate, System, UpdateKind};
+
+    use super::{PeerInfo, MAX_PARENTS};
+    use crate::ssh_agent::peerinfo::models::{ParentProcess, ProcessDetails};
+
+    pub fn get_peer_info(peer_pid: u32) -> Result<PeerInfo, String> {
+        let mut system = System::new();
+        let process = refresh_process(&mut system, Pid::from_u32(peer_pid))
+            .ok_or("Failed to get process".to_string())?;
         let peer_process_name = match process.name().to_str() {
             Some(name) => name.to_string(),
             None => {
@@ -12,12 +167,119 @@ pub fn get_peer_info(peer_pid: u32) -> Result<PeerInfo, String> {
             }
         };
 
-        return Ok(PeerInfo::new(
-            peer_pid,
-            process.pid().as_u32(),
-            peer_process_name,
-        ));
+        let uid = process_uid(process);
+        let mut details = ProcessDetails {
+            exe_path: process.exe().map(|path| path.to_path_buf()),
+            exe_file: process.exe().map(|path| path.to_path_buf()),
+            cmdline: command_line(process),
+            cwd: process.cwd().map(|path| path.to_path_buf()),
+            start_time: Some(process.start_time()),
+            parents: Vec::new(),
+            sandbox: None,
+        };
+
+        let mut parent_pid = process.parent();
+        while let Some(pid) = parent_pid {
+            if details.parents.len() >= MAX_PARENTS {
+                break;
+            }
+            let Some(parent) = refresh_process(&mut system, pid) else {
+                break;
+            };
+            details.parents.push(ParentProcess {
+                pid: pid.as_u32(),
+                name: parent.name().to_string_lossy().to_string(),
+                cmdline: command_line(parent),
+            });
+            parent_pid = parent.parent();
+        }
+
+        Ok(PeerInfo::new(uid, peer_pid, peer_process_name).with_details(details))
+    }
+
+    fn refresh_process(system: &mut System, pid: Pid) -> Option<&Process> {
+        let refresh_kind = ProcessRefreshKind::nothing()
+            .with_exe(UpdateKind::OnlyIfNotSet)
+            .with_cmd(UpdateKind::OnlyIfNotSet)
+            .with_cwd(UpdateKind::OnlyIfNotSet)
+            .with_user(UpdateKind::OnlyIfNotSet);
+        system.refresh_processes_specifics(ProcessesToUpdate::Some(&[pid]), false, refresh_kind);
+        system.process(pid)
     }
 
-    Err("Failed to get process".to_string())
+    fn command_line(process: &Process) -> Vec<String> {
+        process
+            .cmd()
+            .iter()
+            .map(|arg| arg.to_string_lossy().to_string())
+            .collect()
+    }
+
+    #[cfg(unix)]
+    fn process_uid(process: &Process) -> u32 {
+        process.user_id().map(|uid| **uid).unwrap_or_default()
+    }
+
+    // Windows identifies users by SID, which does not fit into a uid
+    #[cfg(not(unix))]
+    fn process_uid(_process: &Process) -> u32 {
+        0
+    }
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use super::linux::*;
+    use super::*;
+    use crate::ssh_agent::trusted::executable_sha256;
+
+    #[test]
+    fn test_parse_stat() {
+        let stat = "1234 (my (weird) name) S 42 1234 1234 0 -1 4194560 1 0 0 0 0 0 0 0 20 0 1 0 \
+                    98765 1000 100 18446744073709551615";
+
+        assert_eq!(
+            parse_stat(stat),
+            Some(Stat {
+                name: "my (weird) name".to_string(),
+                ppid: 42,
+                start_time: 98765,
+            })
+        );
+        assert_eq!(parse_stat("1234 (truncated) S 42"), None);
+    }
+
+    #[test]
+    fn test_parse_uid() {
+        let status = "Name:\tssh\nPid:\t1234\nUid:\t1000\t1001\t1002\t1003\n";
+        assert_eq!(parse_uid(status), Some(1000));
+        assert_eq!(parse_uid("Name:\tssh\n"), None);
+    }
+
+    #[test]
+    fn test_parse_cmdline() {
+        assert_eq!(
+            parse_cmdline(b"git\0commit\0-m\0message\0"),
+            vec!["git", "commit", "-m", "message"]
+        );
+        assert!(parse_cmdline(b"").is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_get_own_peer_info() {
+        let peer_info = get_peer_info(std::process::id()).unwrap();
+        let details = peer_info.details();
+
+        assert_eq!(peer_info.pid(), std::process::id());
+        assert_eq!(details.exe_path, Some(std::env::current_exe().unwrap()));
+        assert_eq!(
+            peer_info.exe_sha256().await,
+            Some(executable_sha256(&std::env::current_exe().unwrap()).unwrap())
+        );
+        assert_eq!(details.cwd, Some(std::env::current_dir().unwrap()));
+        assert!(!details.cmdline.is_empty());
+        assert!(details.start_time.is_some());
+        assert!(details.sandbox.is_none());
+        assert!(details.parents.len() <= MAX_PARENTS);
+    }
 }
@@ -1,4 +1,63 @@
-use std::sync::{atomic::AtomicBool, Arc, Mutex};
+use std::{
+    path::{Path, PathBuf},
+    sync::{atomic::AtomicBool, Arc, Mutex},
+};
+
+use tokio::sync::OnceCell;
+use tokio_util::sync::CancellationToken;
+
+use crate::ssh_agent::trusted::executable_sha256;
+
+/**
+* A session-bind received on a connection, binding it to the host key of an SSH session.
+*/
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SessionBind {
+    pub host_key: Vec<u8>,
+    /// Exchange hash of the SSH session, which userauth requests on the session sign
+    pub session_id: Vec<u8>,
+    pub is_forwarding: bool,
+}
+
+/**
+* Sandbox that a peer process runs in.
+*/
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Sandbox {
+    Flatpak,
+    Snap,
+    /// Any other process running in a separate PID namespace, e.g. Docker or Podman
+    Container,
+}
+
+/**
+* A parent of the peer process.
+*/
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParentProcess {
+    pub pid: u32,
+    pub name: String,
+    pub cmdline: Vec<String>,
+}
+
+/**
+* Details about the peer process, where they could be determined.
+*/
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ProcessDetails {
+    pub exe_path: Option<PathBuf>,
+    /// File that is hashed to check trusted executables. On Linux this is `/proc/<pid>/exe`,
+    /// which is the binary that is actually running, even if the file at its path was replaced
+    /// since.
+    pub exe_file: Option<PathBuf>,
+    pub cmdline: Vec<String>,
+    pub cwd: Option<PathBuf>,
+    /// Unix time in seconds
+    pub start_time: Option<u64>,
+    /// Parent processes, closest first
+    pub parents: Vec<ParentProcess>,
+    pub sandbox: Option<Sandbox>,
+}
 
 /**
 * Peerinfo represents the information of a peer process connecting over a socket.
@@ -9,8 +68,12 @@ pub struct PeerInfo {
     uid: u32,
     pid: u32,
     process_name: String,
+    details: Arc<ProcessDetails>,
+    exe_sha256: Arc<OnceCell<Option<String>>>,
     is_forwarding: Arc<AtomicBool>,
     host_key: Arc<Mutex<Vec<u8>>>,
+    session_binds: Arc<Mutex<Vec<SessionBind>>>,
+    disconnected: CancellationToken,
 }
 
 impl PeerInfo {
@@ -19,8 +82,12 @@ impl PeerInfo {
             uid,
             pid,
             process_name,
+            details: Arc::new(ProcessDetails::default()),
+            exe_sha256: Arc::new(OnceCell::new()),
             is_forwarding: Arc::new(AtomicBool::new(false)),
             host_key: Arc::new(Mutex::new(Vec::new())),
+            session_binds: Arc::new(Mutex::new(Vec::new())),
+            disconnected: CancellationToken::new(),
         }
     }
 
@@ -29,8 +96,12 @@ impl PeerInfo {
             uid: 0,
             pid: 0,
             process_name: "Unknown application".to_string(),
+            details: Arc::new(ProcessDetails::default()),
+            exe_sha256: Arc::new(OnceCell::new()),
             is_forwarding: Arc::new(AtomicBool::new(false)),
             host_key: Arc::new(Mutex::new(Vec::new())),
+            session_binds: Arc::new(Mutex::new(Vec::new())),
+            disconnected: CancellationToken::new(),
         }
     }
 
@@ -46,6 +117,50 @@ impl PeerInfo {
         &self.process_name
     }
 
+    pub fn with_details(mut self, details: ProcessDetails) -> Self {
+        self.details = Arc::new(details);
+        self
+    }
+
+    pub fn details(&self) -> &ProcessDetails {
+        &self.details
+    }
+
+    /// SHA-256 of the executable, in hex. Hashing reads the whole binary, so it only happens when
+    /// a trusted executable needs it, at most once per connection.
+    pub async fn exe_sha256(&self) -> Option<String> {
+        self.exe_sha256
+            .get_or_init(|| async {
+                let exe_file = self.details.exe_file.clone()?;
+                tokio::task::spawn_blocking(move || executable_sha256(&exe_file).ok())
+                    .await
+                    .ok()
+                    .flatten()
+            })
+            .await
+            .clone()
+    }
+
+    /// Describes the peer for prompts, e.g. "ssh launched by git commit in ~/src/foo".
+    pub fn description(&self) -> String {
+        let mut description = self.process_name.clone();
+        if let Some(parent) = self.details.parents.first() {
+            description.push_str(" launched by ");
+            description.push_str(&command_summary(parent));
+        }
+        if let Some(ref cwd) = self.details.cwd {
+            description.push_str(" in ");
+            description.push_str(&display_path(cwd, dirs::home_dir().as_deref()));
+        }
+        match self.details.sandbox {
+            Some(Sandbox::Flatpak) => description.push_str(" (Flatpak)"),
+            Some(Sandbox::Snap) => description.push_str(" (Snap)"),
+            Some(Sandbox::Container) => description.push_str(" (container)"),
+            None => {}
+        }
+        description
+    }
+
     pub fn is_forwarding(&self) -> bool {
         self.is_forwarding
             .load(std::sync::atomic::Ordering::Relaxed)
@@ -64,4 +179,111 @@ impl PeerInfo {
     pub fn host_key(&self) -> Vec<u8> {
         self.host_key.lock().expect("Mutex is not poisoned").clone()
     }
+
+    /// Records a session-bind. Each hop of a forwarded agent connection binds in turn, so the
+    /// most recent bind is the host the client is authenticating to.
+    pub fn add_session_bind(&self, host_key: Vec<u8>, session_id: Vec<u8>, is_forwarding: bool) {
+        self.set_forwarding(is_forwarding);
+        self.set_host_key(host_key.clone());
+        self.session_binds
+            .lock()
+            .expect("Mutex is not poisoned")
+            .push(SessionBind {
+                host_key,
+                session_id,
+                is_forwarding,
+            });
+    }
+
+    pub fn session_binds(&self) -> Vec<SessionBind> {
+        self.session_binds
+            .lock()
+            .expect("Mutex is not poisoned")
+            .clone()
+    }
+
+    /// Marks the connection of the peer as closed.
+    pub fn set_disconnected(&self) {
+        self.disconnected.cancel();
+    }
+
+    /// Completes once the connection of the peer is closed.
+    pub async fn wait_for_disconnect(&self) {
+        self.disconnected.cancelled().await;
+    }
+}
+
+/// The program name and its subcommand, if any, e.g. "git commit".
+fn command_summary(process: &ParentProcess) -> String {
+    let mut args = process.cmdline.iter();
+    let program = args
+        .next()
+        .and_then(|program| Path::new(program).file_name())
+        .map(|program| program.to_string_lossy().to_string())
+        .unwrap_or_else(|| process.name.clone());
+    match args.next() {
+        Some(subcommand) if !subcommand.starts_with('-') => format!("{program} {subcommand}"),
+        _ => program,
+    }
+}
+
+fn display_path(path: &Path, home: Option<&Path>) -> String {
+    match home.and_then(|home| path.strip_prefix(home).ok()) {
+        Some(relative) if relative.as_os_str().is_empty() => "~".to_string(),
+        Some(relative) => format!("~/{}", relative.display()),
+        None => path.display().to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parent(name: &str, cmdline: &[&str]) -> ParentProcess {
+        ParentProcess {
+            pid: 2,
+            name: name.to_string(),
+            cmdline: cmdline.iter().map(|arg| arg.to_string()).collect(),
+        }
+    }
+
+    #[test]
+    fn test_description() {
+        let peer = PeerInfo::new(1000, 3, "ssh".to_string()).with_details(ProcessDetails {
+            cwd: Some(PathBuf::from("/src/foo")),
+            parents: vec![parent("git", &["/usr/bin/git", "commit", "-m", "message"])],
+            ..Default::default()
+        });
+
+        assert_eq!(peer.description(), "ssh launched by git commit in /src/foo");
+    }
+
+    #[test]
+    fn test_description_without_details() {
+        assert_eq!(
+            PeerInfo::new(1000, 3, "ssh".to_string()).description(),
+            "ssh"
+        );
+    }
+
+    #[test]
+    fn test_command_summary_skips_options() {
+        assert_eq!(command_summary(&parent("bash", &["-bash"])), "-bash");
+        assert_eq!(
+            command_summary(&parent("ssh", &["ssh", "-A", "host"])),
+            "ssh"
+        );
+        assert_eq!(command_summary(&parent("code", &[])), "code");
+    }
+
+    #[test]
+    fn test_display_path_abbreviates_home() {
+        let home = Path::new("/home/user");
+        assert_eq!(
+            display_path(Path::new("/home/user/src/foo"), Some(home)),
+            "~/src/foo"
+        );
+        assert_eq!(display_path(Path::new("/home/user"), Some(home)), "~");
+        assert_eq!(display_path(Path::new("/tmp"), Some(home)), "/tmp");
+    }
 }
@@ -0,0 +1,187 @@
+use std::{
+    collections::HashMap,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+};
+
+use futures::future::join_all;
+use tokio::sync::watch;
+
+use super::{peerinfo::models::PeerInfo, SshAgentUIRequest, SshAgentUIResponse};
+
+/// Requests beyond this number of prompts shown at the same time are denied without a prompt.
+pub(crate) const MAX_PENDING_PROMPTS: usize = 8;
+
+/// Identifies requests that can share a single prompt: the same key and request details from the
+/// same client program. SSHSIG requests only share a prompt when they sign the same message, as
+/// the prompt shows the message digest.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub(crate) struct PromptKey {
+    /// Cipher id of vault keys, fingerprint of keys added with `ssh-add`
+    key_id: Option<String>,
+    is_list: bool,
+    is_unlock: bool,
+    namespace: Option<String>,
+    user_name: Option<String>,
+    message_hash: Option<Vec<u8>>,
+    uid: u32,
+    process_name: String,
+    exe_path: Option<PathBuf>,
+    is_forwarding: bool,
+    host_key: Vec<u8>,
+}
+
+impl PromptKey {
+    /// Returns `None` for requests that must be confirmed individually, i.e. signing arbitrary
+    /// data.
+    pub(crate) fn new(
+        key_id: Option<&str>,
+        request: &SshAgentUIRequest,
+        message_hash: Option<&[u8]>,
+        peer: &PeerInfo,
+    ) -> Option<Self> {
+        if request.is_arbitrary_data {
+            return None;
+        }
+
+        Some(Self {
+            key_id: key_id.map(str::to_string),
+            is_list: request.is_list,
+            is_unlock: request.is_unlock,
+            namespace: request.namespace.clone(),
+            user_name: request.user_name.clone(),
+            message_hash: message_hash.map(<[u8]>::to_vec),
+            uid: peer.uid(),
+            process_name: peer.process_name().to_string(),
+            exe_path: peer.details().exe_path.clone(),
+            is_forwarding: peer.is_forwarding(),
+            host_key: peer.host_key(),
+        })
+    }
+}
+
+type Answer = watch::Receiver<Option<SshAgentUIResponse>>;
+
+/// The clients waiting for the answer to a prompt.
+#[derive(Debug)]
+pub(crate) struct Waiters(watch::Sender<Vec<PeerInfo>>);
+
+impl Waiters {
+    fn new(peer: &PeerInfo) -> Arc<Self> {
+        Arc::new(Self(watch::Sender::new(vec![peer.clone()])))
+    }
+
+    fn add(&self, peer: &PeerInfo) {
+        self.0.send_modify(|peers| peers.push(peer.clone()));
+    }
+
+    /// Completes once every client waiting for the prompt has disconnected, including clients
+    /// that start waiting later on.
+    pub(crate) async fn all_disconnected(&self) {
+        let mut peers_rx = self.0.subscribe();
+        loop {
+            let peers = peers_rx.borrow_and_update().clone();
+            tokio::select! {
+                _ = join_all(peers.iter().map(PeerInfo::wait_for_disconnect)) => return,
+                // the sender lives as long as `self`, so this only fails once the prompt is gone
+                Ok(()) = peers_rx.changed() => {}
+            }
+        }
+    }
+}
+
+/// The prompts that are currently shown to the user.
+#[derive(Debug, Default)]
+pub(crate) struct PendingPrompts {
+    count: usize,
+    shared: HashMap<PromptKey, (Answer, Arc<Waiters>)>,
+}
+
+pub(crate) enum Prompt {
+    /// No identical request is pending, so the caller shows the prompt and resolves it.
+    Show(PromptGuard, Arc<Waiters>),
+    /// An identical request is pending; its answer applies to this request as well.
+    Wait(Answer),
+    /// Too many prompts are pending.
+    Rejected,
+}
+
+impl PendingPrompts {
+    /// `peer` is added to the clients waiting for the prompt that answers the request.
+    pub(crate) fn acquire(
+        prompts: &Arc<Mutex<Self>>,
+        key: Option<PromptKey>,
+        peer: &PeerInfo,
+    ) -> Prompt {
+        let mut pending = prompts.lock().expect("Mutex is not poisoned");
+        if let Some((answer, waiters)) = key.as_ref().and_then(|key| pending.shared.get(key)) {
+            waiters.add(peer);
+            return Prompt::Wait(answer.clone());
+        }
+        if pending.count >= MAX_PENDING_PROMPTS {
+            return Prompt::Rejected;
+        }
+
+        let (sender, answer) = watch::channel(None);
+        let waiters = Waiters::new(peer);
+        if let Some(ref key) = key {
+            pending
+                .shared
+                .insert(key.clone(), (answer, waiters.clone()));
+        }
+        pending.count += 1;
+        Prompt::Show(
+            PromptGuard {
+                prompts: prompts.clone(),
+                key,
+                sender,
+                is_released: false,
+            },
+            waiters,
+        )
+    }
+}
+
+/// Held while a prompt is shown. Dropping it without an answer denies all waiting requests.
+pub(crate) struct PromptGuard {
+    prompts: Arc<Mutex<PendingPrompts>>,
+    key: Option<PromptKey>,
+    sender: watch::Sender<Option<SshAgentUIResponse>>,
+    is_released: bool,
+}
+
+impl PromptGuard {
+    /// Passes the answer on to the requests that were waiting for this prompt.
+    pub(crate) fn resolve(mut self, response: SshAgentUIResponse) {
+        // requests arriving from now on get a prompt of their own
+        self.release();
+        self.sender.send_replace(Some(response));
+    }
+
+    fn release(&mut self) {
+        if self.is_released {
+            return;
+        }
+        self.is_released = true;
+        let mut pending = self.prompts.lock().expect("Mutex is not poisoned");
+        pending.count -= 1;
+        if let Some(ref key) = self.key {
+            pending.shared.remove(key);
+        }
+    }
+}
+
+impl Drop for PromptGuard {
+    fn drop(&mut self) {
+        self.release();
+    }
+}
+
+/// Waits for the answer to a shared prompt. Returns `None` if the prompt was abandoned.
+pub(crate) async fn wait_for_answer(mut answer: Answer) -> Option<SshAgentUIResponse> {
+    answer
+        .wait_for(Option::is_some)
+        .await
+        .ok()
+        .and_then(|response| response.clone())
+}
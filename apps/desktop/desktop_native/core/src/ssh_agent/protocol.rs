@@ -0,0 +1,102 @@
+use anyhow::anyhow;
+use ssh_encoding::{Decode, Encode};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+// Message numbers of the agent protocol; based on
+// https://datatracker.ietf.org/doc/html/draft-miller-ssh-agent
+pub(crate) const SSH_AGENT_FAILURE: u8 = 5;
+pub(crate) const SSH_AGENT_SUCCESS: u8 = 6;
+pub(crate) const SSH_AGENTC_REQUEST_IDENTITIES: u8 = 11;
+pub(crate) const SSH_AGENT_IDENTITIES_ANSWER: u8 = 12;
+pub(crate) const SSH_AGENTC_SIGN_REQUEST: u8 = 13;
+pub(crate) const SSH_AGENTC_ADD_IDENTITY: u8 = 17;
+pub(crate) const SSH_AGENTC_REMOVE_IDENTITY: u8 = 18;
+pub(crate) const SSH_AGENTC_REMOVE_ALL_IDENTITIES: u8 = 19;
+pub(crate) const SSH_AGENTC_LOCK: u8 = 22;
+pub(crate) const SSH_AGENTC_UNLOCK: u8 = 23;
+pub(crate) const SSH_AGENTC_ADD_ID_CONSTRAINED: u8 = 25;
+
+pub(crate) const SSH_AGENT_CONSTRAIN_LIFETIME: u8 = 1;
+pub(crate) const SSH_AGENT_CONSTRAIN_CONFIRM: u8 = 2;
+
+/// Same limit as OpenSSH's ssh-agent; anything larger is treated as a protocol error.
+const MAX_MESSAGE_LENGTH: usize = 256 * 1024;
+
+/// Reads one length-prefixed message. Returns `None` if the peer closed the connection.
+pub(crate) async fn read_message<R>(reader: &mut R) -> Result<Option<Vec<u8>>, anyhow::Error>
+where
+    R: AsyncRead + Unpin,
+{
+    let length = match reader.read_u32().await {
+        Ok(length) => length as usize,
+        Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+
+    if length == 0 || length > MAX_MESSAGE_LENGTH {
+        return Err(anyhow!("Invalid agent message length {length}"));
+    }
+
+    let mut message = vec![0; length];
+    reader.read_exact(&mut message).await?;
+    Ok(Some(message))
+}
+
+/// Writes one length-prefixed message.
+pub(crate) async fn write_message<W>(writer: &mut W, message: &[u8]) -> Result<(), anyhow::Error>
+where
+    W: AsyncWrite + Unpin,
+{
+    let length = u32::try_from(message.len())?;
+    writer.write_u32(length).await?;
+    writer.write_all(message).await?;
+    writer.flush().await?;
+    Ok(())
+}
+
+/// The reply for requests that only report success or failure.
+pub(crate) fn status_message(success: bool) -> Vec<u8> {
+    if success {
+        vec![SSH_AGENT_SUCCESS]
+    } else {
+        vec![SSH_AGENT_FAILURE]
+    }
+}
+
+/// The reply to `SSH_AGENTC_REQUEST_IDENTITIES`, listing public key blobs and their comments.
+pub(crate) fn identities_answer(identities: &[(Vec<u8>, String)]) -> Vec<u8> {
+    let mut message = vec![SSH_AGENT_IDENTITIES_ANSWER];
+    let count = u32::try_from(identities.len()).expect("Key count fits in u32");
+    count
+        .encode(&mut message)
+        .expect("Encoding to a Vec does not fail");
+    for (public_key, comment) in identities {
+        public_key
+            .encode(&mut message)
+            .expect("Encoding to a Vec does not fail");
+        comment
+            .encode(&mut message)
+            .expect("Encoding to a Vec does not fail");
+    }
+    message
+}
+
+/// Parses the reply to `SSH_AGENTC_REQUEST_IDENTITIES` into public key blobs and comments.
+pub(crate) fn parse_identities_answer(
+    message: &[u8],
+) -> Result<Vec<(Vec<u8>, String)>, anyhow::Error> {
+    let Some((&SSH_AGENT_IDENTITIES_ANSWER, mut reader)) = message.split_first() else {
+        return Err(anyhow!("Not an identities answer"));
+    };
+
+    let count = u32::decode(&mut reader).map_err(|e| anyhow!("Failed to parse count: {e}"))?;
+    let mut identities = Vec::new();
+    for _ in 0..count {
+        let public_key =
+            Vec::<u8>::decode(&mut reader).map_err(|e| anyhow!("Failed to parse key: {e}"))?;
+        let comment =
+            String::decode(&mut reader).map_err(|e| anyhow!("Failed to parse comment: {e}"))?;
+        identities.push((public_key, comment));
+    }
+    Ok(identities)
+}
@@ -1,41 +1,416 @@
-use bytes::{Buf, Bytes};
+use std::fmt::Write;
+
+use anyhow::anyhow;
+
+/// Message number of SSH_MSG_USERAUTH_REQUEST, RFC 4252 section 5
+const SSH_MSG_USERAUTH_REQUEST: u8 = 50;
+
+/// Number of digest bytes shown to the user when confirming an SSHSIG request.
+const DIGEST_PREVIEW_LENGTH: usize = 8;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum SshSigHashAlgorithm {
+    Sha256,
+    Sha512,
+}
+
+impl SshSigHashAlgorithm {
+    fn from_name(name: &[u8]) -> Result<Self, anyhow::Error> {
+        match name {
+            b"sha256" => Ok(Self::Sha256),
+            b"sha512" => Ok(Self::Sha512),
+            _ => Err(anyhow!(
+                "Unsupported hash algorithm {}",
+                String::from_utf8_lossy(name)
+            )),
+        }
+    }
+
+    pub(crate) fn name(&self) -> &'static str {
+        match self {
+            Self::Sha256 => "sha256",
+            Self::Sha512 => "sha512",
+        }
+    }
+
+    fn digest_length(&self) -> usize {
+        match self {
+            Self::Sha256 => 32,
+            Self::Sha512 => 64,
+        }
+    }
+}
 
 #[derive(Debug)]
 pub(crate) struct SshSigRequest {
     pub namespace: String,
+    pub hash_algorithm: SshSigHashAlgorithm,
+    pub message_hash: Vec<u8>,
+}
+
+impl SshSigRequest {
+    /// The start of the message hash in hex, for display next to the namespace.
+    pub(crate) fn digest_preview(&self) -> String {
+        self.message_hash.iter().take(DIGEST_PREVIEW_LENGTH).fold(
+            String::new(),
+            |mut preview, byte| {
+                let _ = write!(preview, "{byte:02x}");
+                preview
+            },
+        )
+    }
+}
+
+/// The data signed by a client to authenticate with a public key; RFC 4252 section 7.
+#[derive(Debug)]
+pub(crate) struct UserAuthRequest {
+    pub session_id: Vec<u8>,
+    pub user_name: String,
+    pub service: String,
+    pub algorithm: String,
+    pub public_key: Vec<u8>,
+    /// Host key of the server, sent by OpenSSH for the
+    /// `publickey-hostbound-v00@openssh.com` method
+    pub host_key: Option<Vec<u8>>,
 }
 
+/// Data that is neither an SSHSIG nor a userauth request. Signing it could produce a signature
+/// for any purpose, so these requests are approved more strictly.
 #[derive(Debug)]
 pub(crate) struct SignRequest {}
 
 #[derive(Debug)]
 pub(crate) enum SshAgentSignRequest {
     SshSigRequest(SshSigRequest),
+    UserAuth(UserAuthRequest),
     SignRequest(SignRequest),
 }
 
+impl SshAgentSignRequest {
+    pub(crate) fn namespace(&self) -> Option<&str> {
+        match self {
+            SshAgentSignRequest::SshSigRequest(req) => Some(&req.namespace),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn sshsig(&self) -> Option<&SshSigRequest> {
+        match self {
+            SshAgentSignRequest::SshSigRequest(req) => Some(req),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn user_auth(&self) -> Option<&UserAuthRequest> {
+        match self {
+            SshAgentSignRequest::UserAuth(req) => Some(req),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn is_arbitrary_data(&self) -> bool {
+        matches!(self, SshAgentSignRequest::SignRequest(_))
+    }
+
+    /// A userauth request for another key than the one signing is not a valid login, so it is
+    /// handled like arbitrary data.
+    pub(crate) fn for_public_key(self, public_key: &[u8]) -> Self {
+        match self {
+            SshAgentSignRequest::UserAuth(req) if req.public_key != public_key => {
+                SshAgentSignRequest::SignRequest(SignRequest {})
+            }
+            other => other,
+        }
+    }
+}
+
 pub(crate) fn parse_request(data: &[u8]) -> Result<SshAgentSignRequest, anyhow::Error> {
-    let mut data = Bytes::copy_from_slice(data);
     let magic_header = "SSHSIG";
-    let header = data.split_to(magic_header.len());
 
     // sshsig; based on https://github.com/openssh/openssh-portable/blob/master/PROTOCOL.sshsig
-    if header == magic_header.as_bytes() {
-        let _version = data.get_u32();
-
-        // read until null byte
-        let namespace = data
-            .into_iter()
-            .take_while(|&x| x != 0)
-            .collect::<Vec<u8>>();
-        let namespace =
-            String::from_utf8(namespace).map_err(|_| anyhow::anyhow!("Invalid namespace"))?;
-
-        Ok(SshAgentSignRequest::SshSigRequest(SshSigRequest {
-            namespace,
-        }))
+    if let Some(signed_data) = data.strip_prefix(magic_header.as_bytes()) {
+        Ok(SshAgentSignRequest::SshSigRequest(parse_sshsig(
+            signed_data,
+        )?))
+    } else if let Some(request) = parse_user_auth(data) {
+        Ok(SshAgentSignRequest::UserAuth(request))
     } else {
-        // regular sign request
+        // arbitrary data
         Ok(SshAgentSignRequest::SignRequest(SignRequest {}))
     }
 }
+
+/// Parses the data signed for publickey authentication:
+///
+/// ```text
+/// string    session identifier
+/// byte      SSH_MSG_USERAUTH_REQUEST
+/// string    user name
+/// string    service name
+/// string    "publickey" or "publickey-hostbound-v00@openssh.com"
+/// boolean   TRUE
+/// string    public key algorithm name
+/// string    public key blob
+/// string    server host key (only for publickey-hostbound-v00@openssh.com)
+/// ```
+///
+/// Returns `None` if the data is anything else.
+fn parse_user_auth(mut data: &[u8]) -> Option<UserAuthRequest> {
+    let session_id = read_string(&mut data).ok()?.to_vec();
+    let (&message_type, rest) = data.split_first()?;
+    if message_type != SSH_MSG_USERAUTH_REQUEST {
+        return None;
+    }
+    data = rest;
+
+    let user_name = read_utf8(&mut data)?;
+    let service = read_utf8(&mut data)?;
+    let method = read_string(&mut data).ok()?;
+    let is_hostbound = match method {
+        b"publickey" => false,
+        b"publickey-hostbound-v00@openssh.com" => true,
+        _ => return None,
+    };
+    let (&has_signature, rest) = data.split_first()?;
+    if has_signature != 1 {
+        return None;
+    }
+    data = rest;
+
+    let algorithm = read_utf8(&mut data)?;
+    let public_key = read_string(&mut data).ok()?.to_vec();
+    let host_key = if is_hostbound {
+        Some(read_string(&mut data).ok()?.to_vec())
+    } else {
+        None
+    };
+    if !data.is_empty() {
+        return None;
+    }
+
+    Some(UserAuthRequest {
+        session_id,
+        user_name,
+        service,
+        algorithm,
+        public_key,
+        host_key,
+    })
+}
+
+fn read_utf8(data: &mut &[u8]) -> Option<String> {
+    String::from_utf8(read_string(data).ok()?.to_vec()).ok()
+}
+
+/// Parses the data that is signed for an SSHSIG signature, following the magic preamble:
+///
+/// ```text
+/// string    namespace
+/// string    reserved
+/// string    hash_algorithm
+/// string    H(message)
+/// ```
+///
+/// Unlike the signature blob, the signed data carries no version field.
+fn parse_sshsig(mut data: &[u8]) -> Result<SshSigRequest, anyhow::Error> {
+    let namespace = read_string(&mut data).map_err(|e| anyhow!("Invalid namespace: {e}"))?;
+    if namespace.is_empty() {
+        return Err(anyhow!("Invalid namespace: namespace is empty"));
+    }
+    let namespace = String::from_utf8(namespace.to_vec())
+        .map_err(|_| anyhow!("Invalid namespace: namespace is not UTF-8"))?;
+
+    // reserved for future extensions; OpenSSH ignores its contents
+    read_string(&mut data).map_err(|e| anyhow!("Invalid reserved field: {e}"))?;
+
+    let hash_algorithm = SshSigHashAlgorithm::from_name(
+        read_string(&mut data).map_err(|e| anyhow!("Invalid hash algorithm: {e}"))?,
+    )?;
+
+    let message_hash = read_string(&mut data).map_err(|e| anyhow!("Invalid message hash: {e}"))?;
+    if message_hash.len() != hash_algorithm.digest_length() {
+        return Err(anyhow!(
+            "Invalid message hash: expected {} bytes for {}, got {}",
+            hash_algorithm.digest_length(),
+            hash_algorithm.name(),
+            message_hash.len()
+        ));
+    }
+
+    if !data.is_empty() {
+        return Err(anyhow!(
+            "Unexpected trailing data after SSHSIG message hash"
+        ));
+    }
+
+    Ok(SshSigRequest {
+        namespace,
+        hash_algorithm,
+        message_hash: message_hash.to_vec(),
+    })
+}
+
+/// Reads an SSH `string`, a big endian u32 length followed by that many bytes.
+fn read_string<'a>(data: &mut &'a [u8]) -> Result<&'a [u8], anyhow::Error> {
+    let (length, rest) = data
+        .split_first_chunk::<4>()
+        .ok_or_else(|| anyhow!("truncated length"))?;
+    let length = usize::try_from(u32::from_be_bytes(*length))?;
+    let (value, rest) = rest
+        .split_at_checked(length)
+        .ok_or_else(|| anyhow!("length {length} exceeds the remaining {} bytes", rest.len()))?;
+    *data = rest;
+    Ok(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn string(value: &[u8]) -> Vec<u8> {
+        let mut encoded = (value.len() as u32).to_be_bytes().to_vec();
+        encoded.extend_from_slice(value);
+        encoded
+    }
+
+    fn sshsig(namespace: &[u8], hash_algorithm: &[u8], message_hash: &[u8]) -> Vec<u8> {
+        let mut data = b"SSHSIG".to_vec();
+        data.extend(string(namespace));
+        data.extend(string(b""));
+        data.extend(string(hash_algorithm));
+        data.extend(string(message_hash));
+        data
+    }
+
+    #[test]
+    fn test_parse_sshsig_request() {
+        let data = sshsig(b"git", b"sha512", &[0xab; 64]);
+
+        let SshAgentSignRequest::SshSigRequest(request) = parse_request(&data).unwrap() else {
+            panic!("expected an SSHSIG request");
+        };
+        assert_eq!(request.namespace, "git");
+        assert_eq!(request.hash_algorithm, SshSigHashAlgorithm::Sha512);
+        assert_eq!(request.message_hash, vec![0xab; 64]);
+        assert_eq!(request.digest_preview(), "abababababababab");
+    }
+
+    fn user_auth(method: &[u8], host_key: Option<&[u8]>) -> Vec<u8> {
+        let mut data = string(&[7; 32]);
+        data.push(SSH_MSG_USERAUTH_REQUEST);
+        data.extend(string(b"deploy"));
+        data.extend(string(b"ssh-connection"));
+        data.extend(string(method));
+        data.push(1);
+        data.extend(string(b"ssh-ed25519"));
+        data.extend(string(b"public key blob"));
+        if let Some(host_key) = host_key {
+            data.extend(string(host_key));
+        }
+        data
+    }
+
+    #[test]
+    fn test_parse_user_auth_request() {
+        let request = parse_request(&user_auth(b"publickey", None)).unwrap();
+
+        let request = request.user_auth().unwrap();
+        assert_eq!(request.session_id, vec![7; 32]);
+        assert_eq!(request.user_name, "deploy");
+        assert_eq!(request.service, "ssh-connection");
+        assert_eq!(request.algorithm, "ssh-ed25519");
+        assert_eq!(request.public_key, b"public key blob");
+        assert_eq!(request.host_key, None);
+    }
+
+    #[test]
+    fn test_parse_hostbound_user_auth_request() {
+        let data = user_auth(b"publickey-hostbound-v00@openssh.com", Some(b"host key"));
+
+        let request = parse_request(&data).unwrap();
+
+        assert_eq!(
+            request.user_auth().unwrap().host_key.as_deref(),
+            Some(&b"host key"[..])
+        );
+    }
+
+    #[test]
+    fn test_user_auth_request_for_other_key_is_arbitrary_data() {
+        let data = user_auth(b"publickey", None);
+
+        assert!(parse_request(&data)
+            .unwrap()
+            .for_public_key(b"public key blob")
+            .user_auth()
+            .is_some());
+        assert!(parse_request(&data)
+            .unwrap()
+            .for_public_key(b"other key")
+            .is_arbitrary_data());
+    }
+
+    #[test]
+    fn test_malformed_user_auth_request_is_arbitrary_data() {
+        // other authentication method
+        assert!(parse_request(&user_auth(b"password", None))
+            .unwrap()
+            .is_arbitrary_data());
+        // missing host key
+        assert!(
+            parse_request(&user_auth(b"publickey-hostbound-v00@openssh.com", None))
+                .unwrap()
+                .is_arbitrary_data()
+        );
+        // trailing data
+        let mut data = user_auth(b"publickey", None);
+        data.push(0);
+        assert!(parse_request(&data).unwrap().is_arbitrary_data());
+        // truncated
+        let data = user_auth(b"publickey", None);
+        assert!(parse_request(&data[..data.len() - 1])
+            .unwrap()
+            .is_arbitrary_data());
+    }
+
+    #[test]
+    fn test_parse_regular_sign_request() {
+        assert!(matches!(
+            parse_request(b"session data").unwrap(),
+            SshAgentSignRequest::SignRequest(_)
+        ));
+        assert!(matches!(
+            parse_request(b"").unwrap(),
+            SshAgentSignRequest::SignRequest(_)
+        ));
+    }
+
+    #[test]
+    fn test_truncated_sshsig_request_is_rejected() {
+        let data = sshsig(b"git", b"sha256", &[0; 32]);
+
+        // every strict prefix is malformed, and none of them may panic
+        for length in b"SSHSIG".len()..data.len() {
+            assert!(parse_request(&data[..length]).is_err(), "length {length}");
+        }
+    }
+
+    #[test]
+    fn test_malformed_sshsig_request_is_rejected() {
+        // unknown hash algorithm
+        assert!(parse_request(&sshsig(b"git", b"md5", &[0; 16])).is_err());
+        // digest does not match the hash algorithm
+        assert!(parse_request(&sshsig(b"git", b"sha256", &[0; 64])).is_err());
+        // empty and non UTF-8 namespaces
+        assert!(parse_request(&sshsig(b"", b"sha256", &[0; 32])).is_err());
+        assert!(parse_request(&sshsig(&[0xff], b"sha256", &[0; 32])).is_err());
+        // trailing data
+        let mut data = sshsig(b"git", b"sha256", &[0; 32]);
+        data.push(0);
+        assert!(parse_request(&data).is_err());
+        // length larger than the remaining data
+        let mut data = b"SSHSIG".to_vec();
+        data.extend_from_slice(&u32::MAX.to_be_bytes());
+        assert!(parse_request(&data).is_err());
+    }
+}
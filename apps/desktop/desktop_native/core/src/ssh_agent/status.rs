@@ -0,0 +1,166 @@
+use std::{collections::HashSet, path::PathBuf, sync::atomic::Ordering};
+
+use tokio::time::Instant;
+
+use super::{peerinfo::models::PeerInfo, unix_now, BitwardenDesktopAgent, BitwardenSshKey};
+
+/// Sign requests for a key since the vault was unlocked.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct KeyUsage {
+    signatures_approved: u64,
+    signatures_denied: u64,
+    last_used: Option<u64>,
+    last_client: Option<String>,
+}
+
+/// A snapshot of the agent's state, for showing agent activity and debugging.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AgentStatus {
+    pub is_running: bool,
+    /// Number of keys whose private key is available
+    pub loaded_key_count: usize,
+    /// Set while a client locked the agent with `ssh-add -x`. A locked vault shows in
+    /// `loaded_key_count` instead.
+    pub is_client_locked: bool,
+    /// Listing keys requires unlocking the vault first
+    pub needs_unlock: bool,
+    /// Socket or named pipe that clients connect to
+    pub socket_path: Option<PathBuf>,
+    pub connected_clients: usize,
+    pub keys: Vec<KeyStatus>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KeyStatus {
+    pub name: String,
+    /// Not set for keys added with `ssh-add`
+    pub cipher_id: Option<String>,
+    pub fingerprint: Option<String>,
+    /// Whether the private key is available, i.e. neither locked nor expired
+    pub is_loaded: bool,
+    pub signatures_approved: u64,
+    pub signatures_denied: u64,
+    /// Unix time in seconds of the most recent sign request, approved or not
+    pub last_used: Option<u64>,
+    /// Process that sent the most recent sign request
+    pub last_client: Option<String>,
+}
+
+impl BitwardenDesktopAgent {
+    pub fn status(&self) -> AgentStatus {
+        let now = Instant::now();
+        let usage = self.key_usage.lock().expect("Mutex is not poisoned");
+        let mut entries: Vec<BitwardenSshKey> = self
+            .keystore
+            .0
+            .read()
+            .expect("RwLock is not poisoned")
+            .values()
+            .cloned()
+            .collect();
+        // the bare key sorts before its certificate, so it names the key
+        entries.sort_by(|a, b| a.name.cmp(&b.name));
+
+        let mut seen = HashSet::new();
+        let keys: Vec<KeyStatus> = entries
+            .into_iter()
+            .filter(|key| seen.insert(key.cipher_uuid.clone()))
+            .map(|key| {
+                let usage = usage.get(&key.cipher_uuid).cloned().unwrap_or_default();
+                KeyStatus {
+                    fingerprint: key.fingerprint(),
+                    is_loaded: key.private_key.is_some()
+                        && key.expires_at.is_none_or(|expires_at| expires_at > now),
+                    cipher_id: (!key.is_ephemeral).then_some(key.cipher_uuid),
+                    name: key.name,
+                    signatures_approved: usage.signatures_approved,
+                    signatures_denied: usage.signatures_denied,
+                    last_used: usage.last_used,
+                    last_client: usage.last_client,
+                }
+            })
+            .collect();
+
+        AgentStatus {
+            is_running: self.is_running(),
+            loaded_key_count: keys.iter().filter(|key| key.is_loaded).count(),
+            is_client_locked: self.is_client_locked(),
+            needs_unlock: self.needs_unlock.load(Ordering::Relaxed),
+            socket_path: self
+                .socket_path
+                .read()
+                .expect("RwLock is not poisoned")
+                .clone(),
+            connected_clients: self.connected_clients.load(Ordering::Relaxed),
+            keys,
+        }
+    }
+
+    pub(crate) fn record_key_usage(&self, key_id: &str, approved: bool, peer_info: &PeerInfo) {
+        let mut usage = self.key_usage.lock().expect("Mutex is not poisoned");
+        let usage = usage.entry(key_id.to_string()).or_default();
+        if approved {
+            usage.signatures_approved += 1;
+        } else {
+            usage.signatures_denied += 1;
+        }
+        usage.last_used = Some(unix_now());
+        usage.last_client = Some(peer_info.process_name().to_string());
+    }
+
+    pub(crate) fn set_socket_path(&self, socket_path: PathBuf) {
+        *self.socket_path.write().expect("RwLock is not poisoned") = Some(socket_path);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bitwarden_russh::ssh_agent::Agent;
+
+    use crate::ssh_agent::{
+        tests::{fake_ui, peer, sign_data, test_agent, vault_key},
+        ApprovalPolicy, VaultSshKey,
+    };
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_status_counts_signatures() {
+        let (mut agent, request_rx, response_tx) = test_agent();
+        fake_ui(request_rx, response_tx, false, None);
+        agent
+            .set_keys(vec![
+                vault_key(ApprovalPolicy::NeverAsk),
+                VaultSshKey {
+                    cipher_id: "other".to_string(),
+                    name: "other key".to_string(),
+                    ..vault_key(ApprovalPolicy::AlwaysAsk)
+                },
+            ])
+            .unwrap();
+        agent.set_socket_path(PathBuf::from("/tmp/agent.sock"));
+
+        let keys: Vec<_> = agent.keystore.0.read().unwrap().values().cloned().collect();
+        for key in keys {
+            agent.confirm(key.clone(), &sign_data(&key), &peer(1)).await;
+        }
+
+        let status = agent.status();
+        assert!(status.is_running);
+        assert_eq!(status.loaded_key_count, 2);
+        assert_eq!(status.socket_path, Some(PathBuf::from("/tmp/agent.sock")));
+        assert_eq!(status.keys[0].cipher_id.as_deref(), Some("other"));
+        assert_eq!(status.keys[0].signatures_denied, 1);
+        assert_eq!(status.keys[1].cipher_id.as_deref(), Some("cipher"));
+        assert_eq!(status.keys[1].signatures_approved, 1);
+        assert_eq!(status.keys[1].last_client.as_deref(), Some("ssh"));
+        assert!(status.keys[1].last_used.is_some());
+
+        agent.lock().unwrap();
+        let status = agent.status();
+        assert_eq!(status.loaded_key_count, 0);
+        assert_eq!(status.keys[0].signatures_denied, 0);
+        assert_eq!(status.keys[1].signatures_approved, 0);
+        assert_eq!(status.keys[1].last_client, None);
+    }
+}
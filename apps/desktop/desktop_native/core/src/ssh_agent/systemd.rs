@@ -0,0 +1,279 @@
+use std::{
+    fs,
+    os::{
+        fd::{FromRawFd, RawFd},
+        unix::net::UnixListener,
+    },
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use anyhow::anyhow;
+use tokio::net::UnixListener as TokioUnixListener;
+use tracing::info;
+
+use super::platform_ssh_agent::RUNTIME_SOCKFILE_NAME;
+
+/// First file descriptor passed by systemd, see sd_listen_fds(3)
+const SD_LISTEN_FDS_START: RawFd = 3;
+
+/// `FileDescriptorName` of the listening socket in the generated socket unit
+const SOCKET_NAME: &str = "bitwarden-ssh-agent";
+
+pub const SOCKET_UNIT_NAME: &str = "bitwarden-ssh-agent.socket";
+pub const SERVICE_UNIT_NAME: &str = "bitwarden-ssh-agent.service";
+
+/// Set once the socket passed by systemd was taken, since its descriptor may only be owned once
+static ACTIVATION_LISTENER_TAKEN: AtomicBool = AtomicBool::new(false);
+
+/// Takes the listening socket that systemd passed to this process with socket activation, if
+/// any, together with its path.
+pub(crate) fn take_activation_listener(
+) -> Result<Option<(TokioUnixListener, PathBuf)>, anyhow::Error> {
+    // The environment is left unchanged, since changing it is not thread safe. Child processes
+    // that inherit it ignore it, because LISTEN_PID does not match their pid.
+    let fd = select_listen_fd(
+        std::env::var("LISTEN_PID").ok().as_deref(),
+        std::env::var("LISTEN_FDS").ok().as_deref(),
+        std::env::var("LISTEN_FDNAMES").ok().as_deref(),
+        std::process::id(),
+    )?;
+    let Some(fd) = fd else {
+        return Ok(None);
+    };
+    if ACTIVATION_LISTENER_TAKEN.swap(true, Ordering::SeqCst) {
+        return Ok(None);
+    }
+
+    // SAFETY: systemd passes the descriptor to this process only, and the flag above makes sure
+    // that it is taken once
+    let listener = unsafe { UnixListener::from_raw_fd(fd) };
+    // SAFETY: fd is a valid descriptor owned by `listener`
+    if unsafe { libc::fcntl(fd, libc::F_SETFD, libc::FD_CLOEXEC) } == -1 {
+        return Err(std::io::Error::last_os_error().into());
+    }
+    listener.set_nonblocking(true)?;
+
+    let socket_path = listener
+        .local_addr()
+        .map_err(|e| anyhow!("Socket passed by systemd is not a Unix socket: {e}"))?
+        .as_pathname()
+        .map(Path::to_path_buf)
+        .ok_or_else(|| anyhow!("Socket passed by systemd has no path"))?;
+    info!(?socket_path, "Using socket passed by systemd");
+    Ok(Some((TokioUnixListener::from_std(listener)?, socket_path)))
+}
+
+/// Returns the descriptor of the agent socket, based on the `LISTEN_*` environment variables.
+fn select_listen_fd(
+    listen_pid: Option<&str>,
+    listen_fds: Option<&str>,
+    fd_names: Option<&str>,
+    pid: u32,
+) -> Result<Option<RawFd>, anyhow::Error> {
+    let (Some(listen_pid), Some(listen_fds)) = (listen_pid, listen_fds) else {
+        return Ok(None);
+    };
+    // the variables were meant for another process, e.g. the one that started the app
+    if listen_pid.parse::<u32>().ok() != Some(pid) {
+        return Ok(None);
+    }
+
+    let count: RawFd = listen_fds
+        .parse()
+        .map_err(|e| anyhow!("Invalid LISTEN_FDS {listen_fds}: {e}"))?;
+    match count {
+        0 => Ok(None),
+        1 => Ok(Some(SD_LISTEN_FDS_START)),
+        _ => {
+            let index = fd_names
+                .unwrap_or_default()
+                .split(':')
+                .position(|name| name == SOCKET_NAME)
+                .ok_or_else(|| anyhow!("systemd passed no socket named {SOCKET_NAME}"))?;
+            Ok(Some(SD_LISTEN_FDS_START + RawFd::try_from(index)?))
+        }
+    }
+}
+
+/// Whether the installed socket unit listens on `socket_path`, when the app was started without
+/// socket activation. The socket is then left to the unit, whose service serves the clients.
+/// Listeners are looked up in `/proc/net/unix`, since connecting to the socket to probe it would
+/// start the service.
+pub(crate) fn is_held_by_socket_unit(socket_path: &Path) -> bool {
+    let runtime_dir = std::env::var_os("XDG_RUNTIME_DIR").map(PathBuf::from);
+    is_unit_socket_path(socket_path, runtime_dir.as_deref())
+        && unit_directory().is_ok_and(|directory| directory.join(SOCKET_UNIT_NAME).exists())
+        && is_listening(
+            socket_path,
+            &fs::read_to_string("/proc/net/unix").unwrap_or_default(),
+        )
+}
+
+/// Whether `proc_net_unix`, the contents of `/proc/net/unix`, lists a listening socket bound to
+/// `socket_path`.
+fn is_listening(socket_path: &Path, proc_net_unix: &str) -> bool {
+    /// `__SO_ACCEPTCON`, set for listening sockets
+    const ACCEPTCON_FLAGS: &str = "00010000";
+
+    let path_suffix = format!(" {}", socket_path.to_string_lossy());
+    proc_net_unix.lines().any(|line| {
+        // Num RefCount Protocol Flags Type St Inode Path
+        line.ends_with(&path_suffix) && line.split_whitespace().nth(3) == Some(ACCEPTCON_FLAGS)
+    })
+}
+
+/// Whether `socket_path` is the `ListenStream` of the generated socket unit.
+fn is_unit_socket_path(socket_path: &Path, runtime_dir: Option<&Path>) -> bool {
+    runtime_dir.is_some_and(|runtime_dir| socket_path == runtime_dir.join(RUNTIME_SOCKFILE_NAME))
+}
+
+/// `~/.config/systemd/user`
+fn unit_directory() -> Result<PathBuf, anyhow::Error> {
+    Ok(dirs::config_dir()
+        .ok_or_else(|| anyhow!("Could not determine config directory"))?
+        .join("systemd")
+        .join("user"))
+}
+
+/// systemd user units that hold the agent socket while the app is not running. Connections
+/// wait on the socket until the app, started by the service, takes it over.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SystemdUnits {
+    pub socket: String,
+    pub service: String,
+}
+
+/// Generates the units for starting the app at `executable`.
+pub fn systemd_units(executable: &Path) -> SystemdUnits {
+    let socket = format!(
+        "[Unit]
+Description=Bitwarden SSH agent socket
+
+[Socket]
+ListenStream=%t/{RUNTIME_SOCKFILE_NAME}
+SocketMode=0600
+DirectoryMode=0700
+FileDescriptorName={SOCKET_NAME}
+Service={SERVICE_UNIT_NAME}
+
+[Install]
+WantedBy=sockets.target
+"
+    );
+    let service = format!(
+        "[Unit]
+Description=Bitwarden SSH agent
+Requires={SOCKET_UNIT_NAME}
+
+[Service]
+ExecStart={}
+",
+        quote_exec_path(executable)
+    );
+    SystemdUnits { socket, service }
+}
+
+/// Writes the units to `~/.config/systemd/user` and returns the directory. They still need to
+/// be enabled with `systemctl --user enable --now bitwarden-ssh-agent.socket`.
+pub fn install_systemd_units(executable: &Path) -> Result<PathBuf, anyhow::Error> {
+    let directory = unit_directory()?;
+    fs::create_dir_all(&directory)?;
+
+    let units = systemd_units(executable);
+    fs::write(directory.join(SOCKET_UNIT_NAME), units.socket)?;
+    fs::write(directory.join(SERVICE_UNIT_NAME), units.service)?;
+    info!(?directory, "Installed systemd units for the SSH agent");
+    Ok(directory)
+}
+
+/// Quotes a path for `ExecStart`, which treats backslashes, quotes and `%` specially.
+fn quote_exec_path(path: &Path) -> String {
+    let path = path
+        .to_string_lossy()
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('%', "%%");
+    format!("\"{path}\"")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_select_listen_fd() {
+        assert_eq!(select_listen_fd(None, None, None, 10).unwrap(), None);
+        // meant for another process
+        assert_eq!(
+            select_listen_fd(Some("11"), Some("1"), None, 10).unwrap(),
+            None
+        );
+        assert_eq!(
+            select_listen_fd(Some("10"), Some("1"), None, 10).unwrap(),
+            Some(3)
+        );
+        assert_eq!(
+            select_listen_fd(Some("10"), Some("2"), Some("other:bitwarden-ssh-agent"), 10).unwrap(),
+            Some(4)
+        );
+        assert!(select_listen_fd(Some("10"), Some("2"), Some("a:b"), 10).is_err());
+    }
+
+    #[test]
+    fn test_is_unit_socket_path() {
+        let runtime_dir = Path::new("/run/user/1000");
+
+        assert!(is_unit_socket_path(
+            Path::new("/run/user/1000/bitwarden-ssh-agent.sock"),
+            Some(runtime_dir)
+        ));
+        assert!(!is_unit_socket_path(
+            Path::new("/home/user/.bitwarden-ssh-agent.sock"),
+            Some(runtime_dir)
+        ));
+        assert!(!is_unit_socket_path(
+            Path::new("/run/user/1000/bitwarden-ssh-agent.sock"),
+            None
+        ));
+    }
+
+    #[test]
+    fn test_is_listening() {
+        let proc_net_unix = "\
+Num       RefCount Protocol Flags    Type St Inode Path
+0000000000000000: 00000002 00000000 00010000 0001 01 21345 /run/user/1000/bitwarden-ssh-agent.sock
+0000000000000000: 00000003 00000000 00000000 0001 03 21346 /run/user/1000/other.sock
+0000000000000000: 00000003 00000000 00000000 0001 03 21347
+";
+
+        assert!(is_listening(
+            Path::new("/run/user/1000/bitwarden-ssh-agent.sock"),
+            proc_net_unix
+        ));
+        // connected, not listening
+        assert!(!is_listening(
+            Path::new("/run/user/1000/other.sock"),
+            proc_net_unix
+        ));
+        assert!(!is_listening(
+            Path::new("/user/1000/bitwarden-ssh-agent.sock"),
+            proc_net_unix
+        ));
+    }
+
+    #[test]
+    fn test_systemd_units() {
+        let units = systemd_units(Path::new("/opt/Bitwarden 100%/bitwarden"));
+
+        assert!(units
+            .socket
+            .contains("ListenStream=%t/bitwarden-ssh-agent.sock\n"));
+        assert!(units
+            .socket
+            .contains("FileDescriptorName=bitwarden-ssh-agent\n"));
+        assert!(units
+            .service
+            .contains("ExecStart=\"/opt/Bitwarden 100%%/bitwarden\"\n"));
+    }
+}
@@ -0,0 +1,122 @@
+use std::{
+    fs::File,
+    io,
+    path::{Path, PathBuf},
+};
+
+use sha2::{Digest, Sha256};
+
+use super::peerinfo::models::PeerInfo;
+
+/// A client binary that may sign with specific keys without a prompt, e.g. on a build agent.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TrustedExecutable {
+    /// Absolute path of the executable
+    pub path: PathBuf,
+    /// SHA-256 of the executable, in hex. Replacing the binary invalidates the trust.
+    pub sha256: String,
+    /// Cipher ids of the keys that the executable may sign with
+    pub cipher_ids: Vec<String>,
+}
+
+impl TrustedExecutable {
+    /// Returns true if the peer runs this executable and it may sign with the given cipher. The
+    /// executable is only hashed once its path and the cipher match.
+    pub(crate) async fn trusts(&self, cipher_id: &str, peer: &PeerInfo) -> bool {
+        peer.details().exe_path.as_deref() == Some(self.path.as_path())
+            && self.cipher_ids.iter().any(|id| id == cipher_id)
+            && peer
+                .exe_sha256()
+                .await
+                .is_some_and(|sha256| sha256.eq_ignore_ascii_case(&self.sha256))
+    }
+}
+
+/// Returns the SHA-256 of the file at `path`, in hex.
+pub fn executable_sha256(path: &Path) -> Result<String, io::Error> {
+    let mut hasher = Sha256::new();
+    io::copy(&mut File::open(path)?, &mut hasher)?;
+    Ok(hex::encode(hasher.finalize()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ssh_agent::peerinfo::models::ProcessDetails;
+
+    /// SHA-256 of "test"
+    const SHA256: &str = "9f86d081884c7d659a2feaa0c55ad015a3bf4f1b2b0b822cd15d6c15b0f00a08";
+
+    /// Writes an executable with the given contents to a temporary file.
+    fn exe_file(name: &str, contents: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("trusted-{name}-{}", std::process::id()));
+        std::fs::write(&path, contents).unwrap();
+        path
+    }
+
+    fn peer(exe_path: &str, exe_file: &Path) -> PeerInfo {
+        PeerInfo::new(1000, 1, "builder".to_string()).with_details(ProcessDetails {
+            exe_path: Some(PathBuf::from(exe_path)),
+            exe_file: Some(exe_file.to_path_buf()),
+            ..Default::default()
+        })
+    }
+
+    fn trusted() -> TrustedExecutable {
+        TrustedExecutable {
+            path: PathBuf::from("/usr/bin/builder"),
+            sha256: SHA256.to_uppercase(),
+            cipher_ids: vec!["cipher".to_string()],
+        }
+    }
+
+    #[tokio::test]
+    async fn test_trusts_matching_executable() {
+        let exe_file = exe_file("matching", "test");
+
+        assert!(
+            trusted()
+                .trusts("cipher", &peer("/usr/bin/builder", &exe_file))
+                .await
+        );
+        std::fs::remove_file(exe_file).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_does_not_trust_other_executables_or_keys() {
+        let trusted = trusted();
+        let original_file = exe_file("other", "test");
+        let replaced_file = exe_file("replaced", "replaced");
+
+        assert!(
+            !trusted
+                .trusts("other", &peer("/usr/bin/builder", &original_file))
+                .await
+        );
+        assert!(
+            !trusted
+                .trusts("cipher", &peer("/usr/bin/ssh", &original_file))
+                .await
+        );
+        assert!(
+            !trusted
+                .trusts("cipher", &peer("/usr/bin/builder", &replaced_file))
+                .await
+        );
+        assert!(
+            !trusted
+                .trusts("cipher", &PeerInfo::new(1000, 1, "builder".to_string()))
+                .await
+        );
+        std::fs::remove_file(original_file).unwrap();
+        std::fs::remove_file(replaced_file).unwrap();
+    }
+
+    #[test]
+    fn test_executable_sha256() {
+        let path = exe_file("sha256", "test");
+
+        assert_eq!(executable_sha256(&path).unwrap(), SHA256);
+        std::fs::remove_file(path).unwrap();
+    }
+}
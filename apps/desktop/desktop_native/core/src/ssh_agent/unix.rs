@@ -1,4 +1,12 @@
-use std::{fs, os::unix::fs::PermissionsExt, path::PathBuf, sync::Arc};
+use std::{
+    fs, io,
+    os::unix::{
+        fs::{FileTypeExt, PermissionsExt},
+        net::UnixStream,
+    },
+    path::{Path, PathBuf},
+    sync::Arc,
+};
 
 use anyhow::anyhow;
 use bitwarden_russh::ssh_agent;
@@ -8,7 +16,7 @@ use tracing::{error, info};
 
 use crate::ssh_agent::peercred_unix_listener_stream::PeercredUnixListenerStream;
 
-use super::{BitwardenDesktopAgent, SshAgentUIRequest};
+use super::{BitwardenDesktopAgent, SshAgentUIMessage, SshAgentUIResponse};
 
 /// User can override the default socket path with this env var
 const ENV_BITWARDEN_SSH_AUTH_SOCK: &str = "BITWARDEN_SSH_AUTH_SOCK";
@@ -17,70 +25,114 @@ const FLATPAK_DATA_DIR: &str = ".var/app/com.bitwarden.desktop/data";
 
 const SOCKFILE_NAME: &str = ".bitwarden-ssh-agent.sock";
 
+/// Name of the socket in `$XDG_RUNTIME_DIR`, which is private to the user and cleared on logout
+pub(crate) const RUNTIME_SOCKFILE_NAME: &str = "bitwarden-ssh-agent.sock";
+
 impl BitwardenDesktopAgent {
     /// Starts the Bitwarden Desktop SSH Agent server.
     /// # Errors
     /// Will return `Err` if unable to create and set permissions for socket file path or
-    /// if unable to bind to the socket path.
+    /// if unable to bind to the socket path, or if the socket passed by systemd is unusable.
     pub fn start_server(
-        auth_request_tx: tokio::sync::mpsc::Sender<SshAgentUIRequest>,
-        auth_response_rx: Arc<Mutex<tokio::sync::broadcast::Receiver<(u32, bool)>>>,
+        auth_request_tx: tokio::sync::mpsc::Sender<SshAgentUIMessage>,
+        auth_response_rx: Arc<Mutex<tokio::sync::broadcast::Receiver<SshAgentUIResponse>>>,
     ) -> Result<Self, anyhow::Error> {
         let agent_state = BitwardenDesktopAgent::new(auth_request_tx, auth_response_rx);
 
-        let socket_path = get_socket_path()?;
-
-        // if the socket is already present and wasn't cleanly removed during a previous
-        // runtime, remove it before beginning anew.
-        remove_path(&socket_path)?;
-
-        info!(?socket_path, "Starting SSH Agent server");
-
-        match UnixListener::bind(socket_path.clone()) {
-            Ok(listener) => {
-                // Only the current user should be able to access the socket
-                set_user_permissions(&socket_path)?;
+        // a socket passed by systemd is already bound, and connections that arrived before the
+        // app started are waiting on it
+        #[cfg(target_os = "linux")]
+        let activated = super::systemd::take_activation_listener()?;
+        #[cfg(not(target_os = "linux"))]
+        let activated = None;
+
+        let (listener, socket_path) = match activated {
+            Some(activated) => activated,
+            None => bind_listener()?,
+        };
+
+        let cloned_agent_state = agent_state.clone();
+        let stream =
+            cloned_agent_state.intercept_connections(PeercredUnixListenerStream::new(listener));
+
+        let cloned_keystore = cloned_agent_state.keystore.clone();
+        let cloned_cancellation_token = cloned_agent_state.cancellation_token.clone();
+
+        tokio::spawn(async move {
+            let _ = ssh_agent::serve(
+                stream,
+                cloned_agent_state.clone(),
+                cloned_keystore,
+                cloned_cancellation_token,
+            )
+            .await;
+
+            cloned_agent_state
+                .is_running
+                .store(false, std::sync::atomic::Ordering::Relaxed);
+
+            info!("SSH Agent server exited");
+        });
+
+        agent_state
+            .is_running
+            .store(true, std::sync::atomic::Ordering::Relaxed);
+        agent_state.spawn_expiry_task();
+        agent_state.set_socket_path(socket_path.clone());
+
+        info!(?socket_path, "SSH Agent is running.");
 
-                let stream = PeercredUnixListenerStream::new(listener);
+        Ok(agent_state)
+    }
+}
 
-                let cloned_agent_state = agent_state.clone();
-                let cloned_keystore = cloned_agent_state.keystore.clone();
-                let cloned_cancellation_token = cloned_agent_state.cancellation_token.clone();
+/// Binds the socket at the configured path.
+fn bind_listener() -> Result<(UnixListener, PathBuf), anyhow::Error> {
+    let socket_path = get_socket_path()?;
+
+    // an installed socket unit may hold the socket while the app was started by hand
+    #[cfg(target_os = "linux")]
+    if super::systemd::is_held_by_socket_unit(&socket_path) {
+        error!(
+            ?socket_path,
+            "Socket is held by the systemd socket unit, clients are served by its service"
+        );
+        return Err(anyhow!(
+            "Socket {socket_path:?} is held by the systemd socket unit"
+        ));
+    }
 
-                tokio::spawn(async move {
-                    let _ = ssh_agent::serve(
-                        stream,
-                        cloned_agent_state.clone(),
-                        cloned_keystore,
-                        cloned_cancellation_token,
-                    )
-                    .await;
+    // if the socket is already present and wasn't cleanly removed during a previous
+    // runtime, remove it before beginning anew. A socket that another instance still listens
+    // on is left alone.
+    remove_stale_socket(&socket_path)?;
 
-                    cloned_agent_state
-                        .is_running
-                        .store(false, std::sync::atomic::Ordering::Relaxed);
+    info!(?socket_path, "Starting SSH Agent server");
 
-                    info!("SSH Agent server exited");
-                });
+    let listener = match UnixListener::bind(socket_path.clone()) {
+        Ok(listener) => listener,
+        Err(error) => {
+            error!(%error, ?socket_path, "Unable to start start agent server");
+            return Err(error.into());
+        }
+    };
 
-                agent_state
-                    .is_running
-                    .store(true, std::sync::atomic::Ordering::Relaxed);
+    // Only the current user should be able to access the socket
+    set_user_permissions(&socket_path)?;
 
-                info!(?socket_path, "SSH Agent is running.");
-            }
-            Err(error) => {
-                error!(%error, ?socket_path, "Unable to start start agent server");
-                return Err(error.into());
-            }
+    // SSH_AUTH_SOCK of existing setups still points to the old default path
+    if let Some(compat_path) = get_compat_socket_path(&socket_path) {
+        if let Err(e) = link_compat_socket(&compat_path, &socket_path) {
+            error!(error = %e, ?compat_path, "Could not link socket at previous path");
         }
-
-        Ok(agent_state)
     }
+
+    Ok((listener, socket_path))
 }
 
 // one of the following:
 //   - only the env var socket path if it is defined
+//   - the $XDG_RUNTIME_DIR path, outside of flatpak
 //   - the $HOME path and our well known extension
 fn get_socket_path() -> Result<PathBuf, anyhow::Error> {
     if let Ok(path) = std::env::var(ENV_BITWARDEN_SSH_AUTH_SOCK) {
@@ -95,8 +147,21 @@ fn is_flatpak() -> bool {
     std::env::var("container") == Ok("flatpak".to_string())
 }
 
-// use the $HOME directory
+fn get_runtime_dir() -> Option<PathBuf> {
+    std::env::var_os("XDG_RUNTIME_DIR")
+        .map(PathBuf::from)
+        .filter(|path| path.is_absolute() && path.is_dir())
+}
+
 fn get_default_socket_path() -> Result<PathBuf, anyhow::Error> {
+    match get_runtime_dir() {
+        Some(runtime_dir) if !is_flatpak() => Ok(runtime_dir.join(RUNTIME_SOCKFILE_NAME)),
+        _ => get_home_socket_path(),
+    }
+}
+
+// use the $HOME directory
+fn get_home_socket_path() -> Result<PathBuf, anyhow::Error> {
     let Ok(Some(mut ssh_agent_directory)) = my_home() else {
         error!("Could not determine home directory");
         return Err(anyhow!("Could not determine home directory."));
@@ -111,64 +176,145 @@ fn get_default_socket_path() -> Result<PathBuf, anyhow::Error> {
     Ok(ssh_agent_directory)
 }
 
+/// The previous default path, if the socket moved to `$XDG_RUNTIME_DIR`.
+fn get_compat_socket_path(socket_path: &Path) -> Option<PathBuf> {
+    if std::env::var_os(ENV_BITWARDEN_SSH_AUTH_SOCK).is_some() {
+        return None;
+    }
+    get_home_socket_path()
+        .ok()
+        .filter(|home_path| home_path != socket_path)
+}
+
+/// Points the previous default path to the socket, unless another agent listens there.
+fn link_compat_socket(compat_path: &Path, socket_path: &Path) -> Result<(), anyhow::Error> {
+    match fs::symlink_metadata(compat_path) {
+        Ok(metadata) if metadata.file_type().is_symlink() => {
+            if fs::read_link(compat_path)? == socket_path {
+                return Ok(());
+            }
+            if is_live_socket(compat_path) {
+                info!(
+                    ?compat_path,
+                    "Previous socket path is in use by another agent"
+                );
+                return Ok(());
+            }
+            fs::remove_file(compat_path)?;
+        }
+        Ok(_) => remove_stale_socket(compat_path)?,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+        Err(e) => return Err(e.into()),
+    }
+
+    std::os::unix::fs::symlink(socket_path, compat_path)?;
+    info!(?compat_path, ?socket_path, "Linked previous socket path");
+    Ok(())
+}
+
 fn set_user_permissions(path: &PathBuf) -> Result<(), anyhow::Error> {
     fs::set_permissions(path, fs::Permissions::from_mode(0o600))
         .map_err(|e| anyhow!("Could not set socket permissions for {path:?}: {e}"))
 }
 
-// try to remove the given path if it exists
-fn remove_path(path: &PathBuf) -> Result<(), anyhow::Error> {
-    if let Ok(true) = std::fs::exists(path) {
-        std::fs::remove_file(path).map_err(|e| anyhow!("Error removing socket {path:?}: {e}"))?;
+/// Returns true if an agent accepts connections on the socket.
+fn is_live_socket(path: &Path) -> bool {
+    UnixStream::connect(path).is_ok()
+}
+
+// try to remove the socket at the given path if it exists and nothing listens on it
+fn remove_stale_socket(path: &Path) -> Result<(), anyhow::Error> {
+    let metadata = match fs::symlink_metadata(path) {
+        Ok(metadata) => metadata,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(anyhow!("Error reading socket {path:?}: {e}")),
+    };
+
+    if !metadata.file_type().is_socket() && !metadata.file_type().is_symlink() {
+        return Err(anyhow!("{path:?} exists and is not a socket"));
     }
-    Ok(())
+    if is_live_socket(path) {
+        return Err(anyhow!(
+            "Another SSH agent is already listening on {path:?}"
+        ));
+    }
+
+    info!(?path, "Removing stale socket");
+    fs::remove_file(path).map_err(|e| anyhow!("Error removing socket {path:?}: {e}"))
 }
 
 #[cfg(test)]
 mod tests {
-    use rand::{distr::Alphanumeric, Rng};
-
     use super::*;
+    use crate::ssh_agent::tests::rand_file_in_temp;
 
     #[test]
     fn test_default_socket_path_success() {
-        let path = get_default_socket_path().unwrap();
-        let expected = PathBuf::from_iter([
+        let home_path = PathBuf::from_iter([
             std::env::var("HOME").unwrap(),
             ".bitwarden-ssh-agent.sock".to_string(),
         ]);
-        assert_eq!(path, expected);
-    }
+        assert_eq!(get_home_socket_path().unwrap(), home_path);
 
-    fn rand_file_in_temp() -> PathBuf {
-        let mut path = std::env::temp_dir();
-        let s: String = rand::rng()
-            .sample_iter(&Alphanumeric)
-            .take(16)
-            .map(char::from)
-            .collect();
-        path.push(s);
-        path
+        let expected = match get_runtime_dir() {
+            Some(runtime_dir) => runtime_dir.join("bitwarden-ssh-agent.sock"),
+            None => home_path,
+        };
+        assert_eq!(get_default_socket_path().unwrap(), expected);
     }
 
     #[test]
-    fn test_remove_path_exists_success() {
+    fn test_remove_stale_socket_success() {
         let path = rand_file_in_temp();
-        fs::write(&path, "").unwrap();
-        remove_path(&path).unwrap();
+        drop(std::os::unix::net::UnixListener::bind(&path).unwrap());
+        remove_stale_socket(&path).unwrap();
 
         assert!(!fs::exists(&path).unwrap());
     }
 
-    // the remove_path should not fail if the path does not exist
+    // the remove_stale_socket should not fail if the path does not exist
     #[test]
-    fn test_remove_path_not_found_success() {
+    fn test_remove_stale_socket_not_found_success() {
         let path = rand_file_in_temp();
-        remove_path(&path).unwrap();
+        remove_stale_socket(&path).unwrap();
 
         assert!(!fs::exists(&path).unwrap());
     }
 
+    #[test]
+    fn test_remove_stale_socket_keeps_live_socket() {
+        let path = rand_file_in_temp();
+        let _listener = std::os::unix::net::UnixListener::bind(&path).unwrap();
+
+        assert!(remove_stale_socket(&path).is_err());
+        assert!(fs::exists(&path).unwrap());
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_remove_stale_socket_keeps_other_files() {
+        let path = rand_file_in_temp();
+        fs::write(&path, "").unwrap();
+
+        assert!(remove_stale_socket(&path).is_err());
+        assert!(fs::exists(&path).unwrap());
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_link_compat_socket() {
+        let socket_path = rand_file_in_temp();
+        let compat_path = rand_file_in_temp();
+        std::os::unix::fs::symlink(rand_file_in_temp(), &compat_path).unwrap();
+
+        // a dangling link from an earlier run is replaced
+        link_compat_socket(&compat_path, &socket_path).unwrap();
+        assert_eq!(fs::read_link(&compat_path).unwrap(), socket_path);
+        link_compat_socket(&compat_path, &socket_path).unwrap();
+        assert_eq!(fs::read_link(&compat_path).unwrap(), socket_path);
+        fs::remove_file(&compat_path).unwrap();
+    }
+
     #[test]
     fn test_sock_path_file_permissions() {
         let path = rand_file_in_temp();
@@ -181,6 +327,6 @@ mod tests {
 
         assert_eq!(permissions, 0o100_600);
 
-        remove_path(&path).unwrap();
+        fs::remove_file(&path).unwrap();
     }
 }
@@ -0,0 +1,307 @@
+use std::{
+    collections::HashSet,
+    fs,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use anyhow::anyhow;
+use ssh_encoding::Decode;
+use tracing::{debug, error};
+
+use super::{peerinfo::models::PeerInfo, protocol, BitwardenDesktopAgent};
+
+/// How long to wait for the upstream agent to list its keys. Signing can require interaction
+/// with a hardware token, so sign requests wait as long as prompts do.
+const UPSTREAM_LIST_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Chaining to another agent, e.g. the system agent or a hardware token agent, so that its keys
+/// stay available to clients that use the Bitwarden socket. Keys of the upstream agent are not
+/// offered on forwarded connections, since their forwarding policy is not known.
+impl BitwardenDesktopAgent {
+    /// Sets the socket (or named pipe) of the agent to chain to, or `None` to disable chaining.
+    pub fn set_upstream_agent(&self, path: Option<PathBuf>) {
+        *self.upstream_agent.write().expect("RwLock is not poisoned") = path;
+    }
+
+    fn upstream_path(&self, peer_info: &PeerInfo) -> Option<PathBuf> {
+        if peer_info.is_forwarding() {
+            return None;
+        }
+
+        let path = self
+            .upstream_agent
+            .read()
+            .expect("RwLock is not poisoned")
+            .clone()?;
+        // chaining to ourselves would forward requests in a loop
+        if self
+            .socket_path
+            .read()
+            .expect("RwLock is not poisoned")
+            .as_ref()
+            .is_some_and(|socket_path| is_same_path(socket_path, &path))
+        {
+            error!(?path, "Upstream agent is this agent, not chaining");
+            return None;
+        }
+        Some(path)
+    }
+
+    /// Appends the keys of the upstream agent to the answer to a list request. Vault keys come
+    /// first, and the answer is left unchanged if the upstream agent fails.
+    pub(crate) async fn add_upstream_identities(
+        &self,
+        response: Vec<u8>,
+        peer_info: &PeerInfo,
+    ) -> Vec<u8> {
+        let Some(path) = self.upstream_path(peer_info) else {
+            return response;
+        };
+
+        let upstream = match request_upstream(
+            &path,
+            &[protocol::SSH_AGENTC_REQUEST_IDENTITIES],
+            UPSTREAM_LIST_TIMEOUT,
+        )
+        .await
+        .and_then(|response| protocol::parse_identities_answer(&response))
+        {
+            Ok(identities) => identities,
+            Err(e) => {
+                error!(error = %e, ?path, "Could not list keys of upstream agent");
+                return response;
+            }
+        };
+
+        // a denied list request still lists the upstream keys
+        let mut identities = protocol::parse_identities_answer(&response).unwrap_or_default();
+        let vault_keys: HashSet<Vec<u8>> = identities
+            .iter()
+            .map(|(public_key, _)| public_key.clone())
+            .collect();
+        identities.extend(
+            upstream
+                .into_iter()
+                .filter(|(public_key, _)| !vault_keys.contains(public_key)),
+        );
+        protocol::identities_answer(&identities)
+    }
+
+    /// Passes sign requests for keys that this agent does not hold on to the upstream agent,
+    /// unchanged. Returns `None` for requests that are handled here.
+    pub(crate) async fn sign_upstream(
+        &self,
+        message: &[u8],
+        body: &[u8],
+        peer_info: &PeerInfo,
+    ) -> Option<Vec<u8>> {
+        let path = self.upstream_path(peer_info)?;
+        let public_key = Vec::<u8>::decode(&mut &body[..]).ok()?;
+        if self
+            .keystore
+            .0
+            .read()
+            .expect("RwLock is not poisoned")
+            .contains_key(&public_key)
+        {
+            return None;
+        }
+
+        debug!(?path, "Passing sign request on to upstream agent");
+        let timeout = *self.prompt_timeout.read().expect("RwLock is not poisoned");
+        match request_upstream(&path, message, timeout).await {
+            Ok(response) => Some(response),
+            Err(e) => {
+                error!(error = %e, ?path, "Upstream agent could not sign");
+                Some(protocol::status_message(false))
+            }
+        }
+    }
+}
+
+/// Compares paths after resolving symlinks and `..`, e.g. the compatibility symlink to our own
+/// socket. Paths that cannot be resolved, like named pipes, are compared as they are.
+fn is_same_path(a: &Path, b: &Path) -> bool {
+    if a == b {
+        return true;
+    }
+    match (fs::canonicalize(a), fs::canonicalize(b)) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => false,
+    }
+}
+
+/// Sends a single request to the upstream agent on a connection of its own.
+async fn request_upstream(
+    path: &Path,
+    message: &[u8],
+    timeout: Duration,
+) -> Result<Vec<u8>, anyhow::Error> {
+    let request = async {
+        let mut stream = connect(path).await?;
+        protocol::write_message(&mut stream, message).await?;
+        protocol::read_message(&mut stream)
+            .await?
+            .ok_or_else(|| anyhow!("Upstream agent closed the connection"))
+    };
+    tokio::time::timeout(timeout, request)
+        .await
+        .map_err(|_| anyhow!("Upstream agent did not answer in time"))?
+}
+
+#[cfg(unix)]
+async fn connect(path: &Path) -> Result<tokio::net::UnixStream, anyhow::Error> {
+    Ok(tokio::net::UnixStream::connect(path).await?)
+}
+
+#[cfg(windows)]
+#[allow(clippy::unused_async)]
+async fn connect(
+    path: &Path,
+) -> Result<tokio::net::windows::named_pipe::NamedPipeClient, anyhow::Error> {
+    Ok(tokio::net::windows::named_pipe::ClientOptions::new().open(path)?)
+}
+
+#[cfg(test)]
+#[cfg(unix)]
+mod tests {
+    use bitwarden_russh::ssh_agent::SshKey;
+    use ssh_encoding::Encode;
+    use tokio::net::UnixListener;
+
+    use crate::ssh_agent::{
+        tests::{peer, test_agent, vault_key},
+        ApprovalPolicy,
+    };
+
+    use super::*;
+
+    const SIGNATURE: &[u8] = &[14, 0, 0, 0, 3, 1, 2, 3];
+
+    /// Starts an agent that lists `identities` and answers every sign request with `SIGNATURE`.
+    fn fake_upstream(name: &str, identities: Vec<(Vec<u8>, String)>) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("upstream-{name}-{}", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let listener = UnixListener::bind(&path).unwrap();
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                let message = protocol::read_message(&mut stream).await.unwrap().unwrap();
+                let response = match message[0] {
+                    protocol::SSH_AGENTC_REQUEST_IDENTITIES => {
+                        protocol::identities_answer(&identities)
+                    }
+                    _ => SIGNATURE.to_vec(),
+                };
+                protocol::write_message(&mut stream, &response)
+                    .await
+                    .unwrap();
+            }
+        });
+        path
+    }
+
+    fn sign_request(public_key: &[u8]) -> Vec<u8> {
+        let mut message = vec![protocol::SSH_AGENTC_SIGN_REQUEST];
+        public_key.encode(&mut message).unwrap();
+        b"data".as_slice().encode(&mut message).unwrap();
+        // flags
+        0u32.encode(&mut message).unwrap();
+        message
+    }
+
+    #[tokio::test]
+    async fn test_upstream_keys_are_listed_after_vault_keys() {
+        let (mut agent, _request_rx, _response_tx) = test_agent();
+        agent
+            .set_keys(vec![vault_key(ApprovalPolicy::NeverAsk)])
+            .unwrap();
+        let vault_identity = agent
+            .keystore
+            .0
+            .read()
+            .unwrap()
+            .values()
+            .map(|key| (key.public_key_bytes(), key.name.clone()))
+            .next()
+            .unwrap();
+        let upstream_identity = (vec![1, 2, 3], "upstream key".to_string());
+        let path = fake_upstream(
+            "list",
+            vec![upstream_identity.clone(), vault_identity.clone()],
+        );
+        agent.set_upstream_agent(Some(path.clone()));
+
+        let response = protocol::identities_answer(&[vault_identity.clone()]);
+        let merged = agent.add_upstream_identities(response, &peer(1)).await;
+        assert_eq!(
+            protocol::parse_identities_answer(&merged).unwrap(),
+            vec![vault_identity, upstream_identity]
+        );
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_sign_requests_for_other_keys_go_upstream() {
+        let (mut agent, _request_rx, _response_tx) = test_agent();
+        agent
+            .set_keys(vec![vault_key(ApprovalPolicy::NeverAsk)])
+            .unwrap();
+        let vault_public_key = agent.keystore.0.read().unwrap().keys().next().cloned();
+        let path = fake_upstream("sign", Vec::new());
+        agent.set_upstream_agent(Some(path.clone()));
+
+        let message = sign_request(&[1, 2, 3]);
+        assert_eq!(
+            agent.sign_upstream(&message, &message[1..], &peer(1)).await,
+            Some(SIGNATURE.to_vec())
+        );
+        let message = sign_request(&vault_public_key.unwrap());
+        assert_eq!(
+            agent.sign_upstream(&message, &message[1..], &peer(1)).await,
+            None
+        );
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_dead_upstream_does_not_affect_vault_keys() {
+        let (agent, _request_rx, _response_tx) = test_agent();
+        agent.set_upstream_agent(Some(PathBuf::from("/nonexistent/upstream.sock")));
+
+        let response = protocol::identities_answer(&[(vec![4, 5, 6], "vault key".to_string())]);
+        assert_eq!(
+            agent
+                .add_upstream_identities(response.clone(), &peer(1))
+                .await,
+            response
+        );
+
+        let message = sign_request(&[1, 2, 3]);
+        assert_eq!(
+            agent.sign_upstream(&message, &message[1..], &peer(1)).await,
+            Some(protocol::status_message(false))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_own_socket_is_not_chained_through_another_path() {
+        let (agent, _request_rx, _response_tx) = test_agent();
+        let socket_path = fake_upstream("self", Vec::new());
+        *agent.socket_path.write().unwrap() = Some(socket_path.clone());
+        let symlink = socket_path.with_extension("link");
+        let _ = std::fs::remove_file(&symlink);
+        std::os::unix::fs::symlink(&socket_path, &symlink).unwrap();
+        let directory = socket_path.parent().unwrap();
+        let dotdot_path = directory
+            .join("..")
+            .join(directory.file_name().unwrap())
+            .join(socket_path.file_name().unwrap());
+
+        for path in [socket_path.clone(), symlink.clone(), dotdot_path] {
+            agent.set_upstream_agent(Some(path.clone()));
+            assert_eq!(agent.upstream_path(&peer(1)), None, "{path:?}");
+        }
+        let _ = std::fs::remove_file(&symlink);
+    }
+}
@@ -4,12 +4,12 @@ pub mod named_pipe_listener_stream;
 use std::sync::Arc;
 use tokio::sync::Mutex;
 
-use super::{BitwardenDesktopAgent, SshAgentUIRequest};
+use super::{BitwardenDesktopAgent, SshAgentUIMessage, SshAgentUIResponse};
 
 impl BitwardenDesktopAgent {
     pub fn start_server(
-        auth_request_tx: tokio::sync::mpsc::Sender<SshAgentUIRequest>,
-        auth_response_rx: Arc<Mutex<tokio::sync::broadcast::Receiver<(u32, bool)>>>,
+        auth_request_tx: tokio::sync::mpsc::Sender<SshAgentUIMessage>,
+        auth_response_rx: Arc<Mutex<tokio::sync::broadcast::Receiver<SshAgentUIResponse>>>,
     ) -> Result<Self, anyhow::Error> {
         let agent_state = BitwardenDesktopAgent::new(auth_request_tx, auth_response_rx);
 
@@ -19,6 +19,7 @@ impl BitwardenDesktopAgent {
         );
 
         let cloned_agent_state = agent_state.clone();
+        let stream = cloned_agent_state.intercept_connections(stream);
         tokio::spawn(async move {
             cloned_agent_state
                 .is_running
@@ -34,6 +35,8 @@ impl BitwardenDesktopAgent {
                 .is_running
                 .store(false, std::sync::atomic::Ordering::Relaxed);
         });
+        agent_state.set_socket_path(named_pipe_listener_stream::PIPE_NAME.into());
+        agent_state.spawn_expiry_task();
         Ok(agent_state)
     }
 }
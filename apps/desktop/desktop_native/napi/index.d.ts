@@ -75,29 +75,259 @@ export declare namespace clipboards {
   export function write(text: string, password: boolean): Promise<void>
 }
 export declare namespace sshagent {
+  export const enum ApprovalPolicy {
+    AlwaysAsk = 0,
+    AskOncePerUnlock = 1,
+    AskOncePerProcess = 2,
+    NeverAsk = 3
+  }
+  export const enum ForwardingPolicy {
+    Allowed = 0,
+    Denied = 1,
+    /** Forwarded requests always show a prompt, regardless of the approval policy */
+    RequireConfirmation = 2
+  }
+  export const enum KeyLifetimeStart {
+    /** The lifetime starts when the key is loaded with `set_keys` */
+    Loaded = 0,
+    /** The lifetime starts with the first approved signature */
+    FirstUse = 1
+  }
+  export interface DestinationConstraint {
+    /** Host key of the forwarding host, or none for connections from this machine */
+    fromHostKey?: string
+    toHostKey: string
+  }
   export interface PrivateKey {
     privateKey: string
     name: string
     cipherId: string
+    /** Defaults to prompting according to the app's prompt setting */
+    approvalPolicy?: ApprovalPolicy
+    /** Defaults to denying forwarded requests */
+    forwardingPolicy?: ForwardingPolicy
+    /** OpenSSH certificate for the key, in `authorized_keys` format */
+    certificate?: string
+    /** When set, the key may only be used for the listed hosts */
+    destinationConstraints?: Array<DestinationConstraint>
+    /** When set, the private key is dropped this long after the lifetime started */
+    lifetimeSeconds?: number
+    /** Defaults to starting the lifetime when the key is loaded */
+    lifetimeStart?: KeyLifetimeStart
+  }
+  export interface LoadedSshKey {
+    name: string
+    /** Not set for keys added with `ssh-add` */
+    cipherId?: string
+    keyFingerprint?: string
+    isEphemeral: boolean
+    /** Unix time in seconds */
+    expiresAt?: number
+  }
+  export interface TrustedExecutable {
+    /** Absolute path of the executable */
+    path: string
+    /** SHA-256 of the executable, in hex */
+    sha256: string
+    /** Cipher ids of the keys that the executable may sign with without a prompt */
+    cipherIds: Array<string>
+  }
+  export interface PublicKeyExport {
+    /** Defaults to `~/.ssh/bitwarden`. Only files written by the export are replaced or removed. */
+    directory?: string
+    /** Also write all public keys to a single `authorized_keys` file */
+    authorizedKeys?: boolean
+  }
+  export interface PublicKeyCache {
+    accountId: string
+    /** From `get_public_key_cache_key` */
+    integrityKey: string
+    /** Defaults to the user's cache directory */
+    directory?: string
+  }
+  export interface SystemdUnits {
+    /** Contents of `bitwarden-ssh-agent.socket` */
+    socket: string
+    /** Contents of `bitwarden-ssh-agent.service` */
+    service: string
   }
   export interface SshKey {
     privateKey: string
     publicKey: string
     keyFingerprint: string
   }
+  export const enum SshKeyImportStatus {
+    Success = 0,
+    /** The key is encrypted and no passphrase was given */
+    PassphraseRequired = 1,
+    WrongPassphrase = 2,
+    ParsingError = 3,
+    UnsupportedCipher = 4,
+    UnsupportedAlgorithm = 5
+  }
+  export interface SshKeyImportResult {
+    status: SshKeyImportStatus
+    /** The imported key as an unencrypted OpenSSH key, when the import succeeded */
+    sshKey?: SshKey
+    /** Details of the error, such as the unsupported cipher or algorithm */
+    errorMessage?: string
+  }
+  export const enum KeyAlgorithm {
+    Ed25519 = 0,
+    Rsa2048 = 1,
+    Rsa3072 = 2,
+    Rsa4096 = 3,
+    EcdsaP256 = 4,
+    EcdsaP384 = 5,
+    EcdsaP521 = 6
+  }
   export interface SshUiRequest {
+    /** Identifies the request in `cancel_callback` of `serve` */
+    requestId: number
     cipherId?: string
+    keyName?: string
     isList: boolean
+    /** Set when a client asked to unlock the agent with `ssh-add -X` */
+    isUnlock: boolean
+    processName: string
+    /** Who started the process and where, e.g. "ssh launched by git commit in ~/src/foo" */
+    processDescription: string
+    isForwarding: boolean
+    namespace?: string
+    /** Hash algorithm of SSHSIG requests */
+    hashAlgorithm?: string
+    /** Start of the signed message hash of SSHSIG requests, in hex */
+    digestPreview?: string
+    /** User to log in as, for authentication requests */
+    userName?: string
+    /** Set when the data to sign is neither an authentication request nor an SSHSIG message */
+    isArbitraryData: boolean
+    /** Set when the prompt must be shown regardless of the app's prompt setting */
+    mustConfirm: boolean
+    /** Names of the destination host in the known_hosts files */
+    hostNames: Array<string>
+  }
+  export interface SshUiResponse {
+    accepted: boolean
+    /** Approve identical requests without prompting for this many minutes */
+    rememberForMinutes?: number
+  }
+  export const enum SshAuditEventKind {
+    List = 0,
+    Sign = 1,
+    Lock = 2,
+    Unlock = 3
+  }
+  export const enum SshApprovalOutcome {
+    Approved = 0,
+    AutoApproved = 1,
+    Denied = 2
+  }
+  export interface SshAuditEntry {
+    /** Unix time in seconds */
+    timestamp: number
+    kind: SshAuditEventKind
+    keyFingerprint?: string
+    cipherId?: string
+    pid: number
+    uid: number
     processName: string
     isForwarding: boolean
+    hostKey?: string
     namespace?: string
+    outcome: SshApprovalOutcome
+    hash: string
   }
-  export function serve(callback: (err: Error | null, arg: SshUiRequest) => any): Promise<SshAgentState>
+  export function serve(callback: (err: Error | null, arg: SshUiRequest) => any, cancelCallback: (err: Error | null, arg: number) => any): Promise<SshAgentState>
   export function stop(agentState: SshAgentState): void
   export function isRunning(agentState: SshAgentState): boolean
   export function setKeys(agentState: SshAgentState, newKeys: Array<PrivateKey>): void
+  /** When enabled, forwarded connections only list keys whose forwarding policy is `Allowed`. */
+  export function setHideKeysWhenForwarded(agentState: SshAgentState, hide: boolean): void
+  /**
+   * Chains to the agent listening on `path`, so that its keys are listed after the vault keys
+   * and it signs with them. Passing no path disables chaining.
+   */
+  export function setUpstreamAgent(agentState: SshAgentState, path?: string | undefined | null): void
+  /**
+   * Writes the public keys of the vault keys to disk and keeps them in sync with `set_keys`.
+   * Passing no export stops syncing and leaves the files in place.
+   */
+  export function setPublicKeyExport(agentState: SshAgentState, publicKeyExport?: PublicKeyExport | undefined | null): void
+  /**
+   * Returns the key that the public key cache of the account is authenticated with, from the
+   * OS keychain. The key is created on first use.
+   */
+  export function getPublicKeyCacheKey(accountId: string): Promise<string>
+  /**
+   * Lists the cached public keys of the account while listing would otherwise require
+   * unlocking the vault, and keeps the cache in sync with `set_keys`. `clear_keys` disables
+   * the cache, since it belongs to the previous account.
+   */
+  export function setPublicKeyCache(agentState: SshAgentState, publicKeyCache?: PublicKeyCache | undefined | null): void
+  export function setTrustedExecutables(agentState: SshAgentState, trustedExecutables: Array<TrustedExecutable>): void
+  /** Returns the SHA-256 of the executable at `path`, in hex, for registering it as trusted. */
+  export function hashExecutable(path: string): Promise<string>
+  /** Sets how long prompts wait for an answer before the request is denied. */
+  export function setPromptTimeout(agentState: SshAgentState, timeoutSeconds: number): void
+  export interface SshKeyStatus {
+    name: string
+    /** Not set for keys added with `ssh-add` */
+    cipherId?: string
+    keyFingerprint?: string
+    /** Whether the private key is available, i.e. neither locked nor expired */
+    isLoaded: boolean
+    signaturesApproved: number
+    signaturesDenied: number
+    /** Unix time in seconds of the most recent sign request, approved or not */
+    lastUsed?: number
+    /** Process that sent the most recent sign request */
+    lastClient?: string
+  }
+  export interface SshAgentStatus {
+    isRunning: boolean
+    /** Number of keys whose private key is available */
+    loadedKeyCount: number
+    /**
+     * Set while a client locked the agent with `ssh-add -x`. A locked vault shows in
+     * `loaded_key_count` instead.
+     */
+    isClientLocked: boolean
+    /** Listing keys requires unlocking the vault first */
+    needsUnlock: boolean
+    /** Socket or named pipe that clients connect to */
+    socketPath?: string
+    connectedClients: number
+    keys: Array<SshKeyStatus>
+  }
+  export function getStatus(agentState: SshAgentState): SshAgentStatus
+  /** Returns the keys whose private key is currently loaded, and when they expire. */
+  export function getLoadedKeys(agentState: SshAgentState): Array<LoadedSshKey>
   export function lock(agentState: SshAgentState): void
   export function clearKeys(agentState: SshAgentState): void
+  /**
+   * Generates systemd user units that hold the agent socket while the app at `executable_path`
+   * is not running, and start it on the first connection. Linux only.
+   */
+  export function generateSystemdUnits(executablePath: string): SystemdUnits
+  /**
+   * Writes the units of `generate_systemd_units` to the systemd user unit directory and
+   * returns it. Linux only.
+   */
+  export function installSystemdUnits(executablePath: string): string
+  /** Generates a new key. RSA keys take a while to generate, so this runs off the main thread. */
+  export function generateKeypair(keyAlgorithm: KeyAlgorithm, comment?: string | undefined | null): Promise<SshKey>
+  /**
+   * Imports an OpenSSH, PKCS#1, SEC1, PKCS#8 or PuTTY private key, decrypting it with the
+   * passphrase. The key derivation of encrypted keys is slow, so this runs off the main thread.
+   */
+  export function importKey(encodedKey: Buffer, passphrase?: string | undefined | null): Promise<SshKeyImportResult>
+  /** Appends all agent activity to the hash-chained log at `path`. */
+  export function enableAuditLog(agentState: SshAgentState, path: string): void
+  /** Returns up to `limit` of the most recent audit entries, newest first. */
+  export function getAuditEntries(agentState: SshAgentState, limit: number): Array<SshAuditEntry>
+  /** Checks that the audit log at `path` was not modified and returns its number of entries. */
+  export function verifyAuditLog(path: string): number
   export class SshAgentState {   }
 }
 export declare namespace processisolations {
@@ -286,9 +286,12 @@ pub mod clipboards {
 pub mod sshagent {
     use std::sync::Arc;
 
+    use desktop_core::ssh_agent::importer::ImportError;
     use napi::{
         bindgen_prelude::Promise,
-        threadsafe_function::{ErrorStrategy::CalleeHandled, ThreadsafeFunction},
+        threadsafe_function::{
+            ErrorStrategy::CalleeHandled, ThreadsafeFunction, ThreadsafeFunctionCallMode,
+        },
     };
     use tokio::{self, sync::Mutex};
     use tracing::error;
@@ -298,11 +301,150 @@ pub mod sshagent {
         state: desktop_core::ssh_agent::BitwardenDesktopAgent,
     }
 
+    #[napi]
+    pub enum ApprovalPolicy {
+        AlwaysAsk,
+        AskOncePerUnlock,
+        AskOncePerProcess,
+        NeverAsk,
+    }
+
+    impl From<ApprovalPolicy> for desktop_core::ssh_agent::ApprovalPolicy {
+        fn from(policy: ApprovalPolicy) -> Self {
+            match policy {
+                ApprovalPolicy::AlwaysAsk => desktop_core::ssh_agent::ApprovalPolicy::AlwaysAsk,
+                ApprovalPolicy::AskOncePerUnlock => {
+                    desktop_core::ssh_agent::ApprovalPolicy::AskOncePerUnlock
+                }
+                ApprovalPolicy::AskOncePerProcess => {
+                    desktop_core::ssh_agent::ApprovalPolicy::AskOncePerProcess
+                }
+                ApprovalPolicy::NeverAsk => desktop_core::ssh_agent::ApprovalPolicy::NeverAsk,
+            }
+        }
+    }
+
+    #[napi]
+    pub enum ForwardingPolicy {
+        Allowed,
+        Denied,
+        /// Forwarded requests always show a prompt, regardless of the approval policy
+        RequireConfirmation,
+    }
+
+    impl From<ForwardingPolicy> for desktop_core::ssh_agent::ForwardingPolicy {
+        fn from(policy: ForwardingPolicy) -> Self {
+            match policy {
+                ForwardingPolicy::Allowed => desktop_core::ssh_agent::ForwardingPolicy::Allowed,
+                ForwardingPolicy::Denied => desktop_core::ssh_agent::ForwardingPolicy::Denied,
+                ForwardingPolicy::RequireConfirmation => {
+                    desktop_core::ssh_agent::ForwardingPolicy::RequireConfirmation
+                }
+            }
+        }
+    }
+
+    #[napi]
+    pub enum KeyLifetimeStart {
+        /// The lifetime starts when the key is loaded with `set_keys`
+        Loaded,
+        /// The lifetime starts with the first approved signature
+        FirstUse,
+    }
+
+    impl From<KeyLifetimeStart> for desktop_core::ssh_agent::LifetimeStart {
+        fn from(start: KeyLifetimeStart) -> Self {
+            match start {
+                KeyLifetimeStart::Loaded => desktop_core::ssh_agent::LifetimeStart::Loaded,
+                KeyLifetimeStart::FirstUse => desktop_core::ssh_agent::LifetimeStart::FirstUse,
+            }
+        }
+    }
+
+    #[napi(object)]
+    pub struct DestinationConstraint {
+        /// Host key of the forwarding host, or none for connections from this machine
+        pub from_host_key: Option<String>,
+        pub to_host_key: String,
+    }
+
     #[napi(object)]
     pub struct PrivateKey {
         pub private_key: String,
         pub name: String,
         pub cipher_id: String,
+        /// Defaults to prompting according to the app's prompt setting
+        pub approval_policy: Option<ApprovalPolicy>,
+        /// Defaults to denying forwarded requests
+        pub forwarding_policy: Option<ForwardingPolicy>,
+        /// OpenSSH certificate for the key, in `authorized_keys` format
+        pub certificate: Option<String>,
+        /// When set, the key may only be used for the listed hosts
+        pub destination_constraints: Option<Vec<DestinationConstraint>>,
+        /// When set, the private key is dropped this long after the lifetime started
+        pub lifetime_seconds: Option<u32>,
+        /// Defaults to starting the lifetime when the key is loaded
+        pub lifetime_start: Option<KeyLifetimeStart>,
+    }
+
+    #[napi(object)]
+    pub struct LoadedSshKey {
+        pub name: String,
+        /// Not set for keys added with `ssh-add`
+        pub cipher_id: Option<String>,
+        pub key_fingerprint: Option<String>,
+        pub is_ephemeral: bool,
+        /// Unix time in seconds
+        pub expires_at: Option<i64>,
+    }
+
+    impl From<desktop_core::ssh_agent::LoadedKey> for LoadedSshKey {
+        fn from(key: desktop_core::ssh_agent::LoadedKey) -> Self {
+            LoadedSshKey {
+                name: key.name,
+                cipher_id: key.cipher_id,
+                key_fingerprint: key.fingerprint,
+                is_ephemeral: key.is_ephemeral,
+                expires_at: key
+                    .expires_at
+                    .map(|expires_at| i64::try_from(expires_at).unwrap_or(i64::MAX)),
+            }
+        }
+    }
+
+    #[napi(object)]
+    pub struct TrustedExecutable {
+        /// Absolute path of the executable
+        pub path: String,
+        /// SHA-256 of the executable, in hex
+        pub sha256: String,
+        /// Cipher ids of the keys that the executable may sign with without a prompt
+        pub cipher_ids: Vec<String>,
+    }
+
+    #[napi(object)]
+    pub struct PublicKeyExport {
+        /// Defaults to `~/.ssh/bitwarden`. Only files written by the export are replaced or removed.
+        pub directory: Option<String>,
+        /// Also write all public keys to a single `authorized_keys` file
+        pub authorized_keys: Option<bool>,
+    }
+
+    #[napi(object)]
+    pub struct PublicKeyCache {
+        pub account_id: String,
+        /// From `get_public_key_cache_key`
+        pub integrity_key: String,
+        /// Defaults to the user's cache directory
+        pub directory: Option<String>,
+    }
+
+    #[napi(object)]
+    pub struct SystemdUnits {
+        /// Contents of `bitwarden-ssh-agent.socket`
+        pub socket: String,
+        /// Contents of `bitwarden-ssh-agent.service`
+        pub service: String,
     }
 
     #[napi(object)]
@@ -312,69 +454,272 @@ pub mod sshagent {
         pub key_fingerprint: String,
     }
 
+    impl From<desktop_core::ssh_agent::keygen::SshKeyPair> for SshKey {
+        fn from(key: desktop_core::ssh_agent::keygen::SshKeyPair) -> Self {
+            SshKey {
+                private_key: key.private_key,
+                public_key: key.public_key,
+                key_fingerprint: key.key_fingerprint,
+            }
+        }
+    }
+
+    #[napi]
+    pub enum KeyAlgorithm {
+        Ed25519,
+        Rsa2048,
+        Rsa3072,
+        Rsa4096,
+        EcdsaP256,
+        EcdsaP384,
+        EcdsaP521,
+    }
+
+    impl From<KeyAlgorithm> for desktop_core::ssh_agent::keygen::KeyAlgorithm {
+        fn from(algorithm: KeyAlgorithm) -> Self {
+            use desktop_core::ssh_agent::keygen::KeyAlgorithm as CoreKeyAlgorithm;
+            match algorithm {
+                KeyAlgorithm::Ed25519 => CoreKeyAlgorithm::Ed25519,
+                KeyAlgorithm::Rsa2048 => CoreKeyAlgorithm::Rsa2048,
+                KeyAlgorithm::Rsa3072 => CoreKeyAlgorithm::Rsa3072,
+                KeyAlgorithm::Rsa4096 => CoreKeyAlgorithm::Rsa4096,
+                KeyAlgorithm::EcdsaP256 => CoreKeyAlgorithm::EcdsaP256,
+                KeyAlgorithm::EcdsaP384 => CoreKeyAlgorithm::EcdsaP384,
+                KeyAlgorithm::EcdsaP521 => CoreKeyAlgorithm::EcdsaP521,
+            }
+        }
+    }
+
+    #[napi]
+    pub enum SshKeyImportStatus {
+        Success,
+        /// The key is encrypted and no passphrase was given
+        PassphraseRequired,
+        WrongPassphrase,
+        ParsingError,
+        UnsupportedCipher,
+        UnsupportedAlgorithm,
+    }
+
+    #[napi(object)]
+    pub struct SshKeyImportResult {
+        pub status: SshKeyImportStatus,
+        /// The imported key as an unencrypted OpenSSH key, when the import succeeded
+        pub ssh_key: Option<SshKey>,
+        /// Details of the error, such as the unsupported cipher or algorithm
+        pub error_message: Option<String>,
+    }
+
+    impl From<Result<desktop_core::ssh_agent::keygen::SshKeyPair, ImportError>> for SshKeyImportResult {
+        fn from(result: Result<desktop_core::ssh_agent::keygen::SshKeyPair, ImportError>) -> Self {
+            let status = match &result {
+                Ok(_) => SshKeyImportStatus::Success,
+                Err(ImportError::PassphraseRequired) => SshKeyImportStatus::PassphraseRequired,
+                Err(ImportError::WrongPassphrase) => SshKeyImportStatus::WrongPassphrase,
+                Err(ImportError::Parsing(_)) => SshKeyImportStatus::ParsingError,
+                Err(ImportError::UnsupportedCipher(_)) => SshKeyImportStatus::UnsupportedCipher,
+                Err(ImportError::UnsupportedAlgorithm(_)) => {
+                    SshKeyImportStatus::UnsupportedAlgorithm
+                }
+            };
+            match result {
+                Ok(key) => SshKeyImportResult {
+                    status,
+                    ssh_key: Some(key.into()),
+                    error_message: None,
+                },
+                Err(e) => SshKeyImportResult {
+                    status,
+                    ssh_key: None,
+                    error_message: Some(e.to_string()),
+                },
+            }
+        }
+    }
+
     #[napi(object)]
     pub struct SshUIRequest {
+        /// Identifies the request in `cancel_callback` of `serve`
+        pub request_id: u32,
         pub cipher_id: Option<String>,
+        pub key_name: Option<String>,
         pub is_list: bool,
+        /// Set when a client asked to unlock the agent with `ssh-add -X`
+        pub is_unlock: bool,
+        pub process_name: String,
+        /// Who started the process and where, e.g. "ssh launched by git commit in ~/src/foo"
+        pub process_description: String,
+        pub is_forwarding: bool,
+        pub namespace: Option<String>,
+        /// Hash algorithm of SSHSIG requests
+        pub hash_algorithm: Option<String>,
+        /// Start of the signed message hash of SSHSIG requests, in hex
+        pub digest_preview: Option<String>,
+        /// User to log in as, for authentication requests
+        pub user_name: Option<String>,
+        /// Set when the data to sign is neither an authentication request nor an SSHSIG message
+        pub is_arbitrary_data: bool,
+        /// Set when the prompt must be shown regardless of the app's prompt setting
+        pub must_confirm: bool,
+        /// Names of the destination host in the known_hosts files
+        pub host_names: Vec<String>,
+    }
+
+    #[napi(object)]
+    pub struct SshUIResponse {
+        pub accepted: bool,
+        /// Approve identical requests without prompting for this many minutes
+        pub remember_for_minutes: Option<u32>,
+    }
+
+    #[napi]
+    pub enum SshAuditEventKind {
+        List,
+        Sign,
+        Lock,
+        Unlock,
+    }
+
+    impl From<desktop_core::ssh_agent::AuditEventKind> for SshAuditEventKind {
+        fn from(kind: desktop_core::ssh_agent::AuditEventKind) -> Self {
+            match kind {
+                desktop_core::ssh_agent::AuditEventKind::List => SshAuditEventKind::List,
+                desktop_core::ssh_agent::AuditEventKind::Sign => SshAuditEventKind::Sign,
+                desktop_core::ssh_agent::AuditEventKind::Lock => SshAuditEventKind::Lock,
+                desktop_core::ssh_agent::AuditEventKind::Unlock => SshAuditEventKind::Unlock,
+            }
+        }
+    }
+
+    #[napi]
+    pub enum SshApprovalOutcome {
+        Approved,
+        AutoApproved,
+        Denied,
+    }
+
+    impl From<desktop_core::ssh_agent::ApprovalOutcome> for SshApprovalOutcome {
+        fn from(outcome: desktop_core::ssh_agent::ApprovalOutcome) -> Self {
+            match outcome {
+                desktop_core::ssh_agent::ApprovalOutcome::Approved => SshApprovalOutcome::Approved,
+                desktop_core::ssh_agent::ApprovalOutcome::AutoApproved => {
+                    SshApprovalOutcome::AutoApproved
+                }
+                desktop_core::ssh_agent::ApprovalOutcome::Denied => SshApprovalOutcome::Denied,
+            }
+        }
+    }
+
+    #[napi(object)]
+    pub struct SshAuditEntry {
+        /// Unix time in seconds
+        pub timestamp: i64,
+        pub kind: SshAuditEventKind,
+        pub key_fingerprint: Option<String>,
+        pub cipher_id: Option<String>,
+        pub pid: u32,
+        pub uid: u32,
         pub process_name: String,
         pub is_forwarding: bool,
+        pub host_key: Option<String>,
         pub namespace: Option<String>,
+        pub outcome: SshApprovalOutcome,
+        pub hash: String,
+    }
+
+    impl From<desktop_core::ssh_agent::AuditEntry> for SshAuditEntry {
+        fn from(entry: desktop_core::ssh_agent::AuditEntry) -> Self {
+            let record = entry.record;
+            SshAuditEntry {
+                timestamp: i64::try_from(record.timestamp).unwrap_or(i64::MAX),
+                kind: record.kind.into(),
+                key_fingerprint: record.key_fingerprint,
+                cipher_id: record.cipher_id,
+                pid: record.pid,
+                uid: record.uid,
+                process_name: record.process_name,
+                is_forwarding: record.is_forwarding,
+                host_key: record.host_key,
+                namespace: record.namespace,
+                outcome: record.outcome.into(),
+                hash: entry.hash,
+            }
+        }
     }
 
     #[allow(clippy::unused_async)] // FIXME: Remove unused async!
     #[napi]
     pub async fn serve(
         callback: ThreadsafeFunction<SshUIRequest, CalleeHandled>,
+        cancel_callback: ThreadsafeFunction<u32, CalleeHandled>,
     ) -> napi::Result<SshAgentState> {
         let (auth_request_tx, mut auth_request_rx) =
-            tokio::sync::mpsc::channel::<desktop_core::ssh_agent::SshAgentUIRequest>(32);
+            tokio::sync::mpsc::channel::<desktop_core::ssh_agent::SshAgentUIMessage>(32);
         let (auth_response_tx, auth_response_rx) =
-            tokio::sync::broadcast::channel::<(u32, bool)>(32);
+            tokio::sync::broadcast::channel::<desktop_core::ssh_agent::SshAgentUIResponse>(32);
         let auth_response_tx_arc = Arc::new(Mutex::new(auth_response_tx));
         tokio::spawn(async move {
             let _ = auth_response_rx;
 
-            while let Some(request) = auth_request_rx.recv().await {
+            while let Some(message) = auth_request_rx.recv().await {
+                let request = match message {
+                    desktop_core::ssh_agent::SshAgentUIMessage::Request(request) => request,
+                    desktop_core::ssh_agent::SshAgentUIMessage::Cancel { request_id } => {
+                        cancel_callback
+                            .call(Ok(request_id), ThreadsafeFunctionCallMode::NonBlocking);
+                        continue;
+                    }
+                };
                 let cloned_response_tx_arc = auth_response_tx_arc.clone();
                 let cloned_callback = callback.clone();
                 tokio::spawn(async move {
                     let auth_response_tx_arc = cloned_response_tx_arc;
                     let callback = cloned_callback;
-                    let promise_result: Result<Promise<bool>, napi::Error> = callback
+                    let promise_result: Result<Promise<SshUIResponse>, napi::Error> = callback
                         .call_async(Ok(SshUIRequest {
+                            request_id: request.request_id,
                             cipher_id: request.cipher_id,
+                            key_name: request.key_name,
                             is_list: request.is_list,
+                            is_unlock: request.is_unlock,
                             process_name: request.process_name,
+                            process_description: request.process_description,
                             is_forwarding: request.is_forwarding,
                             namespace: request.namespace,
+                            hash_algorithm: request.hash_algorithm,
+                            digest_preview: request.digest_preview,
+                            user_name: request.user_name,
+                            is_arbitrary_data: request.is_arbitrary_data,
+                            must_confirm: request.must_confirm,
+                            host_names: request.host_names,
                         }))
                         .await;
-                    match promise_result {
+                    let denied = desktop_core::ssh_agent::SshAgentUIResponse {
+                        request_id: request.request_id,
+                        accepted: false,
+                        remember_for: None,
+                    };
+                    let response = match promise_result {
                         Ok(promise_result) => match promise_result.await {
-                            Ok(result) => {
-                                let _ = auth_response_tx_arc
-                                    .lock()
-                                    .await
-                                    .send((request.request_id, result))
-                                    .expect("should be able to send auth response to agent");
-                            }
+                            Ok(result) => desktop_core::ssh_agent::SshAgentUIResponse {
+                                request_id: request.request_id,
+                                accepted: result.accepted,
+                                remember_for: result.remember_for_minutes.map(|minutes| {
+                                    std::time::Duration::from_secs(u64::from(minutes) * 60)
+                                }),
+                            },
                             Err(e) => {
                                 error!(error = %e, "Calling UI callback promise was rejected");
-                                let _ = auth_response_tx_arc
-                                    .lock()
-                                    .await
-                                    .send((request.request_id, false))
-                                    .expect("should be able to send auth response to agent");
+                                denied
                             }
                         },
                         Err(e) => {
                             error!(error = %e, "Calling UI callback could not create promise");
-                            let _ = auth_response_tx_arc
-                                .lock()
-                                .await
-                                .send((request.request_id, false))
-                                .expect("should be able to send auth response to agent");
+                            denied
                         }
+                    };
+                    if auth_response_tx_arc.lock().await.send(response).is_err() {
+                        error!("SSH agent is no longer waiting for UI responses");
                     }
                 });
             }
@@ -411,14 +756,238 @@ pub mod sshagent {
         bitwarden_agent_state
             .set_keys(
                 new_keys
-                    .iter()
-                    .map(|k| (k.private_key.clone(), k.name.clone(), k.cipher_id.clone()))
+                    .into_iter()
+                    .map(|k| desktop_core::ssh_agent::VaultSshKey {
+                        private_key: k.private_key,
+                        name: k.name,
+                        cipher_id: k.cipher_id,
+                        approval_policy: k.approval_policy.map(Into::into).unwrap_or_default(),
+                        forwarding_policy: k.forwarding_policy.map(Into::into).unwrap_or_default(),
+                        certificate: k.certificate,
+                        destination_constraints: k
+                            .destination_constraints
+                            .unwrap_or_default()
+                            .into_iter()
+                            .map(|c| desktop_core::ssh_agent::DestinationConstraint {
+                                from_host_key: c.from_host_key,
+                                to_host_key: c.to_host_key,
+                            })
+                            .collect(),
+                        lifetime: k.lifetime_seconds.map(|seconds| {
+                            desktop_core::ssh_agent::KeyLifetime {
+                                duration: std::time::Duration::from_secs(u64::from(seconds)),
+                                start: k.lifetime_start.map(Into::into).unwrap_or_default(),
+                            }
+                        }),
+                    })
                     .collect(),
             )
             .map_err(|e| napi::Error::from_reason(e.to_string()))?;
         Ok(())
     }
 
+    /// When enabled, forwarded connections only list keys whose forwarding policy is `Allowed`.
+    #[napi]
+    pub fn set_hide_keys_when_forwarded(agent_state: &mut SshAgentState, hide: bool) {
+        agent_state.state.set_hide_keys_when_forwarded(hide);
+    }
+
+    /// Chains to the agent listening on `path`, so that its keys are listed after the vault keys
+    /// and it signs with them. `None` disables chaining.
+    #[napi]
+    pub fn set_upstream_agent(agent_state: &mut SshAgentState, path: Option<String>) {
+        agent_state
+            .state
+            .set_upstream_agent(path.map(std::path::PathBuf::from));
+    }
+
+    /// Writes the public keys of the vault keys to disk and keeps them in sync with `set_keys`.
+    /// Passing no export stops syncing and leaves the files in place.
+    #[napi]
+    pub fn set_public_key_export(
+        agent_state: &mut SshAgentState,
+        public_key_export: Option<PublicKeyExport>,
+    ) -> napi::Result<()> {
+        let public_key_export = match public_key_export {
+            Some(export) => Some(desktop_core::ssh_agent::PublicKeyExport {
+                directory: export
+                    .directory
+                    .map(std::path::PathBuf::from)
+                    .or_else(desktop_core::ssh_agent::PublicKeyExport::default_directory)
+                    .ok_or_else(|| {
+                        napi::Error::from_reason("Could not determine home directory")
+                    })?,
+                authorized_keys: export.authorized_keys.unwrap_or(false),
+            }),
+            None => None,
+        };
+        agent_state.state.set_public_key_export(public_key_export);
+        Ok(())
+    }
+
+    /// Keychain service that holds the integrity keys of the public key caches, by account id
+    const PUBLIC_KEY_CACHE_SERVICE: &str = "Bitwarden SSH agent key cache";
+
+    /// Returns the key that the public key cache of the account is authenticated with, from the
+    /// OS keychain. The key is created on first use.
+    #[napi]
+    pub async fn get_public_key_cache_key(account_id: String) -> napi::Result<String> {
+        match desktop_core::password::get_password(PUBLIC_KEY_CACHE_SERVICE, &account_id).await {
+            Ok(key) => Ok(key),
+            Err(e) if e.to_string() == desktop_core::password::PASSWORD_NOT_FOUND => {
+                let key = desktop_core::ssh_agent::PublicKeyCache::generate_integrity_key();
+                desktop_core::password::set_password(PUBLIC_KEY_CACHE_SERVICE, &account_id, &key)
+                    .await
+                    .map_err(|e| napi::Error::from_reason(e.to_string()))?;
+                Ok(key)
+            }
+            Err(e) => Err(napi::Error::from_reason(e.to_string())),
+        }
+    }
+
+    /// Lists the cached public keys of the account while listing would otherwise require
+    /// unlocking the vault, and keeps the cache in sync with `set_keys`. `clear_keys` disables
+    /// the cache, since it belongs to the previous account.
+    #[napi]
+    pub fn set_public_key_cache(
+        agent_state: &mut SshAgentState,
+        public_key_cache: Option<PublicKeyCache>,
+    ) -> napi::Result<()> {
+        let public_key_cache = match public_key_cache {
+            Some(cache) => Some(desktop_core::ssh_agent::PublicKeyCache {
+                directory: cache
+                    .directory
+                    .map(std::path::PathBuf::from)
+                    .or_else(desktop_core::ssh_agent::PublicKeyCache::default_directory)
+                    .ok_or_else(|| {
+                        napi::Error::from_reason("Could not determine cache directory")
+                    })?,
+                account_id: cache.account_id,
+                integrity_key: cache.integrity_key.into_bytes(),
+            }),
+            None => None,
+        };
+        agent_state.state.set_public_key_cache(public_key_cache);
+        Ok(())
+    }
+
+    #[napi]
+    pub fn set_trusted_executables(
+        agent_state: &mut SshAgentState,
+        trusted_executables: Vec<TrustedExecutable>,
+    ) {
+        agent_state.state.set_trusted_executables(
+            trusted_executables
+                .into_iter()
+                .map(|t| desktop_core::ssh_agent::TrustedExecutable {
+                    path: t.path.into(),
+                    sha256: t.sha256,
+                    cipher_ids: t.cipher_ids,
+                })
+                .collect(),
+        );
+    }
+
+    /// Returns the SHA-256 of the executable at `path`, in hex, for registering it as trusted.
+    #[napi]
+    pub async fn hash_executable(path: String) -> napi::Result<String> {
+        tokio::task::spawn_blocking(move || {
+            desktop_core::ssh_agent::executable_sha256(std::path::Path::new(&path))
+        })
+        .await
+        .map_err(|e| napi::Error::from_reason(e.to_string()))?
+        .map_err(|e| napi::Error::from_reason(e.to_string()))
+    }
+
+    /// Sets how long prompts wait for an answer before the request is denied.
+    #[napi]
+    pub fn set_prompt_timeout(agent_state: &mut SshAgentState, timeout_seconds: u32) {
+        agent_state
+            .state
+            .set_prompt_timeout(std::time::Duration::from_secs(u64::from(timeout_seconds)));
+    }
+
+    #[napi(object)]
+    pub struct SshKeyStatus {
+        pub name: String,
+        /// Not set for keys added with `ssh-add`
+        pub cipher_id: Option<String>,
+        pub key_fingerprint: Option<String>,
+        /// Whether the private key is available, i.e. neither locked nor expired
+        pub is_loaded: bool,
+        pub signatures_approved: u32,
+        pub signatures_denied: u32,
+        /// Unix time in seconds of the most recent sign request, approved or not
+        pub last_used: Option<i64>,
+        /// Process that sent the most recent sign request
+        pub last_client: Option<String>,
+    }
+
+    impl From<desktop_core::ssh_agent::KeyStatus> for SshKeyStatus {
+        fn from(key: desktop_core::ssh_agent::KeyStatus) -> Self {
+            SshKeyStatus {
+                name: key.name,
+                cipher_id: key.cipher_id,
+                key_fingerprint: key.fingerprint,
+                is_loaded: key.is_loaded,
+                signatures_approved: u32::try_from(key.signatures_approved).unwrap_or(u32::MAX),
+                signatures_denied: u32::try_from(key.signatures_denied).unwrap_or(u32::MAX),
+                last_used: key
+                    .last_used
+                    .map(|last_used| i64::try_from(last_used).unwrap_or(i64::MAX)),
+                last_client: key.last_client,
+            }
+        }
+    }
+
+    #[napi(object)]
+    pub struct SshAgentStatus {
+        pub is_running: bool,
+        /// Number of keys whose private key is available
+        pub loaded_key_count: u32,
+        /// Set while a client locked the agent with `ssh-add -x`. A locked vault shows in
+        /// `loaded_key_count` instead.
+        pub is_client_locked: bool,
+        /// Listing keys requires unlocking the vault first
+        pub needs_unlock: bool,
+        /// Socket or named pipe that clients connect to
+        pub socket_path: Option<String>,
+        pub connected_clients: u32,
+        pub keys: Vec<SshKeyStatus>,
+    }
+
+    impl From<desktop_core::ssh_agent::AgentStatus> for SshAgentStatus {
+        fn from(status: desktop_core::ssh_agent::AgentStatus) -> Self {
+            SshAgentStatus {
+                is_running: status.is_running,
+                loaded_key_count: u32::try_from(status.loaded_key_count).unwrap_or(u32::MAX),
+                is_client_locked: status.is_client_locked,
+                needs_unlock: status.needs_unlock,
+                socket_path: status
+                    .socket_path
+                    .map(|path| path.to_string_lossy().to_string()),
+                connected_clients: u32::try_from(status.connected_clients).unwrap_or(u32::MAX),
+                keys: status.keys.into_iter().map(Into::into).collect(),
+            }
+        }
+    }
+
+    #[napi]
+    pub fn get_status(agent_state: &mut SshAgentState) -> SshAgentStatus {
+        agent_state.state.status().into()
+    }
+
+    /// Returns the keys whose private key is currently loaded, and when they expire.
+    #[napi]
+    pub fn get_loaded_keys(agent_state: &mut SshAgentState) -> Vec<LoadedSshKey> {
+        agent_state
+            .state
+            .loaded_keys()
+            .into_iter()
+            .map(Into::into)
+            .collect()
+    }
+
     #[napi]
     pub fn lock(agent_state: &mut SshAgentState) -> napi::Result<()> {
         let bitwarden_agent_state = &mut agent_state.state;
@@ -434,6 +1003,91 @@ pub mod sshagent {
             .clear_keys()
             .map_err(|e| napi::Error::from_reason(e.to_string()))
     }
+
+    /// Generates systemd user units that hold the agent socket while the app at `executable_path`
+    /// is not running, and start it on the first connection. Linux only.
+    #[cfg(target_os = "linux")]
+    #[napi]
+    pub fn generate_systemd_units(executable_path: String) -> SystemdUnits {
+        let units =
+            desktop_core::ssh_agent::systemd::systemd_units(std::path::Path::new(&executable_path));
+        SystemdUnits {
+            socket: units.socket,
+            service: units.service,
+        }
+    }
+
+    /// Writes the units of `generate_systemd_units` to the systemd user unit directory and
+    /// returns it. Linux only.
+    #[cfg(target_os = "linux")]
+    #[napi]
+    pub fn install_systemd_units(executable_path: String) -> napi::Result<String> {
+        desktop_core::ssh_agent::systemd::install_systemd_units(std::path::Path::new(
+            &executable_path,
+        ))
+        .map(|directory| directory.to_string_lossy().to_string())
+        .map_err(|e| napi::Error::from_reason(e.to_string()))
+    }
+
+    /// Generates a new key. RSA keys take a while to generate, so this runs off the main thread.
+    #[napi]
+    pub async fn generate_keypair(
+        key_algorithm: KeyAlgorithm,
+        comment: Option<String>,
+    ) -> napi::Result<SshKey> {
+        let algorithm = key_algorithm.into();
+        tokio::task::spawn_blocking(move || {
+            desktop_core::ssh_agent::keygen::generate_keypair(algorithm, comment.as_deref())
+        })
+        .await
+        .map_err(|e| napi::Error::from_reason(e.to_string()))?
+        .map(Into::into)
+        .map_err(|e| napi::Error::from_reason(e.to_string()))
+    }
+
+    /// Imports an OpenSSH, PKCS#1, SEC1, PKCS#8 or PuTTY private key, decrypting it with the
+    /// passphrase. The key derivation of encrypted keys is slow, so this runs off the main thread.
+    #[napi]
+    pub async fn import_key(
+        encoded_key: napi::bindgen_prelude::Buffer,
+        passphrase: Option<String>,
+    ) -> napi::Result<SshKeyImportResult> {
+        let encoded_key = encoded_key.to_vec();
+        tokio::task::spawn_blocking(move || {
+            desktop_core::ssh_agent::importer::import_key(&encoded_key, passphrase.as_deref())
+        })
+        .await
+        .map(Into::into)
+        .map_err(|e| napi::Error::from_reason(e.to_string()))
+    }
+
+    /// Appends all agent activity to the hash-chained log at `path`.
+    #[napi]
+    pub fn enable_audit_log(agent_state: &mut SshAgentState, path: String) -> napi::Result<()> {
+        agent_state
+            .state
+            .enable_audit_log(std::path::Path::new(&path))
+            .map_err(|e| napi::Error::from_reason(e.to_string()))
+    }
+
+    /// Returns up to `limit` of the most recent audit entries, newest first.
+    #[napi]
+    pub fn get_audit_entries(agent_state: &mut SshAgentState, limit: u32) -> Vec<SshAuditEntry> {
+        agent_state
+            .state
+            .audit_entries(limit as usize)
+            .into_iter()
+            .map(Into::into)
+            .collect()
+    }
+
+    /// Checks that the audit log at `path` was not modified and returns its number of entries.
+    #[napi]
+    pub fn verify_audit_log(path: String) -> napi::Result<u32> {
+        let entries = desktop_core::ssh_agent::verify_audit_log(std::path::Path::new(&path))
+            .map_err(|e| napi::Error::from_reason(e.to_string()))?;
+        u32::try_from(entries).map_err(|e| napi::Error::from_reason(e.to_string()))
+    }
 }
 
 #[napi]
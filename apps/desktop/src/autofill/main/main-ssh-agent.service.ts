@@ -1,6 +1,8 @@
 // FIXME: Update this file to be type safe and remove this and next line
 // @ts-strict-ignore
-import { ipcMain } from "electron";
+import * as path from "path";
+
+import { app, ipcMain } from "electron";
 import { concatMap, delay, filter, firstValueFrom, from, race, take, timer } from "rxjs";
 
 import { LogService } from "@bitwarden/common/platform/abstractions/log.service";
@@ -10,15 +12,19 @@ import { sshagent } from "@bitwarden/desktop-napi";
 class AgentResponse {
   requestId: number;
   accepted: boolean;
+  rememberForMinutes?: number;
   timestamp: Date;
 }
 
 export class MainSshAgentService {
   SIGN_TIMEOUT = 60_000;
   REQUEST_POLL_INTERVAL = 50;
+  AUDIT_LOG_FILE = "ssh-agent-audit.log";
 
   private requestResponses: AgentResponse[] = [];
   private request_id = 0;
+  // maps the request ids of the agent to the ids of the requests sent to the renderer
+  private pendingRequests = new Map<number, number>();
   private agentState: sshagent.SshAgentState;
 
   constructor(
@@ -32,6 +38,26 @@ export class MainSshAgentService {
     ipcMain.handle("sshagent.isloaded", async (event: any) => {
       return this.agentState != null;
     });
+
+    ipcMain.handle(
+      "sshagent.generatekey",
+      async (
+        event: any,
+        { algorithm, comment }: { algorithm: sshagent.KeyAlgorithm; comment?: string },
+      ) => {
+        return await sshagent.generateKeypair(algorithm, comment);
+      },
+    );
+
+    ipcMain.handle(
+      "sshagent.importkey",
+      async (
+        event: any,
+        { encodedKey, passphrase }: { encodedKey: Uint8Array; passphrase?: string },
+      ) => {
+        return await sshagent.importKey(Buffer.from(encodedKey), passphrase);
+      },
+    );
   }
 
   init() {
@@ -45,13 +71,23 @@ export class MainSshAgentService {
 
         this.request_id += 1;
         const id_for_this_request = this.request_id;
+        this.pendingRequests.set(sshUiRequest.requestId, id_for_this_request);
         this.messagingService.send("sshagent.signrequest", {
           cipherId: sshUiRequest.cipherId,
+          keyName: sshUiRequest.keyName,
           isListRequest: sshUiRequest.isList,
+          isUnlockRequest: sshUiRequest.isUnlock,
           requestId: id_for_this_request,
           processName: sshUiRequest.processName,
+          processDescription: sshUiRequest.processDescription,
           isAgentForwarding: sshUiRequest.isForwarding,
           namespace: sshUiRequest.namespace,
+          hashAlgorithm: sshUiRequest.hashAlgorithm,
+          digestPreview: sshUiRequest.digestPreview,
+          userName: sshUiRequest.userName,
+          isArbitraryData: sshUiRequest.isArbitraryData,
+          mustConfirm: sshUiRequest.mustConfirm,
+          hostNames: sshUiRequest.hostNames,
         });
 
         const result = await firstValueFrom(
@@ -67,9 +103,10 @@ export class MainSshAgentService {
             ),
           ),
         );
+        this.pendingRequests.delete(sshUiRequest.requestId);
 
         if (!result) {
-          return false;
+          return { accepted: false };
         }
 
         const response = this.requestResponses.find(
@@ -80,11 +117,20 @@ export class MainSshAgentService {
           (response) => response.requestId != id_for_this_request,
         );
 
-        return response.accepted;
-      })
+        return { accepted: response.accepted, rememberForMinutes: response.rememberForMinutes };
+      }, this.cancelRequest.bind(this))
       .then((agentState: sshagent.SshAgentState) => {
         this.agentState = agentState;
         this.logService.info("SSH agent started");
+        sshagent.setPromptTimeout(agentState, this.SIGN_TIMEOUT / 1000);
+        try {
+          sshagent.enableAuditLog(
+            agentState,
+            path.join(app.getPath("userData"), this.AUDIT_LOG_FILE),
+          );
+        } catch (e) {
+          this.logService.error("Could not open SSH agent audit log: ", e);
+        }
       })
       .catch((e) => {
         this.logService.error("SSH agent encountered an error: ", e);
@@ -92,19 +138,95 @@ export class MainSshAgentService {
 
     ipcMain.handle(
       "sshagent.setkeys",
-      async (event: any, keys: { name: string; privateKey: string; cipherId: string }[]) => {
+      async (
+        event: any,
+        keys: { name: string; privateKey: string; cipherId: string; askOncePerUnlock: boolean }[],
+      ) => {
         if (this.agentState != null && (await sshagent.isRunning(this.agentState))) {
-          sshagent.setKeys(this.agentState, keys);
+          sshagent.setKeys(
+            this.agentState,
+            keys.map(({ askOncePerUnlock, ...key }) => ({
+              ...key,
+              approvalPolicy: askOncePerUnlock
+                ? sshagent.ApprovalPolicy.AskOncePerUnlock
+                : undefined,
+            })),
+          );
         }
       },
     );
     ipcMain.handle(
       "sshagent.signrequestresponse",
-      async (event: any, { requestId, accepted }: { requestId: number; accepted: boolean }) => {
-        this.requestResponses.push({ requestId, accepted, timestamp: new Date() });
+      async (
+        event: any,
+        {
+          requestId,
+          accepted,
+          rememberForMinutes,
+        }: { requestId: number; accepted: boolean; rememberForMinutes?: number },
+      ) => {
+        this.requestResponses.push({
+          requestId,
+          accepted,
+          rememberForMinutes,
+          timestamp: new Date(),
+        });
+      },
+    );
+
+    ipcMain.handle("sshagent.sethidekeyswhenforwarded", async (event: any, hide: boolean) => {
+      if (this.agentState != null) {
+        sshagent.setHideKeysWhenForwarded(this.agentState, hide);
+      }
+    });
+
+    ipcMain.handle("sshagent.setupstreamagent", async (event: any, path: string | null) => {
+      if (this.agentState != null) {
+        sshagent.setUpstreamAgent(this.agentState, path);
+      }
+    });
+
+    ipcMain.handle(
+      "sshagent.setpublickeyexport",
+      async (event: any, publicKeyExport: sshagent.PublicKeyExport | null) => {
+        if (this.agentState != null) {
+          sshagent.setPublicKeyExport(this.agentState, publicKeyExport);
+        }
       },
     );
 
+    ipcMain.handle("sshagent.setpublickeycache", async (event: any, accountId: string | null) => {
+      if (this.agentState == null) {
+        return;
+      }
+      if (accountId == null) {
+        sshagent.setPublicKeyCache(this.agentState, null);
+        return;
+      }
+      const integrityKey = await sshagent.getPublicKeyCacheKey(accountId);
+      sshagent.setPublicKeyCache(this.agentState, { accountId, integrityKey });
+    });
+
+    ipcMain.handle(
+      "sshagent.settrustedexecutables",
+      async (event: any, trustedExecutables: sshagent.TrustedExecutable[]) => {
+        if (this.agentState != null) {
+          sshagent.setTrustedExecutables(this.agentState, trustedExecutables);
+        }
+      },
+    );
+
+    ipcMain.handle("sshagent.hashexecutable", async (event: any, path: string) =>
+      sshagent.hashExecutable(path),
+    );
+
+    ipcMain.handle("sshagent.installsystemdunits", async (event: any) => {
+      if (process.platform !== "linux") {
+        return null;
+      }
+      return sshagent.installSystemdUnits(app.getPath("exe"));
+    });
+
     ipcMain.handle("sshagent.lock", async (event: any) => {
       if (this.agentState != null && (await sshagent.isRunning(this.agentState))) {
         sshagent.lock(this.agentState);
@@ -116,5 +238,38 @@ export class MainSshAgentService {
         sshagent.clearKeys(this.agentState);
       }
     });
+
+    ipcMain.handle("sshagent.auditentries", async (event: any, limit: number) => {
+      if (this.agentState == null) {
+        return [];
+      }
+      return sshagent.getAuditEntries(this.agentState, limit);
+    });
+
+    ipcMain.handle("sshagent.status", async (event: any) => {
+      if (this.agentState == null) {
+        return null;
+      }
+      return sshagent.getStatus(this.agentState);
+    });
+
+    ipcMain.handle("sshagent.loadedkeys", async (event: any) => {
+      if (this.agentState == null) {
+        return [];
+      }
+      return sshagent.getLoadedKeys(this.agentState);
+    });
+  }
+
+  // The agent gave up on the request, e.g. because the client disconnected or the agent was locked
+  private cancelRequest(err: Error, agentRequestId: number) {
+    const requestId = this.pendingRequests.get(agentRequestId);
+    if (requestId == null) {
+      return;
+    }
+
+    this.pendingRequests.delete(agentRequestId);
+    this.requestResponses.push({ requestId, accepted: false, timestamp: new Date() });
+    this.messagingService.send("sshagent.signrequestcancelled", { requestId });
   }
 }
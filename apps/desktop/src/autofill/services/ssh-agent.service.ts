@@ -30,7 +30,8 @@ import { CommandDefinition, MessageListener } from "@bitwarden/common/platform/m
 import { UserId } from "@bitwarden/common/types/guid";
 import { CipherService } from "@bitwarden/common/vault/abstractions/cipher.service";
 import { CipherType } from "@bitwarden/common/vault/enums";
-import { DialogService, ToastService } from "@bitwarden/components";
+import { CipherView } from "@bitwarden/common/vault/models/view/cipher.view";
+import { DialogRef, DialogService, ToastService } from "@bitwarden/components";
 
 import { ApproveSshRequestComponent } from "../../platform/components/approve-ssh-request";
 import { DesktopSettingsService } from "../../platform/services/desktop-settings.service";
@@ -44,7 +45,8 @@ export class SshAgentService implements OnDestroy {
   SSH_VAULT_UNLOCK_REQUEST_TIMEOUT = 60_000;
   SSH_REQUEST_UNLOCK_POLLING_INTERVAL = 100;
 
-  private authorizedSshKeys: Record<string, Date> = {};
+  // approval dialogs that are currently shown, by request id
+  private openDialogs = new Map<number, DialogRef<unknown>>();
 
   private isFeatureFlagEnabled = false;
 
@@ -78,6 +80,14 @@ export class SshAgentService implements OnDestroy {
   }
 
   private async initListeners() {
+    // The agent gave up on the request, e.g. because the client disconnected or it timed out
+    this.messageListener
+      .messages$(new CommandDefinition("sshagent.signrequestcancelled"))
+      .pipe(takeUntil(this.destroy$))
+      .subscribe((message) => {
+        this.openDialogs.get(message.requestId as number)?.close();
+      });
+
     this.messageListener
       .messages$(new CommandDefinition("sshagent.signrequest"))
       .pipe(
@@ -143,26 +153,41 @@ export class SshAgentService implements OnDestroy {
         concatMap(async ([message, ciphers]) => {
           const cipherId = message.cipherId as string;
           const isListRequest = message.isListRequest as boolean;
+          const isUnlockRequest = message.isUnlockRequest as boolean;
           const requestId = message.requestId as number;
-          let application = message.processName as string;
+          let application = (message.processDescription ?? message.processName) as string;
           const namespace = message.namespace as string;
+          const hashAlgorithm = message.hashAlgorithm as string;
+          const digestPreview = message.digestPreview as string;
+          const userName = message.userName as string;
+          const isArbitraryData = message.isArbitraryData as boolean;
+          const mustConfirm = message.mustConfirm as boolean;
+          const hostNames = message.hostNames as string[];
           const isAgentForwarding = message.isAgentForwarding as boolean;
           if (application == "") {
             application = this.i18nService.t("unknownApplication");
           }
 
-          if (isListRequest) {
-            const sshCiphers = ciphers.filter(
-              (cipher) => cipher.type === CipherType.SshKey && !cipher.isDeleted,
-            );
-            const keys = sshCiphers.map((cipher) => {
-              return {
-                name: cipher.name,
-                privateKey: cipher.sshKey.privateKey,
-                cipherId: cipher.id,
-              };
+          // `ssh-add -X` undoes a lock that was set on purpose, so it is always confirmed, even
+          // when the vault was already unlocked
+          if (isUnlockRequest) {
+            ipc.platform.focusWindow();
+            const dialogRef = this.dialogService.openSimpleDialogRef({
+              title: { key: "sshAgentUnlockTitle" },
+              content: { key: "sshAgentUnlockMessage", placeholders: [application] },
+              acceptButtonText: { key: "unlock" },
+              cancelButtonText: { key: "deny" },
+              type: "warning",
             });
-            await ipc.platform.sshAgent.setKeys(keys);
+
+            this.openDialogs.set(requestId, dialogRef);
+            const approved = await firstValueFrom(dialogRef.closed);
+            this.openDialogs.delete(requestId);
+            return ipc.platform.sshAgent.signRequestResponse(requestId, approved === true);
+          }
+
+          if (isListRequest) {
+            await ipc.platform.sshAgent.setKeys(await this.agentKeys(ciphers));
             await ipc.platform.sshAgent.signRequestResponse(requestId, true);
             return;
           }
@@ -173,20 +198,32 @@ export class SshAgentService implements OnDestroy {
               .catch((e) => this.logService.error("Failed to respond to SSH request", e));
           }
 
-          if (await this.needsAuthorization(cipherId, isAgentForwarding)) {
+          if (await this.needsAuthorization(isAgentForwarding, isArbitraryData, mustConfirm)) {
             ipc.platform.focusWindow();
+            // keys added with ssh-add are not backed by a cipher
             const cipher = ciphers.find((cipher) => cipher.id == cipherId);
             const dialogRef = ApproveSshRequestComponent.open(
               this.dialogService,
-              cipher.name,
+              cipher?.name ?? (message.keyName as string),
               application,
               isAgentForwarding,
               namespace,
+              hashAlgorithm,
+              digestPreview,
+              userName,
+              isArbitraryData,
+              hostNames,
             );
 
-            if (await firstValueFrom(dialogRef.closed)) {
-              await this.rememberAuthorization(cipherId);
-              return ipc.platform.sshAgent.signRequestResponse(requestId, true);
+            this.openDialogs.set(requestId, dialogRef);
+            const result = await firstValueFrom(dialogRef.closed);
+            this.openDialogs.delete(requestId);
+            if (result != null) {
+              return ipc.platform.sshAgent.signRequestResponse(
+                requestId,
+                true,
+                result.rememberForMinutes,
+              );
             } else {
               return ipc.platform.sshAgent.signRequestResponse(requestId, false);
             }
@@ -204,7 +241,6 @@ export class SshAgentService implements OnDestroy {
           return;
         }
 
-        this.authorizedSshKeys = {};
         this.logService.info("Active account changed, clearing SSH keys");
         ipc.platform.sshAgent
           .clearKeys()
@@ -226,7 +262,6 @@ export class SshAgentService implements OnDestroy {
         }
 
         this.logService.info("Active account observable completed, clearing SSH keys");
-        this.authorizedSshKeys = {};
         ipc.platform.sshAgent
           .clearKeys()
           .catch((e) => this.logService.error("Failed to clear SSH keys", e));
@@ -262,17 +297,7 @@ export class SshAgentService implements OnDestroy {
             return;
           }
 
-          const sshCiphers = ciphers.filter(
-            (cipher) => cipher.type === CipherType.SshKey && !cipher.isDeleted,
-          );
-          const keys = sshCiphers.map((cipher) => {
-            return {
-              name: cipher.name,
-              privateKey: cipher.sshKey.privateKey,
-              cipherId: cipher.id,
-            };
-          });
-          await ipc.platform.sshAgent.setKeys(keys);
+          await ipc.platform.sshAgent.setKeys(await this.agentKeys(ciphers));
         }),
         takeUntil(this.destroy$),
       )
@@ -284,16 +309,42 @@ export class SshAgentService implements OnDestroy {
     this.destroy$.complete();
   }
 
-  private async rememberAuthorization(cipherId: string): Promise<void> {
-    this.authorizedSshKeys[cipherId] = new Date();
+  /**
+   * The SSH keys of the vault, as loaded into the agent. Under `RememberUntilLock` the agent
+   * remembers the first approval of each key until the vault is locked.
+   */
+  private async agentKeys(ciphers: CipherView[]) {
+    const promptType = await firstValueFrom(this.desktopSettingsService.sshAgentPromptBehavior$);
+    return ciphers
+      .filter((cipher) => cipher.type === CipherType.SshKey && !cipher.isDeleted)
+      .map((cipher) => ({
+        name: cipher.name,
+        privateKey: cipher.sshKey.privateKey,
+        cipherId: cipher.id,
+        askOncePerUnlock: promptType === SshAgentPromptType.RememberUntilLock,
+      }));
   }
 
-  private async needsAuthorization(cipherId: string, isForward: boolean): Promise<boolean> {
+  private async needsAuthorization(
+    isForward: boolean,
+    isArbitraryData: boolean,
+    mustConfirm: boolean,
+  ): Promise<boolean> {
     // Agent forwarding ALWAYS needs authorization because it is a remote machine
     if (isForward) {
       return true;
     }
 
+    // Signing data that is neither a login nor a message is always confirmed
+    if (isArbitraryData) {
+      return true;
+    }
+
+    // The key's own approval policy, `ssh-add -c` or its forwarding policy asks for this prompt
+    if (mustConfirm) {
+      return true;
+    }
+
     const promptType = await firstValueFrom(this.desktopSettingsService.sshAgentPromptBehavior$);
     switch (promptType) {
       case SshAgentPromptType.Never:
@@ -301,7 +352,8 @@ export class SshAgentService implements OnDestroy {
       case SshAgentPromptType.Always:
         return true;
       case SshAgentPromptType.RememberUntilLock:
-        return !(cipherId in this.authorizedSshKeys);
+        // the agent only asks for keys that were not approved since the vault was unlocked
+        return true;
     }
   }
 }
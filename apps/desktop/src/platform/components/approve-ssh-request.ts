@@ -12,6 +12,7 @@ import {
   FormFieldModule,
   IconButtonModule,
   DialogService,
+  SelectModule,
 } from "@bitwarden/components";
 
 export interface ApproveSshRequestParams {
@@ -19,6 +20,17 @@ export interface ApproveSshRequestParams {
   applicationName: string;
   isAgentForwarding: boolean;
   action: string;
+  hashAlgorithm?: string;
+  digestPreview?: string;
+  userName?: string;
+  hostNames?: string[];
+  canRemember: boolean;
+}
+
+/** Returned when the request is approved. */
+export interface ApproveSshRequestResult {
+  /** Approve identical requests from the same application for this long without a prompt. */
+  rememberForMinutes?: number;
 }
 
 @Component({
@@ -33,14 +45,24 @@ export interface ApproveSshRequestParams {
     ReactiveFormsModule,
     AsyncActionsModule,
     FormFieldModule,
+    SelectModule,
   ],
 })
 export class ApproveSshRequestComponent {
-  approveSshRequestForm = this.formBuilder.group({});
+  protected rememberOptions = [
+    { label: "doNotRemember", value: 0 },
+    { label: "fiveMinutes", value: 5 },
+    { label: "fifteenMinutes", value: 15 },
+    { label: "oneHour", value: 60 },
+  ];
+
+  approveSshRequestForm = this.formBuilder.group({
+    rememberForMinutes: [0],
+  });
 
   constructor(
     @Inject(DIALOG_DATA) protected params: ApproveSshRequestParams,
-    private dialogRef: DialogRef<boolean>,
+    private dialogRef: DialogRef<ApproveSshRequestResult>,
     private formBuilder: FormBuilder,
   ) {}
 
@@ -50,25 +72,45 @@ export class ApproveSshRequestComponent {
     applicationName: string,
     isAgentForwarding: boolean,
     namespace: string,
+    hashAlgorithm?: string,
+    digestPreview?: string,
+    userName?: string,
+    isArbitraryData?: boolean,
+    hostNames?: string[],
   ) {
     let actioni18nKey = "sshActionLogin";
     if (namespace === "git") {
       actioni18nKey = "sshActionGitSign";
     } else if (namespace != null && namespace != "") {
       actioni18nKey = "sshActionSign";
+    } else if (isArbitraryData) {
+      actioni18nKey = "sshActionSignData";
+    } else if (userName != null && userName != "") {
+      actioni18nKey = "sshActionLoginAs";
     }
 
-    return dialogService.open<boolean, ApproveSshRequestParams>(ApproveSshRequestComponent, {
-      data: {
-        cipherName,
-        applicationName,
-        isAgentForwarding,
-        action: actioni18nKey,
+    return dialogService.open<ApproveSshRequestResult, ApproveSshRequestParams>(
+      ApproveSshRequestComponent,
+      {
+        data: {
+          cipherName,
+          applicationName,
+          isAgentForwarding,
+          action: actioni18nKey,
+          hashAlgorithm,
+          digestPreview,
+          userName,
+          hostNames,
+          // arbitrary data is confirmed every time
+          canRemember: !isArbitraryData,
+        },
       },
-    });
+    );
   }
 
   submit = async () => {
-    this.dialogRef.close(true);
+    const { rememberForMinutes } = this.approveSshRequestForm.getRawValue();
+    // 0 stands for not remembering the approval
+    this.dialogRef.close({ rememberForMinutes: rememberForMinutes || undefined });
   };
 }
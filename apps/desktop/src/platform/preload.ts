@@ -3,6 +3,7 @@ import { ipcRenderer } from "electron";
 import { DeviceType } from "@bitwarden/common/enums";
 import { EncString } from "@bitwarden/common/key-management/crypto/models/enc-string";
 import { ThemeType, LogLevelType } from "@bitwarden/common/platform/enums";
+import type { sshagent } from "@bitwarden/desktop-napi";
 
 import {
   EncryptedMessageResponse,
@@ -52,11 +53,35 @@ const sshAgent = {
   init: async () => {
     await ipcRenderer.invoke("sshagent.init");
   },
-  setKeys: (keys: { name: string; privateKey: string; cipherId: string }[]): Promise<void> =>
+  setKeys: (
+    keys: { name: string; privateKey: string; cipherId: string; askOncePerUnlock: boolean }[],
+  ): Promise<void> =>
     ipcRenderer.invoke("sshagent.setkeys", keys),
-  signRequestResponse: async (requestId: number, accepted: boolean) => {
-    await ipcRenderer.invoke("sshagent.signrequestresponse", { requestId, accepted });
+  signRequestResponse: async (
+    requestId: number,
+    accepted: boolean,
+    rememberForMinutes?: number,
+  ) => {
+    await ipcRenderer.invoke("sshagent.signrequestresponse", {
+      requestId,
+      accepted,
+      rememberForMinutes,
+    });
   },
+  setHideKeysWhenForwarded: (hide: boolean): Promise<void> =>
+    ipcRenderer.invoke("sshagent.sethidekeyswhenforwarded", hide),
+  setUpstreamAgent: (path: string | null): Promise<void> =>
+    ipcRenderer.invoke("sshagent.setupstreamagent", path),
+  setPublicKeyExport: (publicKeyExport: sshagent.PublicKeyExport | null): Promise<void> =>
+    ipcRenderer.invoke("sshagent.setpublickeyexport", publicKeyExport),
+  setPublicKeyCache: (accountId: string | null): Promise<void> =>
+    ipcRenderer.invoke("sshagent.setpublickeycache", accountId),
+  setTrustedExecutables: (trustedExecutables: sshagent.TrustedExecutable[]): Promise<void> =>
+    ipcRenderer.invoke("sshagent.settrustedexecutables", trustedExecutables),
+  hashExecutable: (path: string): Promise<string> =>
+    ipcRenderer.invoke("sshagent.hashexecutable", path),
+  installSystemdUnits: (): Promise<string | null> =>
+    ipcRenderer.invoke("sshagent.installsystemdunits"),
   lock: async () => {
     return await ipcRenderer.invoke("sshagent.lock");
   },
@@ -66,6 +91,21 @@ const sshAgent = {
   isLoaded(): Promise<boolean> {
     return ipcRenderer.invoke("sshagent.isloaded");
   },
+  getAuditEntries(limit: number): Promise<sshagent.SshAuditEntry[]> {
+    return ipcRenderer.invoke("sshagent.auditentries", limit);
+  },
+  getStatus(): Promise<sshagent.SshAgentStatus | null> {
+    return ipcRenderer.invoke("sshagent.status");
+  },
+  getLoadedKeys(): Promise<sshagent.LoadedSshKey[]> {
+    return ipcRenderer.invoke("sshagent.loadedkeys");
+  },
+  generateKey(algorithm: sshagent.KeyAlgorithm, comment?: string): Promise<sshagent.SshKey> {
+    return ipcRenderer.invoke("sshagent.generatekey", { algorithm, comment });
+  },
+  importKey(encodedKey: Uint8Array, passphrase?: string): Promise<sshagent.SshKeyImportResult> {
+    return ipcRenderer.invoke("sshagent.importkey", { encodedKey, passphrase });
+  },
 };
 
 const powermonitor = {